@@ -0,0 +1,413 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+use crate::chunk::*;
+use crate::krand::*;
+use crate::kmath::*;
+use crate::settings::*;
+use crate::world_gen::*;
+use crate::column_cache::*;
+use crate::tile_cache::*;
+use crate::strata::*;
+use crate::climate::*;
+
+/*
+caves dug into whatever generator is underneath, after its blocks are made.
+perlin worms: a worm starts somewhere in each WORM_CELL cell and steers along a noise curve, every step is a capsule.
+ravines are the same thing in 2d, a wandering slit deepest in the middle and pinched shut at the ends.
+like the decorations a chunk works out every worm that could reach it from the cells hash and the ground height
+so chunks dont need each other. a cells worms get kept in a TileCache since a chunk looks at about 50 cells
+and its neighbours want most of the same ones.
+cave floors get moss or glowy fungus in patches. nothing gets dug right under the sea so it doesnt drain into the caves
+*/
+
+const WORM_CELL: i32 = 48;
+const WORM_STEPS: usize = 60;
+const WORM_STEP: f32 = 2.0;
+const WORM_MAX_R: f32 = 3.5;
+const WORM_REACH: i32 = (WORM_STEPS as f32 * WORM_STEP + WORM_MAX_R) as i32 + 1;
+const WORM_DEEPEST: f32 = -96.0;
+
+const RAVINE_CELL: i32 = 160;
+const RAVINE_STEPS: usize = 40;
+const RAVINE_STEP: f32 = 3.0;
+const RAVINE_MAX_W: f32 = 4.0;
+const RAVINE_REACH: i32 = (RAVINE_STEPS as f32 * RAVINE_STEP + RAVINE_MAX_W) as i32 + 1;
+
+// solid left between the caves and the bottom of the sea
+const SEABED: i32 = 4;
+
+// a bit more than the loaded area needs
+const MAX_WORM_CELLS: usize = 2048;
+const MAX_RAVINE_CELLS: usize = 256;
+
+// worm piece, a capsule with the radius going from ra to rb
+#[derive(Clone, Copy, Debug)]
+struct Tunnel {
+    a: Vec3,
+    b: Vec3,
+    ra: f32,
+    rb: f32,
+}
+
+// ravine piece, everything within w of the line and above the floor
+#[derive(Clone, Copy, Debug)]
+struct Slit {
+    a: Vec2,
+    b: Vec2,
+    wa: f32,
+    wb: f32,
+    floor_a: f32,
+    floor_b: f32,
+}
+
+#[derive(Default)]
+struct Cuts {
+    tunnels: Vec<Tunnel>,
+    slits: Vec<Slit>,
+}
+
+impl Cuts {
+    fn is_empty(&self) -> bool {
+        self.tunnels.is_empty() && self.slits.is_empty()
+    }
+
+    fn carved(&self, x: i32, y: i32, z: i32) -> bool {
+        let p = Vec3::new(x as f32, y as f32, z as f32);
+        for t in self.tunnels.iter() {
+            let ab = t.b - t.a;
+            let u = ((p - t.a).dot(ab) / ab.dot(ab)).clamp(0.0, 1.0);
+            let r = lerp(t.ra, t.rb, u);
+            if (p - (t.a + ab * u)).square_distance() < r*r {
+                return true;
+            }
+        }
+        let q = Vec2::new(x as f32, z as f32);
+        for s in self.slits.iter() {
+            let ab = s.b - s.a;
+            let u = ((q - s.a).dot(ab) / ab.dot(ab)).clamp(0.0, 1.0);
+            if (y as f32) < lerp(s.floor_a, s.floor_b, u) {
+                continue;
+            }
+            if (q - (s.a + ab * u)).magnitude() < lerp(s.wa, s.wb, u) {
+                return true;
+            }
+        }
+        false
+    }
+
+    // just the pieces whose bounding box touches the box
+    fn within(&self, x0: i32, y0: i32, z0: i32, x1: i32, y1: i32, z1: i32) -> Cuts {
+        let (x0, y0, z0, x1, y1, z1) = (x0 as f32, y0 as f32, z0 as f32, x1 as f32, y1 as f32, z1 as f32);
+        let tunnels = self.tunnels.iter().filter(|t| {
+            let r = t.ra.max(t.rb);
+            t.a.x.min(t.b.x) - r <= x1 && t.a.x.max(t.b.x) + r >= x0 &&
+            t.a.y.min(t.b.y) - r <= y1 && t.a.y.max(t.b.y) + r >= y0 &&
+            t.a.z.min(t.b.z) - r <= z1 && t.a.z.max(t.b.z) + r >= z0
+        }).copied().collect();
+        let slits = self.slits.iter().filter(|s| {
+            let w = s.wa.max(s.wb);
+            s.a.x.min(s.b.x) - w <= x1 && s.a.x.max(s.b.x) + w >= x0 &&
+            s.a.y.min(s.b.y) - w <= z1 && s.a.y.max(s.b.y) + w >= z0 &&
+            s.floor_a.min(s.floor_b) <= y1
+        }).copied().collect();
+        Cuts { tunnels, slits }
+    }
+
+    // nothing gets dug below this
+    fn lowest(&self) -> f32 {
+        let t = self.tunnels.iter().map(|t| t.a.y.min(t.b.y) - t.ra.max(t.rb));
+        let s = self.slits.iter().map(|s| s.floor_a.min(s.floor_b));
+        t.chain(s).fold(f32::INFINITY, f32::min)
+    }
+}
+
+#[derive(Clone)]
+pub struct Caved<G: LevelGenerator> {
+    inner: G,
+    seed: u32,
+    // average worms per cell
+    worms: f32,
+    // chance of a ravine per cell
+    ravines: f32,
+    // shared by every clone handed to the workers
+    worm_cells: Arc<TileCache<Vec<Tunnel>>>,
+    ravine_cells: Arc<TileCache<Vec<Slit>>>,
+}
+
+impl<G: LevelGenerator> Caved<G> {
+    pub fn new(inner: G, seed: u32) -> Caved<G> {
+        Caved {
+            inner,
+            seed,
+            worms: 1.0,
+            ravines: 0.3,
+            worm_cells: Arc::new(TileCache::new(MAX_WORM_CELLS)),
+            ravine_cells: Arc::new(TileCache::new(MAX_RAVINE_CELLS)),
+        }
+    }
+
+    fn cell_worms(&self, cx: i32, cz: i32) -> Vec<Tunnel> {
+        let mut tunnels = Vec::new();
+        let n = (self.worms + khash_2float(cx as u32, cz as u32, self.seed ^ 0x3C6EF372)) as u32;
+        for w in 0..n {
+            self.worm(cx, cz, w, &mut tunnels);
+        }
+        tunnels
+    }
+
+    fn worm(&self, cx: i32, cz: i32, w: u32, tunnels: &mut Vec<Tunnel>) {
+        let seed = khash(self.seed ^ (cx as u32).wrapping_mul(0x8DA6B343) ^ (cz as u32).wrapping_mul(0xD8163841) ^ w.wrapping_mul(0xCB1AB31F));
+        let sx = (cx * WORM_CELL) as f32 + khash_2float(0, 0, seed) * WORM_CELL as f32;
+        let sz = (cz * WORM_CELL) as f32 + khash_2float(1, 0, seed) * WORM_CELL as f32;
+        let top = (self.inner.height(sx, sz) - 8.0).max(WORM_DEEPEST);
+        let mut p = Vec3::new(sx, lerp(WORM_DEEPEST, top, khash_2float(2, 0, seed)), sz);
+        let yaw0 = khash_2float(3, 0, seed) * 2.0 * PI;
+        let mut r = 0.0;
+        for i in 0..WORM_STEPS {
+            let t = i as f32 * 0.1;
+            let yaw = yaw0 + 6.0 * (fgrad2_isotropic(t, 0.5, seed) - 0.5);
+            let pitch = 1.2 * (fgrad2_isotropic(t, 0.5, seed.wrapping_add(0x1B873593)) - 0.5);
+            // pinched at the ends so they dont stop in a flat wall
+            let ends = ((i.min(WORM_STEPS - 1 - i)) as f32 / 4.0).min(1.0);
+            let r_next = (1.5 + 2.0 * fgrad2_isotropic(t, 0.5, seed.wrapping_add(0x68E31DA4))) * ends;
+            let dir = Vec3::new(pitch.cos() * yaw.cos(), pitch.sin(), pitch.cos() * yaw.sin());
+            let next = p + dir * WORM_STEP;
+            tunnels.push(Tunnel { a: p, b: next, ra: r, rb: r_next.min(WORM_MAX_R) });
+            p = next;
+            r = r_next.min(WORM_MAX_R);
+        }
+    }
+
+    fn ravine(&self, cx: i32, cz: i32) -> Vec<Slit> {
+        let mut slits = Vec::new();
+        if khash_2float(cx as u32, cz as u32, self.seed ^ 0x7F4A7C15) >= self.ravines {
+            return slits;
+        }
+        let seed = khash(self.seed ^ (cx as u32).wrapping_mul(0x27D4EB2F) ^ (cz as u32).wrapping_mul(0x165667B1));
+        let sx = (cx * RAVINE_CELL) as f32 + khash_2float(0, 1, seed) * RAVINE_CELL as f32;
+        let sz = (cz * RAVINE_CELL) as f32 + khash_2float(1, 1, seed) * RAVINE_CELL as f32;
+        let top = self.inner.height(sx, sz);
+        let depth = 20.0 + 30.0 * khash_2float(2, 1, seed);
+        let width = 2.0 + (RAVINE_MAX_W - 2.0) * khash_2float(3, 1, seed);
+        let angle0 = khash_2float(4, 1, seed) * 2.0 * PI;
+        let mut p = Vec2::new(sx, sz);
+        let (mut w, mut floor) = (0.0, top);
+        for i in 1..=RAVINE_STEPS {
+            let t = i as f32 / RAVINE_STEPS as f32;
+            let angle = angle0 + 2.0 * (fgrad2_isotropic(t * 4.0, 0.5, seed) - 0.5);
+            let env = (PI * t).sin().max(0.0);
+            let next = p + Vec2::new(angle.cos(), angle.sin()) * RAVINE_STEP;
+            let (w_next, floor_next) = (width * env.sqrt(), top - depth * env);
+            slits.push(Slit { a: p, b: next, wa: w, wb: w_next, floor_a: floor, floor_b: floor_next });
+            p = next;
+            w = w_next;
+            floor = floor_next;
+        }
+        slits
+    }
+
+    // every worm and ravine that could reach x0..=x1 z0..=z1
+    fn cuts(&self, x0: i32, z0: i32, x1: i32, z1: i32) -> Cuts {
+        let mut cuts = Cuts::default();
+        for cz in (z0 - WORM_REACH).div_euclid(WORM_CELL)..=(z1 + WORM_REACH).div_euclid(WORM_CELL) {
+            for cx in (x0 - WORM_REACH).div_euclid(WORM_CELL)..=(x1 + WORM_REACH).div_euclid(WORM_CELL) {
+                self.worm_cells.with(cx, cz, || self.cell_worms(cx, cz), |t| cuts.tunnels.extend_from_slice(t));
+            }
+        }
+        for cz in (z0 - RAVINE_REACH).div_euclid(RAVINE_CELL)..=(z1 + RAVINE_REACH).div_euclid(RAVINE_CELL) {
+            for cx in (x0 - RAVINE_REACH).div_euclid(RAVINE_CELL)..=(x1 + RAVINE_REACH).div_euclid(RAVINE_CELL) {
+                self.ravine_cells.with(cx, cz, || self.ravine(cx, cz), |r| cuts.slits.extend_from_slice(r));
+            }
+        }
+        cuts.within(x0, i32::MIN / 2, z0, x1, i32::MAX / 2, z1)
+    }
+
+    // under the sea the seabed stays put
+    fn dug(&self, cuts: &Cuts, x: i32, y: i32, z: i32, height: f32) -> bool {
+        if height < SEA_LEVEL_F32 + 2.0 && y > height as i32 - SEABED {
+            return false;
+        }
+        cuts.carved(x, y, z)
+    }
+
+    fn carve(&self, ox: i32, oy: i32, oz: i32, blocks: &mut [Block], heights: impl Fn(usize, usize) -> f32) {
+        let (x0, y0, z0) = (ox*S as i32, oy*S as i32, oz*S as i32);
+        let s = S as i32;
+        // one over the top so the floors can see whats above them
+        let cuts = self.cuts(x0, z0, x0 + s - 1, z0 + s - 1).within(x0, y0, z0, x0 + s - 1, y0 + s, z0 + s - 1);
+        if cuts.is_empty() {
+            return;
+        }
+        for k in 0..S {
+            let z = z0 + k as i32;
+            for i in 0..S {
+                let x = x0 + i as i32;
+                let h = heights(i, k);
+                for j in 0..S {
+                    let idx = k*S + j*S*S + i;
+                    let y = y0 + j as i32;
+                    if blocks[idx] == Block::Air || blocks[idx] == Block::Water {
+                        continue;
+                    }
+                    if self.dug(&cuts, x, y, z, h) {
+                        blocks[idx] = Block::Air;
+                    } else if y < h as i32 - 1 && self.dug(&cuts, x, y + 1, z, h) {
+                        let patch = worley3(x as f32 / 10.0, y as f32 / 10.0, z as f32 / 10.0, self.seed.wrapping_add(0x2545F491));
+                        if patch.edge() > 0.1 {
+                            match patch.id % 3 {
+                                0 => blocks[idx] = Block::BlueFungus,
+                                1 => blocks[idx] = Block::Moss,
+                                _ => {},
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<G: LevelGenerator> LevelGenerator for Caved<G> {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        self.inner.height(x, z)
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        let mut blocks = self.inner.generate_blocks(ox, oy, oz);
+        let (x0, z0) = (ox*S as i32, oz*S as i32);
+        self.carve(ox, oy, oz, &mut blocks, |i, k| self.inner.height((x0 + i as i32) as f32, (z0 + k as i32) as f32));
+        blocks
+    }
+
+    fn height_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        self.inner.height_gradient(x, z)
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        self.inner.surface_block(x, y, z, height)
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        self.inner.column_blocks(x, z, oy, height)
+    }
+
+    fn density(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.inner.density(x, y, z)
+    }
+
+    // min goes down to the bottom of any cave under the column, so the chunks around caves dont count as solid stone
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        let mut surface = self.inner.column_surface(ox, oz)?;
+        let (x0, z0) = (ox*S as i32, oz*S as i32);
+        let lowest = self.cuts(x0 - 1, z0 - 1, x0 + S as i32, z0 + S as i32).lowest();
+        surface.min = surface.min.min(lowest.floor());
+        Some(surface)
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        let mut blocks = self.inner.generate_blocks_with(ox, oy, oz, surface);
+        self.carve(ox, oy, oz, &mut blocks, |i, k| surface.height[k*S + i]);
+        blocks
+    }
+
+    // digging only ever makes air, so anything but stone still holds
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        self.inner.uniform_block(oy, surface)
+    }
+
+    fn geology(&self) -> Geology {
+        self.inner.geology()
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Option<Climate> {
+        self.inner.climate(x, z)
+    }
+
+    fn params(&self) -> Vec<GenParam> {
+        let mut params = self.inner.params();
+        params.push(GenParam::new("worms", self.worms, 0.0, 4.0));
+        params.push(GenParam::new("ravines", self.ravines, 0.0, 1.0));
+        params
+    }
+
+    // the worms follow the inner height too, so any change means digging them out again
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "worms" => self.worms = value,
+            "ravines" => self.ravines = value,
+            _ => self.inner.set_param(name, value),
+        }
+        self.worm_cells = Arc::new(TileCache::new(MAX_WORM_CELLS));
+        self.ravine_cells = Arc::new(TileCache::new(MAX_RAVINE_CELLS));
+    }
+}
+
+#[test]
+fn test_caves_cross_chunks() {
+    use crate::world_gen2::WorldGen;
+    let gen = Caved::new(WorldGen::new(7), 7);
+    let s = S as i32;
+    // what gets dug cant depend on which chunk asked
+    let big = gen.cuts(-2*s, -2*s, 2*s - 1, 2*s - 1);
+    let cache = ColumnCache::new();
+    let (mut dug, mut floors) = (0, 0);
+    for oz in -2..2 {
+        for ox in -2..2 {
+            let small = gen.cuts(ox*s, oz*s, ox*s + s - 1, oz*s + s - 1);
+            let big = big.within(ox*s, -6*s, oz*s, ox*s + s - 1, 2*s, oz*s + s - 1);
+            for oy in -5..2 {
+                let blocks = gen.generate_blocks(ox, oy, oz);
+                let plain = gen.inner.generate_blocks(ox, oy, oz);
+                let (cached, uniform) = cached_blocks(&gen, &cache, 0, ox, oy, oz);
+                match uniform {
+                    // no caves snuck into a chunk the column said was solid
+                    Some(b) => assert!(blocks.iter().all(|x| *x == b)),
+                    None => assert_eq!(cached, chunk_blocks(&gen, ox, oy, oz)),
+                }
+                for idx in 0..S*S*S {
+                    let (i, j, k) = ((idx % S) as i32, (idx / (S*S)) as i32, ((idx / S) % S) as i32);
+                    let (x, y, z) = (ox*s + i, oy*s + j, oz*s + k);
+                    assert_eq!(big.carved(x, y, z), small.carved(x, y, z));
+                    if blocks[idx] == Block::Air && plain[idx] != Block::Air {
+                        dug += 1;
+                    }
+                    if blocks[idx] == Block::Moss || blocks[idx] == Block::BlueFungus {
+                        floors += 1;
+                    }
+                }
+            }
+        }
+    }
+    assert!(dug > 0 && floors > 0, "dug {} floors {}", dug, floors);
+}
+
+#[test]
+fn test_ravine() {
+    use crate::world_gen2::WorldGen;
+    let mut gen = Caved::new(WorldGen::new(3), 3);
+    gen.set_param("worms", 0.0);
+    gen.set_param("ravines", 1.0);
+    let cuts = gen.cuts(0, 0, RAVINE_CELL - 1, RAVINE_CELL - 1);
+    assert!(cuts.tunnels.is_empty());
+    let mid = cuts.slits.iter().max_by(|a, b| a.wb.partial_cmp(&b.wb).unwrap()).unwrap();
+    let (x, z) = (mid.b.x.round() as i32, mid.b.y.round() as i32);
+    // open from the floor all the way up, solid just under it
+    assert!(cuts.carved(x, mid.floor_b.ceil() as i32 + 1, z));
+    assert!(cuts.carved(x, mid.floor_b as i32 + 30, z));
+    assert!(!cuts.carved(x, mid.floor_b.floor() as i32 - 2, z));
+}
+
+#[test]
+fn test_cave_params() {
+    use crate::world_gen2::WorldGen;
+    let mut gen = Caved::new(WorldGen::new(7), 7);
+    // a second look comes out of the cache the same
+    let first = gen.cuts(0, 0, 63, 63);
+    let again = gen.cuts(0, 0, 63, 63);
+    assert!(!first.tunnels.is_empty());
+    assert_eq!(format!("{:?}", first.tunnels), format!("{:?}", again.tunnels));
+    // and the sliders dont get stuck with the old worms
+    gen.set_param("worms", 0.0);
+    gen.set_param("ravines", 0.0);
+    assert!(gen.cuts(0, 0, 63, 63).is_empty());
+}
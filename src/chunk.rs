@@ -5,6 +5,7 @@ use bytemuck::*;
 use crate::chunk_manager::*;
 use crate::world_gen::*;
 use crate::world_gen2::*;
+use crate::column_cache::*;
 use crate::settings::*;
 
 
@@ -13,6 +14,8 @@ use crate::settings::*;
 pub struct ChunkData {
     pub blocks: Vec<Block>,
     pub cc: ChunkCoordinates,
+    // all one block with nothing to see, dont bother meshing
+    pub uniform: Option<Block>,
 }
 
 pub struct Chunk {
@@ -97,6 +100,19 @@ pub enum Block {
     Moss,
     BlueFungus,
     YellowFungus,
+    Wood,
+    Leaves,
+    Granite,
+    Slate,
+    Limestone,
+    Basalt,
+    Coal,
+    Iron,
+    Gold,
+    Diamond,
+    Ice,
+    Sandstone,
+    RedRock,
     Wat,
 }
 
@@ -108,6 +124,40 @@ impl Block {
             _ => true,
         }
     }
+
+    pub fn from_name(name: &str) -> Option<Block> {
+        match name {
+            "Air" => Some(Block::Air),
+            "Dirt" => Some(Block::Dirt),
+            "Grass" => Some(Block::Grass),
+            "Stone" => Some(Block::Stone),
+            "Snow" => Some(Block::Snow),
+            "Water" => Some(Block::Water),
+            "Sand" => Some(Block::Sand),
+            "Lava" => Some(Block::Lava),
+            "Hellstone" => Some(Block::Hellstone),
+            "DeadGrass" => Some(Block::DeadGrass),
+            "MoonRock" => Some(Block::MoonRock),
+            "Moss" => Some(Block::Moss),
+            "BlueFungus" => Some(Block::BlueFungus),
+            "YellowFungus" => Some(Block::YellowFungus),
+            "Wood" => Some(Block::Wood),
+            "Leaves" => Some(Block::Leaves),
+            "Granite" => Some(Block::Granite),
+            "Slate" => Some(Block::Slate),
+            "Limestone" => Some(Block::Limestone),
+            "Basalt" => Some(Block::Basalt),
+            "Coal" => Some(Block::Coal),
+            "Iron" => Some(Block::Iron),
+            "Gold" => Some(Block::Gold),
+            "Diamond" => Some(Block::Diamond),
+            "Ice" => Some(Block::Ice),
+            "Sandstone" => Some(Block::Sandstone),
+            "RedRock" => Some(Block::RedRock),
+            "Wat" => Some(Block::Wat),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -171,12 +221,23 @@ const CUBE_VERTS: [f32; 72] = [
 ];
 
 impl ChunkData {
-    pub fn new(cc: ChunkCoordinates, level_gen: &WorldGen) -> ChunkData {
+    pub fn new<G: LevelGenerator>(cc: ChunkCoordinates, level_gen: &G) -> ChunkData {
         ChunkData {
-            blocks: level_gen.generate_blocks(cc.x, cc.y, cc.z),
+            blocks: chunk_blocks(level_gen, cc.x, cc.y, cc.z),
             cc,
+            uniform: None,
         }
-    }    
+    }
+
+    // same as new but shares the 2d work with the other chunks in the column
+    pub fn new_cached<G: LevelGenerator>(cc: ChunkCoordinates, level_gen: &G, cache: &ColumnCache, epoch: u32) -> ChunkData {
+        let (blocks, uniform) = cached_blocks(level_gen, cache, epoch, cc.x, cc.y, cc.z);
+        ChunkData {
+            blocks,
+            cc,
+            uniform,
+        }
+    }
 
     pub fn faces_rle(&self) -> Vec<BlockRLE> {
         let mut ret = vec![BlockRLE::new(); 6*S*S];
@@ -295,6 +356,19 @@ impl ChunkData {
                                 Block::BlueFungus => {[0.0, 0.7, 1.0, 1.0]},
                                 Block::YellowFungus => {[0.5, 1.0, 0.1, 1.0]},
                                 Block::Snow => {[1.0, 1.0, 1.0, 1.0]},
+                                Block::Wood => {[0.4, 0.25, 0.1, 1.0]},
+                                Block::Leaves => {[0.1, 0.5, 0.1, 1.0]},
+                                Block::Granite => {[0.7, 0.55, 0.5, 1.0]},
+                                Block::Slate => {[0.35, 0.38, 0.42, 1.0]},
+                                Block::Limestone => {[0.85, 0.82, 0.7, 1.0]},
+                                Block::Basalt => {[0.2, 0.2, 0.22, 1.0]},
+                                Block::Coal => {[0.1, 0.1, 0.1, 1.0]},
+                                Block::Iron => {[0.75, 0.6, 0.5, 1.0]},
+                                Block::Gold => {[1.0, 0.8, 0.1, 1.0]},
+                                Block::Diamond => {[0.5, 1.0, 1.0, 1.0]},
+                                Block::Ice => {[0.7, 0.85, 0.95, 1.0]},
+                                Block::Sandstone => {[0.85, 0.72, 0.5, 1.0]},
+                                Block::RedRock => {[0.68, 0.33, 0.2, 1.0]},
                                 Block::Wat => {[1.0, 0.0, 1.0, 1.0]},
                 
                             };
@@ -390,6 +464,19 @@ impl ChunkData {
                 Block::BlueFungus => {[0.0, 0.7, 1.0, 1.0]},
                 Block::YellowFungus => {[0.5, 1.0, 0.1, 1.0]},
                 Block::Snow => {[1.0, 1.0, 1.0, 1.0]},
+                Block::Wood => {[0.4, 0.25, 0.1, 1.0]},
+                Block::Leaves => {[0.1, 0.5, 0.1, 1.0]},
+                Block::Granite => {[0.7, 0.55, 0.5, 1.0]},
+                Block::Slate => {[0.35, 0.38, 0.42, 1.0]},
+                Block::Limestone => {[0.85, 0.82, 0.7, 1.0]},
+                Block::Basalt => {[0.2, 0.2, 0.22, 1.0]},
+                Block::Coal => {[0.1, 0.1, 0.1, 1.0]},
+                Block::Iron => {[0.75, 0.6, 0.5, 1.0]},
+                Block::Gold => {[1.0, 0.8, 0.1, 1.0]},
+                Block::Diamond => {[0.5, 1.0, 1.0, 1.0]},
+                Block::Ice => {[0.7, 0.85, 0.95, 1.0]},
+                Block::Sandstone => {[0.85, 0.72, 0.5, 1.0]},
+                Block::RedRock => {[0.68, 0.33, 0.2, 1.0]},
                 Block::Wat => {[1.0, 0.0, 1.0, 1.0]},
             };
             
@@ -1251,6 +1338,19 @@ impl ChunkData {
                                 Block::BlueFungus => {[0.0, 0.7, 1.0, 1.0]},
                                 Block::YellowFungus => {[0.5, 1.0, 0.1, 1.0]},
                                 Block::Snow => {[1.0, 1.0, 1.0, 1.0]},
+                                Block::Wood => {[0.4, 0.25, 0.1, 1.0]},
+                                Block::Leaves => {[0.1, 0.5, 0.1, 1.0]},
+                                Block::Granite => {[0.7, 0.55, 0.5, 1.0]},
+                                Block::Slate => {[0.35, 0.38, 0.42, 1.0]},
+                                Block::Limestone => {[0.85, 0.82, 0.7, 1.0]},
+                                Block::Basalt => {[0.2, 0.2, 0.22, 1.0]},
+                                Block::Coal => {[0.1, 0.1, 0.1, 1.0]},
+                                Block::Iron => {[0.75, 0.6, 0.5, 1.0]},
+                                Block::Gold => {[1.0, 0.8, 0.1, 1.0]},
+                                Block::Diamond => {[0.5, 1.0, 1.0, 1.0]},
+                                Block::Ice => {[0.7, 0.85, 0.95, 1.0]},
+                                Block::Sandstone => {[0.85, 0.72, 0.5, 1.0]},
+                                Block::RedRock => {[0.68, 0.33, 0.2, 1.0]},
                                 Block::Wat => {[1.0, 0.0, 1.0, 1.0]},
                 
                             };
@@ -5,13 +5,15 @@ use glam::Mat4;
 use crate::chunk::*;
 use crate::kmath::*;
 use crate::priority_queue::*;
-// use crate::world_gen::*;
-use crate::world_gen2::*;
+use crate::world_gen::*;
+use crate::column_cache::*;
 use crate::settings::*;
 use crate::camera::*;
 use crossbeam::*;
 use crossbeam_channel::*;
 use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 /*
 Responsibilities:
@@ -66,36 +68,51 @@ impl ChunkCoordinates {
     }
 }
 
-pub struct ChunkManager {
+pub struct ChunkManager<G: LevelGenerator + Clone> {
     pub chunk_map: HashMap<ChunkCoordinates, Chunk>,
     //chunks_to_generate: PriorityQueue<f32, ChunkCoordinates>,
 
-    job_sender: Sender<ChunkCoordinates>,
-    chunk_receiver: Receiver<(ChunkData, (Vec<f32>, Vec<u32>), (Vec<f32>, Vec<u32>))>,    // might be doing unnecessary copying
+    // jobs carry the generator and the epoch they were posted in, bumping the epoch cancels everything in flight
+    job_sender: Sender<(ChunkCoordinates, G, u32)>,
+    chunk_receiver: Receiver<(ChunkData, (Vec<f32>, Vec<u32>), (Vec<f32>, Vec<u32>), u32)>,    // might be doing unnecessary copying
     loading: HashSet<ChunkCoordinates>,
-    gen: WorldGen,
+    // loaded but made by an old generator, get regenerated in place
+    stale: HashSet<ChunkCoordinates>,
+    gen: G,
+    epoch: u32,
+    shared_epoch: Arc<AtomicU32>,
+    // per column heights etc shared by the workers, dropped when the column leaves the treadmill
+    column_cache: Arc<ColumnCache>,
 }
 
-impl ChunkManager {
-    pub fn new(gl: &glow::Context, gen: WorldGen) -> ChunkManager {
+impl<G: LevelGenerator + Clone> ChunkManager<G> {
+    pub fn new(gl: &glow::Context, gen: G) -> ChunkManager<G> {
         let mut chunk_map = HashMap::new();
 
-        let (job_sender, job_receiver) = unbounded();
+        let (job_sender, job_receiver) = unbounded::<(ChunkCoordinates, G, u32)>();
         let (chunk_sender, chunk_receiver) = unbounded();
+        let shared_epoch = Arc::new(AtomicU32::new(0));
+        let column_cache = Arc::new(ColumnCache::new());
 
         for i in 0..N_WORKERS {
             let job_receiver =  job_receiver.clone();
             let chunk_sender = chunk_sender.clone();
-            // let gen = gen.clone();
+            let shared_epoch = shared_epoch.clone();
+            let column_cache = column_cache.clone();
             std::thread::spawn(move || {
-                let thread_gen = gen.clone();
 
                 loop {
-                    let job = job_receiver.recv().unwrap();
-                    let chunk_data = ChunkData::new(job, &thread_gen);
-                    let opaque_stuff = chunk_data.opaque_buffers_opt();
-                    let transparent_stuff = chunk_data.transparent_buffers_opt();
-                    chunk_sender.send((chunk_data, opaque_stuff, transparent_stuff)).unwrap();
+                    let (job, job_gen, job_epoch) = job_receiver.recv().unwrap();
+                    if job_epoch != shared_epoch.load(Ordering::Relaxed) {
+                        continue;
+                    }
+                    let chunk_data = ChunkData::new_cached(job, &job_gen, &column_cache, job_epoch);
+                    let (opaque_stuff, transparent_stuff) = if chunk_data.uniform.is_some() {
+                        ((Vec::new(), Vec::new()), (Vec::new(), Vec::new()))
+                    } else {
+                        (chunk_data.opaque_buffers_opt(), chunk_data.transparent_buffers_opt())
+                    };
+                    chunk_sender.send((chunk_data, opaque_stuff, transparent_stuff, job_epoch)).unwrap();
                 }
             });
         }
@@ -105,8 +122,38 @@ impl ChunkManager {
             job_sender,
             chunk_receiver,
             loading: HashSet::new(),
+            stale: HashSet::new(),
             gen,
+            epoch: 0,
+            shared_epoch,
+            column_cache,
+        }
+    }
+
+    // throw the whole world away and start streaming it in again with a new generator
+    pub fn rebuild(&mut self, gl: &glow::Context, gen: G) {
+        self.epoch += 1;
+        self.shared_epoch.store(self.epoch, Ordering::Relaxed);
+
+        for chunk in self.chunk_map.values_mut() {
+            chunk.destroy(gl);
         }
+        self.chunk_map.clear();
+        self.loading.clear();
+        self.stale.clear();
+        self.column_cache.clear();
+        self.gen = gen;
+    }
+
+    // swap the generator but keep drawing the old chunks until their replacements come in, nearest first
+    pub fn regenerate(&mut self, gen: G) {
+        self.epoch += 1;
+        self.shared_epoch.store(self.epoch, Ordering::Relaxed);
+
+        self.loading.clear();
+        self.stale = self.chunk_map.keys().copied().collect();
+        self.column_cache.clear();
+        self.gen = gen;
     }
 
     pub fn draw(&self, gl: &glow::Context, cam: &Camera) {
@@ -159,11 +206,16 @@ impl ChunkManager {
 
             if !keep {
                 chunk.destroy(gl);
+                self.stale.remove(cc);
             }
 
             keep
         });
 
+        self.column_cache.retain(|x, z| {
+            (x - in_chunk.x).abs() <= CHUNK_RADIUS && (z - in_chunk.z).abs() <= CHUNK_RADIUS
+        });
+
         let mut new_jobs = Vec::new();
 
         // post jobs
@@ -176,7 +228,7 @@ impl ChunkManager {
 
                     let cc = ChunkCoordinates {x,y,z};
 
-                    if !self.chunk_map.contains_key(&cc) && !self.loading.contains(&cc) {
+                    if (!self.chunk_map.contains_key(&cc) || self.stale.contains(&cc)) && !self.loading.contains(&cc) {
                         new_jobs.push(cc);
                     }
                 }
@@ -200,7 +252,7 @@ impl ChunkManager {
         while self.loading.len() < watermark {
             if let Some(job) = new_jobs.pop() {
                 self.loading.insert(job);
-                self.job_sender.send(job).unwrap();
+                self.job_sender.send((job, self.gen.clone(), self.epoch)).unwrap();
             } else {
                 break;
             }
@@ -208,7 +260,10 @@ impl ChunkManager {
 
         let mut chunks_this_frame = 0;
         // reap chunks
-        while let Ok((chunk_data, (ov, oe), (tv, te))) = self.chunk_receiver.try_recv() {
+        while let Ok((chunk_data, (ov, oe), (tv, te), epoch)) = self.chunk_receiver.try_recv() {
+            if epoch != self.epoch {
+                continue;
+            }
             let opaque_mesh = new_opaque_mesh(gl, &ov, &oe);
             let transparent_mesh = new_transparent_mesh(gl, &tv, &te);
 
@@ -218,7 +273,10 @@ impl ChunkManager {
                 transparent_mesh,
             };
             self.loading.remove(&new_chunk.data.cc);
-            self.chunk_map.insert(new_chunk.data.cc, new_chunk);
+            self.stale.remove(&new_chunk.data.cc);
+            if let Some(mut old_chunk) = self.chunk_map.insert(new_chunk.data.cc, new_chunk) {
+                old_chunk.destroy(gl);
+            }
             chunks_this_frame += 1;
             if chunks_this_frame > CHUNKS_PER_FRAME {
                 break;
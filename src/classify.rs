@@ -0,0 +1,422 @@
+use std::collections::{HashMap, HashSet};
+use crate::chunk::*;
+use crate::kmath::*;
+use crate::settings::*;
+use crate::world_gen::*;
+use crate::column_cache::*;
+use crate::strata::*;
+use crate::climate::*;
+use crate::priority_queue::*;
+
+/*
+biomes from rules that look around, not just at the column itself. like the readme says,
+"it satisfies X and neighbours N away also satisfy X". a rule is a Pred tree over height, slope and wetness,
+and Near asks a whole predicate at a ring of points around the column, so its rims, interiors and valleys.
+all the samples are at whole block offsets from the column so the answer only depends on where the column is,
+a chunk remembers the points its already asked about because neighbouring columns rings overlap a lot.
+nesting Nears multiplies the samples, keep it to a couple deep.
+a rule that fills (the sulfur pools) floods its hollow up to one flat level instead of just swapping the top block.
+the level comes from the bottom of the hollow, so every column in it agrees whichever chunk its in
+*/
+
+// points on a Near or Relief ring
+const RING: usize = 8;
+// deepest a pool gets filled above the bottom of its hollow, chunks further above the ground than this are still all air
+const POOL_DEPTH: i32 = 4;
+// how far a pool floods out from its bottom looking for where it spills
+const POOL_REACH: i32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Prop {
+    Height,
+    Slope,
+    // the climate moisture if the generator has one, otherwise just low ground is wet
+    Wetness,
+}
+
+#[derive(Clone, Debug)]
+pub enum Pred {
+    // lo <= prop < hi here
+    Is(Prop, f32, f32),
+    // lo <= average ring height - height < hi. positive is a hollow, negative a hump
+    Relief(f32, f32, f32),
+    // at least frac of the ring radius away satisfy it
+    Near(Box<Pred>, f32, f32),
+    And(Vec<Pred>),
+    Or(Vec<Pred>),
+    Not(Box<Pred>),
+}
+
+#[derive(Clone, Debug)]
+pub struct BiomeRule {
+    pub when: Pred,
+    // what the top block turns into
+    pub top: Block,
+    // fill the hollow with top up to where it would spill over, not just the top block
+    pub fill: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Props {
+    height: f32,
+    slope: f32,
+    wetness: f32,
+}
+
+// memoised 2d properties for one chunks worth of asking
+pub struct Sampler<'a, G: LevelGenerator + ?Sized> {
+    gen: &'a G,
+    props: HashMap<(i32, i32), Props>,
+    // fill level by the bottom of the hollow
+    pools: HashMap<(i32, i32), i32>,
+}
+
+impl<'a, G: LevelGenerator + ?Sized> Sampler<'a, G> {
+    pub fn new(gen: &'a G) -> Sampler<'a, G> {
+        Sampler { gen, props: HashMap::new(), pools: HashMap::new() }
+    }
+
+    fn props(&mut self, x: i32, z: i32) -> Props {
+        let gen = self.gen;
+        *self.props.entry((x, z)).or_insert_with(|| {
+            let (height, grad) = gen.height_gradient(x as f32, z as f32);
+            let wetness = match gen.climate(x as f32, z as f32) {
+                Some(c) => c.moisture,
+                None => (1.0 - (height - SEA_LEVEL_F32) / 60.0).clamp(0.0, 1.0),
+            };
+            Props { height, slope: grad.magnitude(), wetness }
+        })
+    }
+
+    fn ring(radius: f32) -> impl Iterator<Item = (i32, i32)> {
+        (0..RING).map(move |n| {
+            let a = n as f32 * 2.0 * std::f32::consts::PI / RING as f32;
+            ((a.cos() * radius).round() as i32, (a.sin() * radius).round() as i32)
+        })
+    }
+
+    pub fn holds(&mut self, pred: &Pred, x: i32, z: i32) -> bool {
+        match pred {
+            Pred::Is(prop, lo, hi) => {
+                let p = self.props(x, z);
+                let v = match prop {
+                    Prop::Height => p.height,
+                    Prop::Slope => p.slope,
+                    Prop::Wetness => p.wetness,
+                };
+                v >= *lo && v < *hi
+            },
+            Pred::Relief(radius, lo, hi) => {
+                let here = self.props(x, z).height;
+                let mean = Self::ring(*radius).map(|(dx, dz)| self.props(x + dx, z + dz).height).sum::<f32>() / RING as f32;
+                mean - here >= *lo && mean - here < *hi
+            },
+            Pred::Near(inner, radius, frac) => {
+                let count = Self::ring(*radius).filter(|(dx, dz)| self.holds(inner, x + dx, z + dz)).count();
+                count as f32 >= frac * RING as f32
+            },
+            Pred::And(preds) => preds.iter().all(|p| self.holds(p, x, z)),
+            Pred::Or(preds) => preds.iter().any(|p| self.holds(p, x, z)),
+            Pred::Not(inner) => !self.holds(inner, x, z),
+        }
+    }
+
+    // downhill to the bottom of the hollow. ties go by position so a flat floor all finds the same corner
+    fn bottom(&mut self, x: i32, z: i32) -> (i32, i32) {
+        let (mut x, mut z) = (x, z);
+        for _ in 0..4*POOL_REACH {
+            let here = (self.props(x, z).height, z, x);
+            let mut best = here;
+            for dz in -1..=1 {
+                for dx in -1..=1 {
+                    let key = (self.props(x + dx, z + dz).height, z + dz, x + dx);
+                    if key < best {
+                        best = key;
+                    }
+                }
+            }
+            if best == here {
+                break;
+            }
+            (x, z) = (best.2, best.1);
+        }
+        (x, z)
+    }
+
+    // highest y a pool at x z fills to. flooded out from the bottom, lowest first, till it gets POOL_REACH away,
+    // the highest ground it had to climb is where it would spill
+    pub fn pool_top(&mut self, x: i32, z: i32) -> i32 {
+        let bottom = self.bottom(x, z);
+        if let Some(top) = self.pools.get(&bottom) {
+            return *top;
+        }
+        let floor = self.props(bottom.0, bottom.1).height;
+        let mut level = floor;
+        let mut pq = PriorityQueue::new();
+        let mut seen = HashSet::new();
+        pq.set(floor, bottom);
+        seen.insert(bottom);
+        while let Some((px, pz)) = pq.remove_min() {
+            level = level.max(self.props(px, pz).height);
+            if (px - bottom.0).abs().max((pz - bottom.1).abs()) >= POOL_REACH {
+                break;
+            }
+            for dz in -1..=1 {
+                for dx in -1..=1 {
+                    let p = (px + dx, pz + dz);
+                    if seen.insert(p) {
+                        pq.set(self.props(p.0, p.1).height, p);
+                    }
+                }
+            }
+        }
+        let top = (level as i32).min(floor as i32 + POOL_DEPTH);
+        self.pools.insert(bottom, top);
+        top
+    }
+
+    // first rule that holds
+    pub fn classify<'r>(&mut self, rules: &'r [BiomeRule], x: i32, z: i32) -> Option<&'r BiomeRule> {
+        rules.iter().find(|r| self.holds(&r.when, x, z))
+    }
+}
+
+pub fn default_biomes() -> Vec<BiomeRule> {
+    let above_sea = Pred::Is(Prop::Height, SEA_LEVEL_F32 + 5.0, f32::INFINITY);
+    let pool = Pred::And(vec![above_sea.clone(), Pred::Relief(6.0, 2.5, f32::INFINITY)]);
+    let boggy = Pred::And(vec![
+        Pred::Is(Prop::Height, SEA_LEVEL_F32 + 1.0, SEA_LEVEL_F32 + 8.0),
+        Pred::Is(Prop::Slope, 0.0, 0.3),
+        Pred::Is(Prop::Wetness, 0.5, f32::INFINITY),
+    ]);
+    vec![
+        // little sulfur pools in pits on the high ground, and the crust around them
+        BiomeRule { when: pool.clone(), top: Block::Water, fill: true },
+        BiomeRule { when: Pred::And(vec![above_sea, Pred::Not(Box::new(pool.clone())), Pred::Near(Box::new(pool), 3.0, 0.1)]), top: Block::YellowFungus, fill: false },
+        // boggy ground thats boggy all the way round, the edges stay normal
+        BiomeRule { when: Pred::And(vec![boggy.clone(), Pred::Near(Box::new(boggy), 12.0, 1.0)]), top: Block::Moss, fill: false },
+        // sheltered valley floors and gullies stay damp
+        BiomeRule {
+            when: Pred::And(vec![
+                Pred::Is(Prop::Height, SEA_LEVEL_F32 + 10.0, f32::INFINITY),
+                Pred::Or(vec![Pred::Relief(24.0, 6.0, f32::INFINITY), Pred::Relief(10.0, 4.0, f32::INFINITY)]),
+            ]),
+            top: Block::Moss,
+            fill: false,
+        },
+    ]
+}
+
+// any generator with its top blocks picked by the biome rules
+#[derive(Clone)]
+pub struct Classified<G: LevelGenerator> {
+    inner: G,
+    rules: Vec<BiomeRule>,
+}
+
+impl<G: LevelGenerator> Classified<G> {
+    pub fn new(inner: G, rules: Vec<BiomeRule>) -> Classified<G> {
+        Classified { inner, rules }
+    }
+
+    // what a column turns into and the highest y it goes up to, thats above the ground only for a pool
+    fn style(&self, sampler: &mut Sampler<G>, x: i32, z: i32, h: i32) -> Option<(Block, i32)> {
+        let rule = sampler.classify(&self.rules, x, z)?;
+        let top = if rule.fill { sampler.pool_top(x, z).max(h) } else { h };
+        Some((rule.top, top))
+    }
+
+    // how far above the ground a column can change
+    fn reach(&self) -> i32 {
+        if self.rules.iter().any(|r| r.fill) { POOL_DEPTH } else { 0 }
+    }
+
+    fn restyle(&self, ox: i32, oy: i32, oz: i32, blocks: &mut [Block], heights: impl Fn(usize, usize) -> f32) {
+        let (x0, y0, z0) = (ox*S as i32, oy*S as i32, oz*S as i32);
+        let y1 = y0 + S as i32 - 1;
+        let reach = self.reach();
+        let mut sampler = Sampler::new(&self.inner);
+        for k in 0..S {
+            for i in 0..S {
+                let h = heights(i, k) as i32;
+                if h + reach < y0 || h > y1 {
+                    continue;
+                }
+                let at = |y: i32| k*S + (y - y0) as usize*S*S + i;
+                if h >= y0 && (blocks[at(h)] == Block::Air || blocks[at(h)] == Block::Water) {
+                    continue;
+                }
+                let Some((block, top)) = self.style(&mut sampler, x0 + i as i32, z0 + k as i32, h) else { continue };
+                for y in h.max(y0)..=top.min(y1) {
+                    if y == h || blocks[at(y)] == Block::Air {
+                        blocks[at(y)] = block;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<G: LevelGenerator> LevelGenerator for Classified<G> {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        self.inner.height(x, z)
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        let mut blocks = self.inner.generate_blocks(ox, oy, oz);
+        let (x0, z0) = (ox*S as i32, oz*S as i32);
+        self.restyle(ox, oy, oz, &mut blocks, |i, k| self.inner.height((x0 + i as i32) as f32, (z0 + k as i32) as f32));
+        blocks
+    }
+
+    fn height_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        self.inner.height_gradient(x, z)
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        let block = self.inner.surface_block(x, y, z, height);
+        if y == height && block != Block::Air && block != Block::Water {
+            return self.style(&mut Sampler::new(&self.inner), x, z, height).map_or(block, |(b, _)| b);
+        }
+        if y > height && y <= height + self.reach() && block == Block::Air {
+            return match self.style(&mut Sampler::new(&self.inner), x, z, height) {
+                Some((b, top)) if y <= top => b,
+                _ => block,
+            };
+        }
+        block
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        let mut column = self.inner.column_blocks(x, z, oy, height);
+        let (y0, y1) = (oy*S as i32, oy*S as i32 + S as i32 - 1);
+        let at = |y: i32| (y - y0) as usize;
+        if height + self.reach() < y0 || height > y1 {
+            return column;
+        }
+        if height >= y0 && (column[at(height)] == Block::Air || column[at(height)] == Block::Water) {
+            return column;
+        }
+        if let Some((block, top)) = self.style(&mut Sampler::new(&self.inner), x, z, height) {
+            for y in height.max(y0)..=top.min(y1) {
+                if y == height || column[at(y)] == Block::Air {
+                    column[at(y)] = block;
+                }
+            }
+        }
+        column
+    }
+
+    fn density(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.inner.density(x, y, z)
+    }
+
+    fn params(&self) -> Vec<GenParam> {
+        self.inner.params()
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        self.inner.set_param(name, value);
+    }
+
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        self.inner.column_surface(ox, oz)
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        let mut blocks = self.inner.generate_blocks_with(ox, oy, oz, surface);
+        self.restyle(ox, oy, oz, &mut blocks, |i, k| surface.height[k*S + i]);
+        blocks
+    }
+
+    // only ever swaps the top block, or fills a pool a little above it
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        match self.inner.uniform_block(oy, surface) {
+            Some(Block::Air) if oy*S as i32 <= surface.max as i32 + self.reach() => None,
+            block => block,
+        }
+    }
+
+    fn geology(&self) -> Geology {
+        self.inner.geology()
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Option<Climate> {
+        self.inner.climate(x, z)
+    }
+}
+
+// high flat ground with a pit at the origin, and a boggy strip x 100..200
+#[cfg(test)]
+struct Bumps;
+
+#[cfg(test)]
+impl LevelGenerator for Bumps {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        if (x*x + z*z).sqrt() < 4.0 {
+            16.0
+        } else if x >= 100.0 && x < 200.0 {
+            3.0
+        } else {
+            20.0
+        }
+    }
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+}
+
+#[test]
+fn test_classifier() {
+    let gen = Classified::new(Bumps, default_biomes());
+    // which rule, pool rim swamp valley
+    let biome = |x, z| {
+        let rules = default_biomes();
+        Sampler::new(&Bumps).classify(&rules, x, z).map(|r| rules.iter().position(|q| std::ptr::eq(q, r)).unwrap())
+    };
+    assert_eq!(biome(0, 0), Some(0));
+    assert_eq!(biome(2, -1), Some(0));
+    assert_eq!(biome(6, 0), Some(1));
+    assert_eq!(biome(30, 30), None);
+    assert_eq!(biome(150, 40), Some(2));
+    // the edge of the bog isnt surrounded by bog
+    assert_eq!(biome(104, 40), None);
+
+    // a column gets the same answer from whichever chunk asks, however much the sampler has seen
+    let mut a = Sampler::new(&Bumps);
+    let mut b = Sampler::new(&Bumps);
+    let rules = default_biomes();
+    for x in -20..20 {
+        a.classify(&rules, x, 3);
+    }
+    for x in (-20..20).rev() {
+        assert_eq!(a.classify(&rules, x, -3).map(|r| r.top), b.classify(&rules, x, -3).map(|r| r.top));
+    }
+    let s = S as i32;
+    let blocks = gen.generate_blocks(0, 1, 0);
+    let left = gen.generate_blocks(-1, 1, 0);
+    // the pit straddles the chunk corner, both sides got their pool and rim
+    let top = |blocks: &Vec<Block>, i: i32, k: i32, h: i32| blocks[(k*s + (h - s)*s*s + i) as usize];
+    assert_eq!(top(&blocks, 0, 0, 16), Block::Water);
+    assert_eq!(top(&left, s - 1, 0, 16), Block::Water);
+    assert_eq!(top(&blocks, 5, 0, 20), Block::YellowFungus);
+    assert_eq!(top(&left, s - 6, 0, 20), Block::YellowFungus);
+    // the pool is filled flat up to the rim on both sides
+    for (chunk, i0) in [(&blocks, 0), (&left, s)] {
+        for z in -3..4 {
+            for x in -3..4 {
+                if x*x + z*z >= 16 || (i0 == 0) != (x >= 0) || z < 0 {
+                    continue;
+                }
+                for y in 16..=20 {
+                    assert_eq!(top(chunk, x + i0, z, y), Block::Water, "pool at {} {} {}", x, y, z);
+                }
+                assert_eq!(top(chunk, x + i0, z, 21), Block::Air);
+            }
+        }
+    }
+    // and the single columns agree
+    assert_eq!(gen.column_blocks(-2, 1, 1, 16)[4], Block::Water);
+    assert_eq!(gen.surface_block(-2, 20, 1, 16), Block::Water);
+    assert_eq!(gen.surface_block(-2, 21, 1, 16), Block::Air);
+}
@@ -0,0 +1,313 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+use crate::chunk::*;
+use crate::krand::*;
+use crate::kmath::*;
+use crate::settings::*;
+use crate::world_gen::*;
+use crate::column_cache::*;
+use crate::tile_cache::*;
+use crate::strata::*;
+
+/*
+temperature and moisture over the terrain.
+temperature starts from a huge latitude-ish noise and drops off with altitude.
+moisture comes off the sea: lots near the coast, less the further inland, and the wind (from the west, always)
+drops it on the first mountains it hits so the far side of a range is dry.
+the ocean distance and rain shadow take a lot of height samples so they get done on a LATTICE grid,
+a tile of it at a time in a TileCache, and blended. altitude is applied per column after that so peaks stay sharp.
+a ClimateMap goes with one generator, its tiles are that generators numbers
+*/
+
+const LATTICE: i32 = 32;
+const TILE: i32 = 8;
+const MAX_TILES: usize = 64;
+// degrees per block above the sea
+const LAPSE: f32 = 0.1;
+const OCEAN_DISTS: [f32; 5] = [24.0, 48.0, 96.0, 192.0, 384.0];
+const UPWIND_STEP: f32 = 32.0;
+const UPWIND_STEPS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Climate {
+    // degrees
+    pub temperature: f32,
+    // 0 desert to 1 swamp
+    pub moisture: f32,
+}
+
+// lattice values for one tile, sea level temperature and moisture
+struct ClimateTile {
+    temperature: Vec<f32>,
+    moisture: Vec<f32>,
+}
+
+#[derive(Clone)]
+pub struct ClimateMap {
+    seed: u32,
+    // added on after the tiles, so changing them is free
+    pub warmth: f32,
+    pub wetness: f32,
+    tiles: Arc<TileCache<ClimateTile>>,
+}
+
+impl ClimateMap {
+    pub fn new(seed: u32) -> ClimateMap {
+        ClimateMap { seed, warmth: 0.0, wetness: 0.0, tiles: Arc::new(TileCache::new(MAX_TILES)) }
+    }
+
+    // the expensive bit, at one lattice point
+    fn sample<G: LevelGenerator + ?Sized>(&self, gen: &G, x: f32, z: f32) -> (f32, f32) {
+        let latitude = fgrad2_isotropic(0.0007 * x, 0.0007 * z, self.seed);
+        let temperature = lerp(32.0, -12.0, latitude);
+
+        let h = gen.height(x, z);
+        let mut ocean = 512.0;
+        if h < SEA_LEVEL_F32 {
+            ocean = 0.0;
+        } else {
+            'search: for d in OCEAN_DISTS {
+                for n in 0..8 {
+                    let a = n as f32 * PI / 4.0;
+                    if gen.height(x + a.cos() * d, z + a.sin() * d) < SEA_LEVEL_F32 {
+                        ocean = d;
+                        break 'search;
+                    }
+                }
+            }
+        }
+
+        // whatever the wind had to get over on the way here
+        let ridge = (1..=UPWIND_STEPS).map(|i| gen.height(x - i as f32 * UPWIND_STEP, z)).fold(SEA_LEVEL_F32, f32::max);
+        let shadow = ((ridge - h.max(SEA_LEVEL_F32)) / 120.0).clamp(0.0, 1.0);
+
+        let noise = fgrad2_isotropic(0.003 * x, 0.003 * z, self.seed.wrapping_add(0x5851F42D)) - 0.5;
+        let moisture = (0.25 + 0.75 * (-ocean / 200.0).exp()) * (1.0 - 0.7 * shadow) + 0.3 * noise;
+        (temperature, moisture)
+    }
+
+    fn make_tile<G: LevelGenerator + ?Sized>(&self, gen: &G, tx: i32, tz: i32) -> ClimateTile {
+        let n = (TILE + 1) as usize;
+        let mut temperature = Vec::with_capacity(n*n);
+        let mut moisture = Vec::with_capacity(n*n);
+        for k in 0..=TILE {
+            for i in 0..=TILE {
+                let (t, m) = self.sample(gen, ((tx*TILE + i) * LATTICE) as f32, ((tz*TILE + k) * LATTICE) as f32);
+                temperature.push(t);
+                moisture.push(m);
+            }
+        }
+        ClimateTile { temperature, moisture }
+    }
+
+    pub fn at<G: LevelGenerator + ?Sized>(&self, gen: &G, x: f32, z: f32) -> Climate {
+        let (gx, gz) = (x / LATTICE as f32, z / LATTICE as f32);
+        let (cx, cz) = (gx.floor() as i32, gz.floor() as i32);
+        let (u, v) = (gx - cx as f32, gz - cz as f32);
+        let (tx, tz) = (cx.div_euclid(TILE), cz.div_euclid(TILE));
+        let (i, k) = (cx.rem_euclid(TILE) as usize, cz.rem_euclid(TILE) as usize);
+        let n = (TILE + 1) as usize;
+        let (t, m) = self.tiles.with(tx, tz, || self.make_tile(gen, tx, tz), |tile| {
+            let blend = |f: &[f32]| lerp(
+                lerp(f[k*n + i], f[k*n + i + 1], u),
+                lerp(f[(k + 1)*n + i], f[(k + 1)*n + i + 1], u),
+                v,
+            );
+            (blend(&tile.temperature), blend(&tile.moisture))
+        });
+        let altitude = (gen.height(x, z) - SEA_LEVEL_F32).max(0.0);
+        Climate {
+            temperature: t - LAPSE * altitude + self.warmth,
+            moisture: (m + self.wetness).clamp(0.0, 1.0),
+        }
+    }
+}
+
+// what the top block at height turns into. only the land above the beaches changes
+pub fn biome_block(climate: Climate, block: Block, height: i32) -> Block {
+    if height <= SEA_LEVEL_I32 + 4 {
+        return block;
+    }
+    match block {
+        Block::Grass | Block::Dirt | Block::Stone if climate.temperature < -2.0 => Block::Snow,
+        Block::Grass if climate.moisture < 0.25 && climate.temperature > 8.0 => Block::DeadGrass,
+        Block::Grass if climate.moisture > 0.75 && height < SEA_LEVEL_I32 + 30 => Block::Moss,
+        b => b,
+    }
+}
+
+// any generator with its top blocks picked by the climate
+#[derive(Clone)]
+pub struct Climatic<G: LevelGenerator> {
+    inner: G,
+    map: ClimateMap,
+}
+
+impl<G: LevelGenerator> Climatic<G> {
+    pub fn new(inner: G, seed: u32) -> Climatic<G> {
+        Climatic { inner, map: ClimateMap::new(seed) }
+    }
+
+    fn restyle(&self, ox: i32, oy: i32, oz: i32, blocks: &mut [Block], heights: impl Fn(usize, usize) -> f32) {
+        let (x0, y0, z0) = (ox*S as i32, oy*S as i32, oz*S as i32);
+        for k in 0..S {
+            for i in 0..S {
+                let h = heights(i, k) as i32;
+                if h < y0 || h >= y0 + S as i32 {
+                    continue;
+                }
+                let idx = k*S + (h - y0) as usize*S*S + i;
+                let c = self.map.at(&self.inner, (x0 + i as i32) as f32, (z0 + k as i32) as f32);
+                blocks[idx] = biome_block(c, blocks[idx], h);
+            }
+        }
+    }
+}
+
+impl<G: LevelGenerator> LevelGenerator for Climatic<G> {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        self.inner.height(x, z)
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        let mut blocks = self.inner.generate_blocks(ox, oy, oz);
+        let (x0, z0) = (ox*S as i32, oz*S as i32);
+        self.restyle(ox, oy, oz, &mut blocks, |i, k| self.inner.height((x0 + i as i32) as f32, (z0 + k as i32) as f32));
+        blocks
+    }
+
+    fn height_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        self.inner.height_gradient(x, z)
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        let block = self.inner.surface_block(x, y, z, height);
+        if y != height {
+            return block;
+        }
+        biome_block(self.map.at(&self.inner, x as f32, z as f32), block, height)
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        let mut column = self.inner.column_blocks(x, z, oy, height);
+        let top = (height - oy*S as i32) as usize;
+        if top < S {
+            column[top] = biome_block(self.map.at(&self.inner, x as f32, z as f32), column[top], height);
+        }
+        column
+    }
+
+    fn density(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.inner.density(x, y, z)
+    }
+
+    fn params(&self) -> Vec<GenParam> {
+        let mut params = self.inner.params();
+        params.push(GenParam::new("warmth", self.map.warmth, -20.0, 20.0));
+        params.push(GenParam::new("wetness", self.map.wetness, -1.0, 1.0));
+        params
+    }
+
+    // the tiles are sampled off the inner height, so an inner change means making them again
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "warmth" => self.map.warmth = value,
+            "wetness" => self.map.wetness = value,
+            _ => {
+                self.inner.set_param(name, value);
+                self.map.tiles = Arc::new(TileCache::new(MAX_TILES));
+            },
+        }
+    }
+
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        self.inner.column_surface(ox, oz)
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        let mut blocks = self.inner.generate_blocks_with(ox, oy, oz, surface);
+        self.restyle(ox, oy, oz, &mut blocks, |i, k| surface.height[k*S + i]);
+        blocks
+    }
+
+    // only ever swaps one surface block for another
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        self.inner.uniform_block(oy, surface)
+    }
+
+    fn geology(&self) -> Geology {
+        self.inner.geology()
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Option<Climate> {
+        Some(self.map.at(&self.inner, x, z))
+    }
+}
+
+// sea to the west, flat land, optionally a big ridge across the wind
+#[cfg(test)]
+struct Coast {
+    ridge: bool,
+}
+
+#[cfg(test)]
+impl LevelGenerator for Coast {
+    fn height(&self, x: f32, _z: f32) -> f32 {
+        if x < 0.0 {
+            -30.0
+        } else if self.ridge && x > 400.0 && x < 500.0 {
+            10.0 + 200.0 * (1.0 - ((x - 450.0) / 50.0).abs())
+        } else {
+            10.0
+        }
+    }
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+}
+
+#[test]
+fn test_climate_fields() {
+    let flat = Coast { ridge: false };
+    let ridge = Coast { ridge: true };
+    let flat_map = ClimateMap::new(9);
+    let ridge_map = ClimateMap::new(9);
+
+    // wetter by the sea
+    assert!(flat_map.at(&flat, 40.0, 100.0).moisture > flat_map.at(&flat, 900.0, 100.0).moisture + 0.2);
+    // dry behind the mountains, nothing changes before them
+    let lee = (ridge_map.at(&ridge, 600.0, 100.0), flat_map.at(&flat, 600.0, 100.0));
+    assert!(lee.0.moisture < lee.1.moisture - 0.1, "{:?}", lee);
+    assert_eq!(ridge_map.at(&ridge, 200.0, 100.0), flat_map.at(&flat, 200.0, 100.0));
+    // cold on top
+    assert!(ridge_map.at(&ridge, 450.0, 100.0).temperature < ridge_map.at(&ridge, 300.0, 100.0).temperature - 15.0);
+}
+
+#[test]
+fn test_biome_blocks() {
+    let c = |temperature, moisture| Climate { temperature, moisture };
+    assert_eq!(biome_block(c(-10.0, 0.5), Block::Stone, 150), Block::Snow);
+    assert_eq!(biome_block(c(25.0, 0.1), Block::Grass, 20), Block::DeadGrass);
+    assert_eq!(biome_block(c(15.0, 0.9), Block::Grass, 10), Block::Moss);
+    assert_eq!(biome_block(c(15.0, 0.5), Block::Grass, 20), Block::Grass);
+    // beaches stay beaches
+    assert_eq!(biome_block(c(-10.0, 0.5), Block::Sand, 2), Block::Sand);
+
+    let gen = Climatic::new(crate::world_gen2::WorldGen::new(4), 4);
+    let cache = ColumnCache::new();
+    for oy in -1..3 {
+        match cached_blocks(&gen, &cache, 0, 2, oy, 5) {
+            (blocks, None) => assert_eq!(blocks, chunk_blocks(&gen, 2, oy, 5)),
+            (blocks, Some(b)) => assert!(blocks.iter().all(|x| *x == b)),
+        }
+    }
+
+    // the climate follows the inner generator after its sliders move
+    let mut gen = gen;
+    let before = gen.climate(300.0, 700.0);
+    gen.set_param("ocean_lo", 0.1);
+    let mut fresh = crate::world_gen2::WorldGen::new(4);
+    fresh.set_param("ocean_lo", 0.1);
+    assert_eq!(gen.climate(300.0, 700.0), Climatic::new(fresh, 4).climate(300.0, 700.0));
+    assert_ne!(gen.climate(300.0, 700.0), before);
+}
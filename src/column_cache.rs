@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use crate::chunk::*;
+use crate::kmath::*;
+use crate::settings::*;
+use crate::world_gen::*;
+
+/*
+the loaded region is ~13 chunks tall and every one of them wants the same 16x16 heights.
+so the 2d stuff for a chunk column gets worked out once, put in here and shared by all the workers.
+entries are tagged with the epoch of the generator that made them, a worker still finishing an old job
+cant sneak an old surface back in after a regenerate
+*/
+
+// 2d properties of one chunk column, indexed k*S + i like a layer of blocks
+#[derive(Clone, Debug)]
+pub struct ColumnSurface {
+    pub height: Vec<f32>,
+    // empty unless the generator asked for gradients
+    pub grad: Vec<Vec2>,
+    // lowest and highest height over the column plus a 1 block border,
+    // the border is so a buried chunk knows its side faces are covered too
+    pub min: f32,
+    pub max: f32,
+    // the wrapped generators own surface, for wrappers that carve into its chunk
+    pub inner: Option<Box<ColumnSurface>>,
+    // what a wrapper put over the ground of each column, empty if it didnt
+    pub cover: Vec<Cover>,
+}
+
+// water or ice on a column up to top, and whether the ground under it gets snow
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cover {
+    pub top: Option<f32>,
+    pub snowy: bool,
+}
+
+impl ColumnSurface {
+    pub fn heights<G: LevelGenerator + ?Sized>(gen: &G, ox: i32, oz: i32) -> ColumnSurface {
+        let off = gen.sample_offset();
+        let mut height = Vec::with_capacity(S*S);
+        for k in 0..S {
+            let z = oz*S as i32 + k as i32;
+            for i in 0..S {
+                let x = ox*S as i32 + i as i32;
+                height.push(gen.height(x as f32 + off, z as f32 + off));
+            }
+        }
+        ColumnSurface::bounded(gen, ox, oz, height, Vec::new())
+    }
+
+    pub fn gradients<G: LevelGenerator + ?Sized>(gen: &G, ox: i32, oz: i32) -> ColumnSurface {
+        let off = gen.sample_offset();
+        let mut height = Vec::with_capacity(S*S);
+        let mut grad = Vec::with_capacity(S*S);
+        for k in 0..S {
+            let z = oz*S as i32 + k as i32;
+            for i in 0..S {
+                let x = ox*S as i32 + i as i32;
+                let (h, g) = gen.height_gradient(x as f32 + off, z as f32 + off);
+                height.push(h);
+                grad.push(g);
+            }
+        }
+        ColumnSurface::bounded(gen, ox, oz, height, grad)
+    }
+
+    // from heights over the column and its 1 block border, an S+2 square starting at x0-1 z0-1,
+    // for generators that get a whole column cheaper than point by point
+    pub fn bordered(all: &[f32]) -> ColumnSurface {
+        let w = S + 2;
+        let height = (0..S*S).map(|idx| all[(idx / S + 1)*w + idx % S + 1]).collect();
+        let min = all.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = all.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        ColumnSurface { height, grad: Vec::new(), min, max, inner: None, cover: Vec::new() }
+    }
+
+    fn bounded<G: LevelGenerator + ?Sized>(gen: &G, ox: i32, oz: i32, height: Vec<f32>, grad: Vec<Vec2>) -> ColumnSurface {
+        let (x0, z0) = (ox*S as i32, oz*S as i32);
+        let mut min = f32::INFINITY;
+        let mut max = f32::NEG_INFINITY;
+        let off = gen.sample_offset();
+        let border = (-1..=S as i32).flat_map(|t| [(t, -1), (t, S as i32), (-1, t), (S as i32, t)]);
+        for h in height.iter().copied().chain(border.map(|(i, k)| gen.height((x0 + i) as f32 + off, (z0 + k) as f32 + off))) {
+            min = min.min(h);
+            max = max.max(h);
+        }
+        ColumnSurface { height, grad, min, max, inner: None, cover: Vec::new() }
+    }
+}
+
+// the standard_block stack: air above the highest surface if its above sea level,
+// stone more than the dirt layers below the lowest one
+pub fn standard_uniform_block(oy: i32, surface: &ColumnSurface) -> Option<Block> {
+    let y_lo = oy*S as i32;
+    let y_hi = y_lo + S as i32 - 1;
+    if y_lo > surface.max as i32 && y_lo > SEA_LEVEL_I32 {
+        Some(Block::Air)
+    } else if y_hi <= surface.min as i32 - 4 {
+        Some(Block::Stone)
+    } else {
+        None
+    }
+}
+
+// None means the generator doesnt do column surfaces, remembered so we dont keep asking
+type Entry = (u32, Option<Arc<ColumnSurface>>);
+
+#[derive(Default)]
+pub struct ColumnCache {
+    columns: Mutex<HashMap<(i32, i32), Entry>>,
+}
+
+impl ColumnCache {
+    pub fn new() -> ColumnCache {
+        ColumnCache::default()
+    }
+
+    // computed outside the lock, if two workers race on a column one of the answers just gets dropped
+    pub fn get<G: LevelGenerator + ?Sized>(&self, gen: &G, epoch: u32, ox: i32, oz: i32) -> Option<Arc<ColumnSurface>> {
+        if let Some((e, surface)) = self.columns.lock().unwrap().get(&(ox, oz)) {
+            if *e == epoch {
+                return surface.clone();
+            }
+        }
+        let surface = gen.column_surface(ox, oz).map(Arc::new);
+        let mut columns = self.columns.lock().unwrap();
+        match columns.get(&(ox, oz)) {
+            Some((e, _)) if *e > epoch => {},
+            _ => { columns.insert((ox, oz), (epoch, surface.clone())); },
+        }
+        surface
+    }
+
+    pub fn clear(&self) {
+        self.columns.lock().unwrap().clear();
+    }
+
+    pub fn retain(&self, mut keep: impl FnMut(i32, i32) -> bool) {
+        self.columns.lock().unwrap().retain(|(x, z), _| keep(*x, *z));
+    }
+}
+
+// generate_blocks then the rock layers and ores
+pub fn chunk_blocks<G: LevelGenerator + ?Sized>(gen: &G, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+    let mut blocks = gen.generate_blocks(ox, oy, oz);
+    let (x0, z0) = (ox*S as i32, oz*S as i32);
+    let off = gen.sample_offset();
+    gen.geology().apply(ox, oy, oz, &mut blocks, |i, k| gen.height((x0 + i as i32) as f32 + off, (z0 + k as i32) as f32 + off));
+    blocks
+}
+
+// what a worker does for a chunk, uses the cached column if the generator has one.
+// the block is Some when the column says the chunk is all that and nothing in it can be seen,
+// those dont bother with the geology either
+pub fn cached_blocks<G: LevelGenerator + ?Sized>(gen: &G, cache: &ColumnCache, epoch: u32, ox: i32, oy: i32, oz: i32) -> (Vec<Block>, Option<Block>) {
+    match cache.get(gen, epoch, ox, oz) {
+        Some(surface) => match gen.uniform_block(oy, &surface) {
+            Some(block) => (vec![block; S*S*S], Some(block)),
+            None => {
+                let mut blocks = gen.generate_blocks_with(ox, oy, oz, &surface);
+                gen.geology().apply(ox, oy, oz, &mut blocks, |i, k| surface.height[k*S + i]);
+                (blocks, None)
+            },
+        },
+        None => (chunk_blocks(gen, ox, oy, oz), None),
+    }
+}
+
+#[test]
+fn test_column_cache() {
+    use crate::world_gen2::WorldGen;
+    let gen = WorldGen::new(69);
+    let cache = ColumnCache::new();
+    for oy in -2..3 {
+        match cached_blocks(&gen, &cache, 0, 3, oy, -5) {
+            (blocks, None) => assert_eq!(blocks, chunk_blocks(&gen, 3, oy, -5)),
+            (blocks, Some(b)) => assert_eq!(blocks, gen.generate_blocks(3, oy, -5), "not all {:?}", b),
+        }
+    }
+    assert_eq!(cache.columns.lock().unwrap().len(), 1);
+
+    // a new epoch recomputes, an old one cant overwrite it
+    let other = WorldGen::new(70);
+    assert_eq!(cached_blocks(&other, &cache, 1, 3, 0, -5).0, chunk_blocks(&other, 3, 0, -5));
+    cache.get(&gen, 0, 3, -5);
+    assert_eq!(cached_blocks(&other, &cache, 1, 3, 0, -5).0, chunk_blocks(&other, 3, 0, -5));
+
+    cache.retain(|x, _| x != 3);
+    assert_eq!(cache.columns.lock().unwrap().len(), 0);
+}
+
+#[test]
+fn test_uniform_chunks() {
+    use crate::world_gen2::WorldGen;
+    let gen = WorldGen::new(69);
+    let cache = ColumnCache::new();
+    let (mut air, mut stone) = (0, 0);
+    for ox in -2..2 {
+        for oy in -8..8 {
+            let (blocks, uniform) = cached_blocks(&gen, &cache, 0, ox, oy, 7);
+            let full = chunk_blocks(&gen, ox, oy, 7);
+            match uniform {
+                Some(Block::Air) => {
+                    air += 1;
+                    assert_eq!(blocks, full);
+                },
+                // the layers and ores dont get put in, nobody can see them
+                Some(Block::Stone) => {
+                    stone += 1;
+                    assert!(full.iter().all(|b| b.is_opaque()));
+                },
+                Some(b) => panic!("unexpected uniform {:?}", b),
+                None => assert_eq!(blocks, full),
+            }
+        }
+    }
+    assert!(air > 0 && stone > 0);
+}
@@ -0,0 +1,435 @@
+use std::sync::Arc;
+use crate::chunk::*;
+use crate::krand::*;
+use crate::kmath::*;
+use crate::settings::*;
+use crate::world_gen::*;
+use crate::column_cache::*;
+use crate::tile_cache::*;
+use crate::strata::*;
+use crate::climate::*;
+
+/*
+the stage after the terrain: trees, boulders, ruins, anything bigger than a block.
+its pull based, a chunk never pushes pieces into its neighbours. instead every chunk goes over the anchor cells
+of every feature that could reach it, works the feature out from the cells hash and the ground height and keeps
+the pieces that land inside itself. so it doesnt matter which worker makes which chunk or in what order,
+a tree on a chunk border comes out whole.
+anchors come from the poisson disk sampler in krand, so theyre never closer than spacing and dont line up on a grid.
+a decorator can spread its anchors out to SPREAD times that where its sparse, like trees where its dry.
+pieces only go into air and the features are always visited in the same order, so overlaps come out the same too
+*/
+
+// offset from the anchor and what goes there
+pub type Piece = (i32, i32, i32, Block);
+
+pub trait Decorator: Send + Sync + 'static {
+    // closest two anchors can be
+    fn spacing(&self) -> i32;
+    // furthest a piece can be from its anchor sideways
+    fn reach(&self) -> i32;
+    // the feature anchored at x z where the ground is y, nothing if it doesnt want to go there.
+    // r is a hash thats different for every anchor
+    fn place(&self, gen: &dyn LevelGenerator, x: i32, y: i32, z: i32, r: u32, out: &mut Vec<Piece>);
+    // 0..1, how closely packed the anchors are around x z. 1 is spacing apart, 0 is SPREAD times that
+    fn crowding(&self, _gen: &dyn LevelGenerator, _x: f32, _z: f32) -> f32 {
+        1.0
+    }
+}
+
+// how much further apart anchors get where a decorator isnt crowded at all
+const SPREAD: f32 = 3.0;
+// which disk candidates got kept, per decorator. plenty for the loaded area at the closest spacings
+const MAX_DISK_CELLS: usize = 8192;
+
+fn roll(roll: u32, salt: u32) -> f32 {
+    khash(roll.wrapping_add(salt.wrapping_mul(0x2545F491))) as f32 / u32::MAX as f32
+}
+
+#[derive(Clone)]
+pub struct Decorated<G: LevelGenerator> {
+    inner: G,
+    seed: u32,
+    decorators: Vec<Arc<dyn Decorator>>,
+    disks: Vec<Arc<TileCache<DiskCell>>>,
+}
+
+impl<G: LevelGenerator> Decorated<G> {
+    pub fn new(inner: G, seed: u32, decorators: Vec<Arc<dyn Decorator>>) -> Decorated<G> {
+        let disks = Self::disks(&decorators);
+        Decorated { inner, seed, decorators, disks }
+    }
+
+    fn disks(decorators: &[Arc<dyn Decorator>]) -> Vec<Arc<TileCache<DiskCell>>> {
+        decorators.iter().map(|_| Arc::new(TileCache::new(MAX_DISK_CELLS))).collect()
+    }
+
+    // every piece of every feature that could land in x0..=x1 z0..=z1, in world coords.
+    // always in the same order whoever asks
+    pub fn pieces(&self, x0: i32, z0: i32, x1: i32, z1: i32) -> Vec<Piece> {
+        let mut pieces = Vec::new();
+        let mut feature = Vec::new();
+        for (d, dec) in self.decorators.iter().enumerate() {
+            let (sp, r) = (dec.spacing(), dec.reach());
+            let seed = khash(self.seed.wrapping_add(d as u32 * 0x3C6EF372));
+            let near = sp as f32;
+            let disk = PoissonDisk::new(seed, near, SPREAD * near, |x, z| {
+                lerp(SPREAD * near, near, dec.crowding(&self.inner, x, z).clamp(0.0, 1.0))
+            }, self.disks[d].clone());
+            let (fx0, fz0, fx1, fz1) = ((x0 - r) as f32, (z0 - r) as f32, (x1 + r + 1) as f32, (z1 + r + 1) as f32);
+            for (px, pz, anchor_roll) in disk.points(fx0, fz0, fx1, fz1) {
+                let (ax, az) = (px.floor() as i32, pz.floor() as i32);
+                let ay = self.inner.height(ax as f32, az as f32) as i32;
+                feature.clear();
+                dec.place(&self.inner, ax, ay, az, anchor_roll, &mut feature);
+                pieces.extend(feature.iter().map(|(x, y, z, b)| (ax + x, ay + y, az + z, *b)));
+            }
+        }
+        pieces
+    }
+
+    fn decorate(&self, ox: i32, oy: i32, oz: i32, blocks: &mut [Block]) {
+        let (x0, y0, z0) = (ox*S as i32, oy*S as i32, oz*S as i32);
+        let s = S as i32;
+        for (x, y, z, block) in self.pieces(x0, z0, x0 + s - 1, z0 + s - 1) {
+            let (i, j, k) = (x - x0, y - y0, z - z0);
+            if i < 0 || j < 0 || k < 0 || i >= s || j >= s || k >= s {
+                continue;
+            }
+            let idx = k as usize*S + j as usize*S*S + i as usize;
+            if blocks[idx] == Block::Air {
+                blocks[idx] = block;
+            }
+        }
+    }
+}
+
+impl<G: LevelGenerator> LevelGenerator for Decorated<G> {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        self.inner.height(x, z)
+    }
+
+    // the inner geology goes in first so it only ever sees the inner generators rock, never a boulder
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        let mut blocks = chunk_blocks(&self.inner, ox, oy, oz);
+        self.decorate(ox, oy, oz, &mut blocks);
+        blocks
+    }
+
+    fn height_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        self.inner.height_gradient(x, z)
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        self.inner.surface_block(x, y, z, height)
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        self.inner.column_blocks(x, z, oy, height)
+    }
+
+    fn density(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.inner.density(x, y, z)
+    }
+
+    fn params(&self) -> Vec<GenParam> {
+        self.inner.params()
+    }
+
+    // crowding looks at the inner generator, so the kept points could all change
+    fn set_param(&mut self, name: &str, value: f32) {
+        self.inner.set_param(name, value);
+        self.disks = Self::disks(&self.decorators);
+    }
+
+    // max goes up to the tallest feature reaching the column so the chunks with treetops in them dont count as air
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        let mut surface = self.inner.column_surface(ox, oz)?;
+        let (x0, z0) = (ox*S as i32, oz*S as i32);
+        for (_, y, _, _) in self.pieces(x0 - 1, z0 - 1, x0 + S as i32, z0 + S as i32) {
+            surface.max = surface.max.max(y as f32);
+        }
+        Some(surface)
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        let mut blocks = self.inner.generate_blocks_with(ox, oy, oz, surface);
+        self.inner.geology().apply(ox, oy, oz, &mut blocks, |i, k| surface.height[k*S + i]);
+        self.decorate(ox, oy, oz, &mut blocks);
+        blocks
+    }
+
+    // pieces only go into air, so anything else the inner one says still holds
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        match self.inner.uniform_block(oy, surface) {
+            Some(Block::Air) if oy*S as i32 <= surface.max as i32 => None,
+            b => b,
+        }
+    }
+
+    // already done under the decorations, nothing left for after
+    fn geology(&self) -> Geology {
+        Geology { strata: Vec::new(), ores: Vec::new(), ..self.inner.geology() }
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Option<Climate> {
+        self.inner.climate(x, z)
+    }
+}
+
+// a trunk and a blob of leaves, on grass thats not too steep
+pub struct Trees {
+    pub spacing: i32,
+    pub density: f32,
+}
+
+impl Decorator for Trees {
+    fn spacing(&self) -> i32 { self.spacing }
+    fn reach(&self) -> i32 { 2 }
+
+    fn place(&self, gen: &dyn LevelGenerator, x: i32, y: i32, z: i32, r: u32, out: &mut Vec<Piece>) {
+        let ground = gen.surface_block(x, y, z, y);
+        if roll(r, 0) > self.density || (ground != Block::Grass && ground != Block::Moss) {
+            return;
+        }
+        let (_, grad) = gen.height_gradient(x as f32, z as f32);
+        if grad.x.abs() > 1.0 || grad.y.abs() > 1.0 {
+            return;
+        }
+        let trunk = 4 + (roll(r, 1) * 3.0) as i32;
+        for dy in 1..=trunk {
+            out.push((0, dy, 0, Block::Wood));
+        }
+        for dy in trunk - 2..=trunk + 1 {
+            let rad: i32 = if dy > trunk - 1 { 1 } else { 2 };
+            for dz in -rad..=rad {
+                for dx in -rad..=rad {
+                    // knock some corners off so they dont all look like boxes
+                    let corner = dx.abs() == rad && dz.abs() == rad;
+                    if corner && roll(r, (dx + 3 + 8*(dz + 3) + 64*dy) as u32) < 0.6 {
+                        continue;
+                    }
+                    out.push((dx, dy, dz, Block::Leaves));
+                }
+            }
+        }
+    }
+
+    // further apart where its dry if theres a climate to go by
+    fn crowding(&self, gen: &dyn LevelGenerator, x: f32, z: f32) -> f32 {
+        gen.climate(x, z).map_or(1.0, |c| (1.5 * c.moisture).min(1.0))
+    }
+}
+
+// lumps of rock sat half in the ground, anywhere above water
+pub struct Boulders {
+    pub spacing: i32,
+    pub density: f32,
+}
+
+impl Decorator for Boulders {
+    fn spacing(&self) -> i32 { self.spacing }
+    fn reach(&self) -> i32 { 3 }
+
+    fn place(&self, _gen: &dyn LevelGenerator, _x: i32, y: i32, _z: i32, r: u32, out: &mut Vec<Piece>) {
+        if roll(r, 0) > self.density || y <= SEA_LEVEL_I32 {
+            return;
+        }
+        let rad = 1.5 + roll(r, 1) * 1.5;
+        let squash = 0.6 + roll(r, 2) * 0.4;
+        for dy in -1..=3 {
+            for dz in -3..=3 {
+                for dx in -3..=3 {
+                    let (fx, fy, fz) = (dx as f32, (dy as f32 - 0.5) / squash, dz as f32);
+                    if fx*fx + fy*fy + fz*fz < rad*rad {
+                        out.push((dx, dy, dz, Block::Stone));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// the crumbled walls of some old hut, only on flat ground
+pub struct Ruins {
+    pub spacing: i32,
+    pub density: f32,
+}
+
+impl Decorator for Ruins {
+    fn spacing(&self) -> i32 { self.spacing }
+    fn reach(&self) -> i32 { 4 }
+
+    fn place(&self, gen: &dyn LevelGenerator, x: i32, y: i32, z: i32, r: u32, out: &mut Vec<Piece>) {
+        if roll(r, 0) > self.density || y <= SEA_LEVEL_I32 + 4 {
+            return;
+        }
+        let flat = [(-4, -4), (4, -4), (-4, 4), (4, 4)].iter()
+            .all(|(dx, dz)| (gen.height((x + dx) as f32, (z + dz) as f32) as i32 - y).abs() <= 2);
+        if !flat {
+            return;
+        }
+        for dz in -4..=4i32 {
+            for dx in -4..=4i32 {
+                if (dx.abs() != 4 && dz.abs() != 4) || (dx == 0 && dz == -4) {
+                    continue;
+                }
+                let wall = (roll(r, (dx + 4 + 9*(dz + 4)) as u32) * 4.0) as i32;
+                // down a couple too so the walls sit on the ground where its a bit lower
+                for dy in -2..=wall {
+                    out.push((dx, dy, dz, Block::Stone));
+                }
+            }
+        }
+    }
+}
+
+pub fn default_decorators() -> Vec<Arc<dyn Decorator>> {
+    vec![
+        Arc::new(Ruins { spacing: 64, density: 0.3 }),
+        Arc::new(Boulders { spacing: 24, density: 0.35 }),
+        Arc::new(Trees { spacing: 7, density: 0.6 }),
+    ]
+}
+
+#[cfg(test)]
+struct Flat;
+
+#[cfg(test)]
+impl LevelGenerator for Flat {
+    fn height(&self, _x: f32, _z: f32) -> f32 {
+        10.0
+    }
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+    // plain stone so the test can build what to expect from standard_block
+    fn geology(&self) -> Geology {
+        Geology { strata: Vec::new(), ores: Vec::new(), ..Geology::standard(0) }
+    }
+}
+
+// flat too but with the standard rock layers and ores under it
+#[cfg(test)]
+struct Layered;
+
+#[cfg(test)]
+impl LevelGenerator for Layered {
+    fn height(&self, _x: f32, _z: f32) -> f32 {
+        40.0
+    }
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+}
+
+#[test]
+fn test_decoration_geology() {
+    // boulders and ruins stay plain stone, the rock under them still gets its layers
+    let gen = Decorated::new(Layered, 5, vec![
+        Arc::new(Ruins { spacing: 12, density: 1.0 }) as Arc<dyn Decorator>,
+        Arc::new(Boulders { spacing: 8, density: 1.0 }),
+    ]);
+    let cache = ColumnCache::new();
+    let s = S as i32;
+    let mut placed = 0;
+    for ox in 0..3 {
+        let pieces = gen.pieces(ox*s, 0, ox*s + s - 1, s - 1);
+        for blocks in [chunk_blocks(&gen, ox, 2, 0), cached_blocks(&gen, &cache, 0, ox, 2, 0).0] {
+            for (x, y, z, b) in pieces.iter().copied() {
+                let (i, j, k) = (x - ox*s, y - 2*s, z);
+                if y > 40 && (0..s).contains(&i) && (0..s).contains(&j) && (0..s).contains(&k) {
+                    assert_eq!(blocks[(k*s + j*s*s + i) as usize], b, "at {} {} {}", x, y, z);
+                    placed += 1;
+                }
+            }
+        }
+        let deep = chunk_blocks(&gen, ox, 1, 0);
+        assert!(deep.iter().any(|b| *b != Block::Stone && b.is_opaque()));
+    }
+    assert!(placed > 0);
+}
+
+#[test]
+fn test_decoration_borders() {
+    let gen = Arc::new(Decorated::new(Flat, 5, default_decorators()));
+    let s = S as i32;
+    let w = 3*s;
+    let idx = |x: i32, y: i32, z: i32| ((z + s)*w*2*s + y*w + x + s) as usize;
+
+    // the whole 3x2x3 area decorated in one go
+    let mut reference = vec![Block::Air; (w*w*2*s) as usize];
+    for z in -s..2*s {
+        for y in 0..2*s {
+            for x in -s..2*s {
+                reference[idx(x, y, z)] = standard_block(y, 10);
+            }
+        }
+    }
+    for (x, y, z, b) in gen.pieces(-s, -s, 2*s - 1, 2*s - 1) {
+        if x >= -s && x < 2*s && z >= -s && z < 2*s && y >= 0 && y < 2*s && reference[idx(x, y, z)] == Block::Air {
+            reference[idx(x, y, z)] = b;
+        }
+    }
+
+    // chunk by chunk, backwards, each on its own thread, some through the column cache
+    let mut jobs = Vec::new();
+    for oz in (-1..2).rev() {
+        for oy in (0..2).rev() {
+            for ox in (-1..2).rev() {
+                let gen = gen.clone();
+                jobs.push(((ox, oy, oz), std::thread::spawn(move || {
+                    let cache = ColumnCache::new();
+                    if ox == 0 { gen.generate_blocks(ox, oy, oz) } else { cached_blocks(&gen, &cache, 0, ox, oy, oz).0 }
+                })));
+            }
+        }
+    }
+    let (mut wood, mut split) = (0, 0);
+    for ((ox, oy, oz), job) in jobs {
+        let blocks = job.join().unwrap();
+        for k in 0..s {
+            for j in 0..s {
+                for i in 0..s {
+                    let (x, y, z) = (ox*s + i, oy*s + j, oz*s + k);
+                    let b = blocks[(k*s + j*s*s + i) as usize];
+                    assert_eq!(b, reference[idx(x, y, z)], "at {} {} {}", x, y, z);
+                    if b == Block::Wood {
+                        wood += 1;
+                        // a trunk right by the edge has leaves over in the next chunk
+                        if i == 0 || k == 0 || i == s - 1 || k == s - 1 {
+                            split += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    assert!(wood > 0 && split > 0);
+}
+
+#[test]
+fn test_decoration_uniform() {
+    // treetops poke into the chunk above the ground so it cant be skipped as air
+    let gen = Decorated::new(Flat, 5, vec![Arc::new(Trees { spacing: 5, density: 1.0 }) as Arc<dyn Decorator>]);
+    let cache = ColumnCache::new();
+    for ox in 0..3 {
+        let (blocks, uniform) = cached_blocks(&gen, &cache, 0, ox, 1, 0);
+        assert_eq!(uniform, None);
+        assert!(blocks.contains(&Block::Leaves));
+        assert_eq!(cached_blocks(&gen, &cache, 0, ox, 2, 0).1, Some(Block::Air));
+        assert_eq!(cached_blocks(&gen, &cache, 0, ox, -1, 0).1, Some(Block::Stone));
+    }
+}
@@ -1,12 +1,20 @@
 use glow::*;
 use crate::chunk_manager::*;
 use crate::world_gen::*;
-use crate::world_gen2::*;
+use crate::gen_graph::*;
 use crate::camera::*;
 use crate::kmath::*;
 use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::SystemTime;
 use crate::kimg::*;
 
+// where the current world comes from, so it can be rebuilt with new params
+enum GenSource {
+    Builtin(usize),
+    Graph(Graph),
+}
+
 pub struct Game {
     show_menu: bool,
     gl: glow::Context,
@@ -21,11 +29,22 @@ pub struct Game {
     pc_program: glow::Program,
     pcn_program: glow::Program,
 
-    chunk_manager: ChunkManager,
+    chunk_manager: ChunkManager<SharedGen>,
     cam: Camera,
 
     fog_intensity: f32,
     fog_colour: [f32; 3],
+
+    gen_source: GenSource,
+    params: Vec<GenParam>,
+    gen_choice: usize,
+    seed: u32,
+    seed_text: String,
+    graph_path: String,
+    graph_error: Option<String>,
+    // the graph file the world currently comes from and when it was last modified, polled for hot reload
+    graph_watch: Option<(String, SystemTime)>,
+    graph_poll: f32,
 }
 
 fn  make_shader(gl: &glow::Context, vert_path: &str, frag_path: &str) -> glow::Program {
@@ -90,7 +109,7 @@ unsafe fn opengl_boilerplate(xres: f32, yres: f32, event_loop: &glutin::event_lo
 }
 
 impl Game {
-    pub fn new(event_loop: &glutin::event_loop::EventLoop<()>) -> Game {
+    pub fn new(event_loop: &glutin::event_loop::EventLoop<()>, gen_name: &str, seed: u32) -> Game {
         let default_xres = 1600.0;
         let default_yres = 900.0;
         let fovx = 0.9;
@@ -101,10 +120,12 @@ impl Game {
         let pc_program = make_shader(&gl, "src/test.vert", "src/test.frag");
         let pcn_program = make_shader(&gl, "src/pcn.vert", "src/pcn.frag");
 
-        let gen = WorldGen::new(69);
-        let chunk_manager = ChunkManager::new(&gl, gen);
+        let gen_choice = generator_index(gen_name).expect("unknown generator");
+        let gen = (GENERATORS[gen_choice].1)(seed);
 
         let cam = Camera::new(fovx, default_xres/default_yres, Vec3::new(0.0, gen.height(0.0, 0.0) + 1.0, 0.0));
+        let params = gen.params();
+        let chunk_manager = ChunkManager::new(&gl, gen);
 
         let mut game = Game {
             show_menu: false,
@@ -120,6 +141,15 @@ impl Game {
             cam,
             fog_intensity: 0.0003,
             fog_colour: [0.0, 0.0, 0.0],
+            gen_source: GenSource::Builtin(gen_choice),
+            params,
+            gen_choice,
+            seed,
+            seed_text: seed.to_string(),
+            graph_path: "graphs/rolling.graph".to_string(),
+            graph_error: None,
+            graph_watch: None,
+            graph_poll: 0.0,
         };
 
         game.lock_focus();
@@ -206,6 +236,9 @@ impl Game {
                 self.gl.disable(DEPTH_TEST);
                 
                 
+                let mut apply_gen = false;
+                let mut load_graph = false;
+                let mut params_changed = false;
                 let (needs_repaint, shapes) = self.egui.run(self.window.window(), |egui_ctx| {
                     egui::SidePanel::left("my_side_panel").show(egui_ctx, |ui| {
                         ui.heading("Fog Intensity");
@@ -216,6 +249,47 @@ impl Game {
                         ui.color_edit_button_rgb(&mut self.fog_colour);
                         ui.end_row();
 
+                        ui.heading("Generator");
+                        egui::ComboBox::from_id_source("generator")
+                            .selected_text(GENERATORS[self.gen_choice].0)
+                            .show_ui(ui, |ui| {
+                                for (i, (name, _)) in GENERATORS.iter().enumerate() {
+                                    ui.selectable_value(&mut self.gen_choice, i, *name);
+                                }
+                            });
+                        ui.horizontal(|ui| {
+                            ui.label("Seed");
+                            ui.text_edit_singleline(&mut self.seed_text);
+                        });
+                        let seed = self.seed_text.trim().parse::<u32>();
+                        if seed.is_err() {
+                            ui.colored_label(egui::Color32::RED, "seed must be a u32");
+                        }
+                        if ui.button("Apply").clicked() {
+                            if let Ok(seed) = seed {
+                                self.seed = seed;
+                                apply_gen = true;
+                            }
+                        }
+                        ui.end_row();
+
+                        ui.heading("Generator Graph");
+                        ui.text_edit_singleline(&mut self.graph_path);
+                        load_graph = ui.button("Load").clicked();
+                        if let Some(err) = &self.graph_error {
+                            ui.colored_label(egui::Color32::RED, err);
+                        }
+                        ui.end_row();
+
+                        if !self.params.is_empty() {
+                            ui.heading("Parameters");
+                            for param in self.params.iter_mut() {
+                                let slider = egui::Slider::new(&mut param.value, param.min..=param.max).text(param.name);
+                                params_changed |= ui.add(slider).changed();
+                            }
+                            ui.end_row();
+                        }
+
                         ui.heading("Hello World!");
                         if ui.button("Quit").clicked() {
                             println!("spaget");
@@ -224,6 +298,33 @@ impl Game {
                 });
         
                 self.egui.paint(&self.window, &self.gl, shapes);
+
+                if apply_gen {
+                    self.gen_source = GenSource::Builtin(self.gen_choice);
+                    self.params.clear();
+                    let gen = self.make_gen();
+                    self.params = gen.params();
+                    self.chunk_manager.rebuild(&self.gl, gen);
+                    self.graph_watch = None;
+                }
+                if load_graph {
+                    match Graph::load(&self.graph_path) {
+                        Ok(graph) => {
+                            self.graph_error = None;
+                            self.gen_source = GenSource::Graph(graph);
+                            self.params.clear();
+                            let gen = self.make_gen();
+                            self.params = gen.params();
+                            self.chunk_manager.rebuild(&self.gl, gen);
+                            self.graph_watch = modified_time(&self.graph_path).map(|t| (self.graph_path.clone(), t));
+                        },
+                        Err(e) => self.graph_error = Some(e.to_string()),
+                    }
+                }
+                if params_changed {
+                    let gen = self.make_gen();
+                    self.chunk_manager.regenerate(gen);
+                }
                 self.gl.enable(DEPTH_TEST);
             }
             
@@ -258,10 +359,51 @@ impl Game {
         }
 
 
+        self.graph_poll -= dt;
+        if self.graph_poll <= 0.0 {
+            self.graph_poll = 0.5;
+            self.hot_reload_graph();
+        }
+
         self.chunk_manager.treadmill(&self.gl, &self.cam);
 
     }
 
+    // fresh generator from the current source with the menus params applied
+    fn make_gen(&self) -> SharedGen {
+        let mut gen = match &self.gen_source {
+            GenSource::Builtin(idx) => (GENERATORS[*idx].1)(self.seed),
+            GenSource::Graph(graph) => Arc::new(GenGraph::new(graph.clone(), self.seed)),
+        };
+        for param in self.params.iter() {
+            gen.set_param(param.name, param.value);
+        }
+        gen
+    }
+
+    // if the graph file changed on disk, swap it in and let the chunks regenerate in place
+    fn hot_reload_graph(&mut self) {
+        let (path, last_modified) = match &self.graph_watch {
+            Some(watch) => watch.clone(),
+            None => return,
+        };
+        let modified = match modified_time(&path) {
+            Some(t) if t != last_modified => t,
+            _ => return,
+        };
+        self.graph_watch = Some((path.clone(), modified));
+
+        match Graph::load(&path) {
+            Ok(graph) => {
+                self.graph_error = None;
+                self.gen_source = GenSource::Graph(graph);
+                let gen = self.make_gen();
+                self.chunk_manager.regenerate(gen);
+            },
+            Err(e) => self.graph_error = Some(e.to_string()),
+        }
+    }
+
     pub fn destroy(&mut self) {
         unsafe {
             self.gl.delete_program(self.pc_program);
@@ -284,3 +426,6 @@ impl Game {
     }
 }
 
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,553 @@
+use crate::chunk::*;
+use crate::krand::*;
+use crate::kmath::*;
+use crate::world_gen::*;
+use crate::column_cache::*;
+use crate::strata::*;
+use crate::climate::*;
+use crate::settings::*;
+
+/*
+adapters that are themselves LevelGenerators, so landscapes can be built by plugging
+existing generators together instead of writing yet another Gen* struct.
+block placement goes through heightmap_blocks so the wrapped generators column_blocks still gets used,
+the mixing ones pick whose blocks once a column
+*/
+
+// anything that gives a number for a 2d position, used for blend masks and warp offsets
+pub trait Field2: Send + Sync + 'static {
+    fn sample(&self, x: f32, z: f32) -> f32;
+
+    // value and gradient, central difference unless the field knows better
+    fn sample_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        let eps = 0.05;
+        let dx = self.sample(x + eps, z) - self.sample(x - eps, z);
+        let dz = self.sample(x, z + eps) - self.sample(x, z - eps);
+        (self.sample(x, z), Vec2::new(dx, dz) / (2.0 * eps))
+    }
+}
+
+impl<F: Fn(f32, f32) -> f32 + Send + Sync + 'static> Field2 for F {
+    fn sample(&self, x: f32, z: f32) -> f32 {
+        self(x, z)
+    }
+}
+
+// fgrad2_isotropic at some frequency, 0..1 ish
+#[derive(Clone, Copy)]
+pub struct NoiseField {
+    pub frequency: f32,
+    pub seed: u32,
+}
+
+impl NoiseField {
+    pub fn new(frequency: f32, seed: u32) -> NoiseField {
+        NoiseField { frequency, seed }
+    }
+}
+
+impl Field2 for NoiseField {
+    fn sample(&self, x: f32, z: f32) -> f32 {
+        fgrad2_isotropic(self.frequency * x, self.frequency * z, self.seed)
+    }
+
+    fn sample_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        let (n, g) = fgrad2_isotropic_d(self.frequency * x, self.frequency * z, self.seed);
+        (n, g * self.frequency)
+    }
+}
+
+// use another generators height as a field
+#[derive(Clone)]
+pub struct HeightField<G: LevelGenerator>(pub G);
+
+impl<G: LevelGenerator> Field2 for HeightField<G> {
+    fn sample(&self, x: f32, z: f32) -> f32 {
+        self.0.height(x, z)
+    }
+
+    fn sample_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        self.0.height_gradient(x, z)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum BlendOp {
+    Lerp(f32),
+    SmoothMin(f32),
+    SmoothMax(f32),
+}
+
+#[derive(Clone)]
+pub struct Blend<A: LevelGenerator, B: LevelGenerator> {
+    a: A,
+    b: B,
+    op: BlendOp,
+}
+
+impl<A: LevelGenerator, B: LevelGenerator> Blend<A, B> {
+    pub fn new(a: A, b: B, op: BlendOp) -> Blend<A, B> {
+        Blend { a, b, op }
+    }
+    pub fn lerp(a: A, b: B, t: f32) -> Blend<A, B> {
+        Blend::new(a, b, BlendOp::Lerp(t))
+    }
+    pub fn smin(a: A, b: B, k: f32) -> Blend<A, B> {
+        Blend::new(a, b, BlendOp::SmoothMin(k))
+    }
+    pub fn smax(a: A, b: B, k: f32) -> Blend<A, B> {
+        Blend::new(a, b, BlendOp::SmoothMax(k))
+    }
+
+    // whose blocks go on top
+    fn use_b(&self, x: i32, z: i32) -> bool {
+        match self.op {
+            BlendOp::Lerp(t) => t > 0.5,
+            BlendOp::SmoothMin(_) => self.b.height(x as f32, z as f32) < self.a.height(x as f32, z as f32),
+            BlendOp::SmoothMax(_) => self.b.height(x as f32, z as f32) > self.a.height(x as f32, z as f32),
+        }
+    }
+}
+
+impl<A: LevelGenerator, B: LevelGenerator> LevelGenerator for Blend<A, B> {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        let ha = self.a.height(x, z);
+        let hb = self.b.height(x, z);
+        match self.op {
+            BlendOp::Lerp(t) => lerp(ha, hb, t),
+            BlendOp::SmoothMin(k) => smin(ha, hb, k),
+            BlendOp::SmoothMax(k) => smax(ha, hb, k),
+        }
+    }
+
+    fn height_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        let (ha, ga) = self.a.height_gradient(x, z);
+        let (hb, gb) = self.b.height_gradient(x, z);
+        match self.op {
+            BlendOp::Lerp(t) => (lerp(ha, hb, t), ga * (1.0 - t) + gb * t),
+            BlendOp::SmoothMin(k) => {
+                let (h, t) = smin_h(ha, hb, k);
+                (h, gb * (1.0 - t) + ga * t)
+            },
+            // smax(a, b) is -smin(-a, -b)
+            BlendOp::SmoothMax(k) => {
+                let (h, t) = smin_h(-ha, -hb, k);
+                (-h, gb * (1.0 - t) + ga * t)
+            },
+        }
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    // surface comes from whichever side is winning
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        let a = self.a.uniform_block(oy, surface);
+        if a == self.b.uniform_block(oy, surface) { a } else { None }
+    }
+
+    // the rock underneath comes from a
+    fn geology(&self) -> Geology {
+        self.a.geology()
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Option<Climate> {
+        self.a.climate(x, z)
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        if self.use_b(x, z) {
+            self.b.surface_block(x, y, z, height)
+        } else {
+            self.a.surface_block(x, y, z, height)
+        }
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        if self.use_b(x, z) {
+            self.b.column_blocks(x, z, oy, height)
+        } else {
+            self.a.column_blocks(x, z, oy, height)
+        }
+    }
+}
+
+// a where the mask is below lo, b where its above hi, lerp in between
+#[derive(Clone)]
+pub struct Mask<A: LevelGenerator, B: LevelGenerator, M: Field2> {
+    a: A,
+    b: B,
+    mask: M,
+    lo: f32,
+    hi: f32,
+}
+
+impl<A: LevelGenerator, B: LevelGenerator, M: Field2> Mask<A, B, M> {
+    pub fn new(a: A, b: B, mask: M, lo: f32, hi: f32) -> Mask<A, B, M> {
+        Mask { a, b, mask, lo, hi }
+    }
+
+    fn t(&self, x: f32, z: f32) -> f32 {
+        saturate(self.mask.sample(x, z), self.lo, self.hi)
+    }
+}
+
+impl<A: LevelGenerator, B: LevelGenerator, M: Field2> LevelGenerator for Mask<A, B, M> {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        let t = self.t(x, z);
+        if t <= 0.0 {
+            self.a.height(x, z)
+        } else if t >= 1.0 {
+            self.b.height(x, z)
+        } else {
+            lerp(self.a.height(x, z), self.b.height(x, z), t)
+        }
+    }
+
+    fn height_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        let (m, gm) = self.mask.sample_gradient(x, z);
+        let (t, dt) = saturate_d(m, self.lo, self.hi);
+        if t <= 0.0 {
+            self.a.height_gradient(x, z)
+        } else if t >= 1.0 {
+            self.b.height_gradient(x, z)
+        } else {
+            let (ha, ga) = self.a.height_gradient(x, z);
+            let (hb, gb) = self.b.height_gradient(x, z);
+            (lerp(ha, hb, t), ga * (1.0 - t) + gb * t + gm * (dt * (hb - ha)))
+        }
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        let a = self.a.uniform_block(oy, surface);
+        if a == self.b.uniform_block(oy, surface) { a } else { None }
+    }
+
+    fn geology(&self) -> Geology {
+        self.a.geology()
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Option<Climate> {
+        self.a.climate(x, z)
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        if self.t(x as f32, z as f32) > 0.5 {
+            self.b.surface_block(x, y, z, height)
+        } else {
+            self.a.surface_block(x, y, z, height)
+        }
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        if self.t(x as f32, z as f32) > 0.5 {
+            self.b.column_blocks(x, z, oy, height)
+        } else {
+            self.a.column_blocks(x, z, oy, height)
+        }
+    }
+}
+
+// domain warp, samples inner at p + magnitude * (noise2(p) - 0.5)
+#[derive(Clone)]
+pub struct Warp<G: LevelGenerator> {
+    inner: G,
+    frequency: f32,
+    magnitude: f32,
+    seed: u32,
+}
+
+impl<G: LevelGenerator> Warp<G> {
+    pub fn new(inner: G, frequency: f32, magnitude: f32, seed: u32) -> Warp<G> {
+        Warp { inner, frequency, magnitude, seed }
+    }
+
+    fn warp(&self, x: f32, z: f32) -> Vec2 {
+        let p = self.frequency * Vec2::new(x, z);
+        let offset = Vec2::new(
+            fgrad2_isotropic(p.x, p.y, self.seed) - 0.5,
+            fgrad2_isotropic(p.x, p.y, self.seed.wrapping_add(0x230895F7)) - 0.5,
+        );
+        Vec2::new(x, z) + self.magnitude * offset
+    }
+
+    // warped position and its jacobian
+    fn warp_d(&self, x: f32, z: f32) -> (Vec2, Mat2) {
+        let p = self.frequency * Vec2::new(x, z);
+        let (ox, gx) = fgrad2_isotropic_d(p.x, p.y, self.seed);
+        let (oz, gz) = fgrad2_isotropic_d(p.x, p.y, self.seed.wrapping_add(0x230895F7));
+        let offset = Vec2::new(ox - 0.5, oz - 0.5);
+        let j = Mat2::identity().add(Mat2::new(gx, gz).scale(self.magnitude * self.frequency));
+        (Vec2::new(x, z) + self.magnitude * offset, j)
+    }
+}
+
+impl<G: LevelGenerator> LevelGenerator for Warp<G> {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        let p = self.warp(x, z);
+        self.inner.height(p.x, p.y)
+    }
+
+    fn height_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        let (p, j) = self.warp_d(x, z);
+        let (h, g) = self.inner.height_gradient(p.x, p.y);
+        (h, j.transpose_mul_vec(g))
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        self.inner.uniform_block(oy, surface)
+    }
+
+    fn geology(&self) -> Geology {
+        self.inner.geology()
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Option<Climate> {
+        self.inner.climate(x, z)
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        self.inner.surface_block(x, y, z, height)
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        self.inner.column_blocks(x, z, oy, height)
+    }
+}
+
+// piecewise linear height curve, points sorted by input, flat outside the ends
+#[derive(Clone, Debug)]
+pub struct Curve {
+    points: Vec<(f32, f32)>,
+}
+
+impl Curve {
+    pub fn new(mut points: Vec<(f32, f32)>) -> Curve {
+        assert!(!points.is_empty(), "curve needs at least one point");
+        points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Curve { points }
+    }
+
+    pub fn eval(&self, x: f32) -> f32 {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if x <= first.0 {
+            return first.1;
+        }
+        if x >= last.0 {
+            return last.1;
+        }
+        for w in self.points.windows(2) {
+            let (x0, y0) = w[0];
+            let (x1, y1) = w[1];
+            if x <= x1 {
+                return lerp(y0, y1, unlerp(x0, x1, x));
+            }
+        }
+        last.1
+    }
+
+    // value and slope, slope is 0 on the flat ends
+    pub fn eval_d(&self, x: f32) -> (f32, f32) {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if x <= first.0 {
+            return (first.1, 0.0);
+        }
+        if x >= last.0 {
+            return (last.1, 0.0);
+        }
+        for w in self.points.windows(2) {
+            let (x0, y0) = w[0];
+            let (x1, y1) = w[1];
+            if x <= x1 {
+                return (lerp(y0, y1, unlerp(x0, x1, x)), (y1 - y0) / (x1 - x0));
+            }
+        }
+        (last.1, 0.0)
+    }
+}
+
+#[derive(Clone)]
+pub struct Remap<G: LevelGenerator> {
+    inner: G,
+    curve: Curve,
+}
+
+impl<G: LevelGenerator> Remap<G> {
+    pub fn new(inner: G, curve: Curve) -> Remap<G> {
+        Remap { inner, curve }
+    }
+}
+
+impl<G: LevelGenerator> LevelGenerator for Remap<G> {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        self.curve.eval(self.inner.height(x, z))
+    }
+
+    fn height_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        let (h, g) = self.inner.height_gradient(x, z);
+        let (c, dc) = self.curve.eval_d(h);
+        (c, g * dc)
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        self.inner.uniform_block(oy, surface)
+    }
+
+    fn geology(&self) -> Geology {
+        self.inner.geology()
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Option<Climate> {
+        self.inner.climate(x, z)
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        self.inner.surface_block(x, y, z, height)
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        self.inner.column_blocks(x, z, oy, height)
+    }
+}
+
+// the readme headland: warped cliffy noise smooth maxed into the landscape, only near the coast
+pub fn headland(seed: u32) -> impl LevelGenerator {
+    let land = GenErosion::new(seed);
+    let cliffs = Warp::new(
+        Remap::new(GenCrag::new(seed.wrapping_add(0x5123)), Curve::new(vec![(-20.0, -20.0), (10.0, 10.0), (20.0, 45.0), (60.0, 60.0)])),
+        0.004, 60.0, seed.wrapping_add(0x98123),
+    );
+    let headlands = Blend::smax(land.clone(), cliffs, 8.0);
+    let coast = NoiseField::new(0.001, seed.wrapping_add(0x3434));
+    Mask::new(land, headlands, coast, 0.45, 0.55)
+}
+
+// a crag and erosion mix smooth minned into worldgen so its valleys cut into the hills.
+// masked by worldgens own height so its only up in the hills, the lowlands and coasts stay as they were
+pub fn valleys(seed: u32) -> impl LevelGenerator {
+    use crate::world_gen2::WorldGen;
+    let land = WorldGen::new(seed);
+    let cutter = Blend::lerp(GenErosion::new(seed.wrapping_add(0x7A11)), GenCrag::new(seed.wrapping_add(0x7A12)), 0.4);
+    Mask::new(land, Blend::smin(land, cutter, 12.0), HeightField(land), 20.0, 60.0)
+}
+
+#[test]
+fn test_curve() {
+    let c = Curve::new(vec![(1.0, 10.0), (0.0, 0.0)]);
+    assert_eq!(c.eval(-1.0), 0.0);
+    assert_eq!(c.eval(0.5), 5.0);
+    assert_eq!(c.eval(2.0), 10.0);
+}
+
+#[test]
+fn test_blend_bounds() {
+    let a = GenCrag::new(1);
+    let b = GenErosion::new(2);
+    let lo = Blend::smin(a.clone(), b.clone(), 4.0);
+    let hi = Blend::smax(a.clone(), b.clone(), 4.0);
+    for i in 0..50 {
+        let x = i as f32 * 37.0;
+        let z = i as f32 * -11.0;
+        let (ha, hb) = (a.height(x, z), b.height(x, z));
+        assert!(lo.height(x, z) <= ha.min(hb));
+        assert!(hi.height(x, z) >= ha.max(hb));
+    }
+}
+
+#[test]
+fn test_combinator_gradients() {
+    // warp and mask over worldgen so everything has an analytic path
+    use crate::world_gen2::WorldGen;
+    let land = WorldGen::new(3);
+    let gens: Vec<(&str, Box<dyn LevelGenerator>)> = vec![
+        ("blend", Box::new(Blend::smax(land, WorldGen::new(4), 8.0))),
+        ("mask", Box::new(Mask::new(land, WorldGen::new(5), NoiseField::new(0.003, 7), 0.4, 0.6))),
+        ("warp", Box::new(Warp::new(land, 0.004, 60.0, 9))),
+        ("remap", Box::new(Remap::new(land, Curve::new(vec![(-50.0, -50.0), (0.0, 0.0), (50.0, 100.0)])))),
+    ];
+    let eps = 0.01;
+    for (name, gen) in gens.iter() {
+        for i in 0..100 {
+            let x = i as f32 * 47.3 - 2000.0;
+            let z = i as f32 * -13.9 + 700.0;
+            let (h, g) = gen.height_gradient(x, z);
+            assert!((h - gen.height(x, z)).abs() < 1e-3, "{} value at {} {}", name, x, z);
+            let fdx = (gen.height(x + eps, z) - gen.height(x - eps, z)) / (2.0 * eps);
+            let fdz = (gen.height(x, z + eps) - gen.height(x, z - eps)) / (2.0 * eps);
+            let tol = 0.1 + 0.05 * g.magnitude();
+            assert!((g.x - fdx).abs() < tol && (g.y - fdz).abs() < tol,
+                "{} gradient at {} {}: {:?} vs {} {}", name, x, z, g, fdx, fdz);
+        }
+    }
+}
+
+#[test]
+fn test_mask_keeps_looks() {
+    // beach for x < 0, worldgen for x > 1. each side should come out block for block like the generator on its own,
+    // including the beach sand and worldgens bare rock on steep slopes that the standard stack doesnt have
+    use crate::world_gen2::WorldGen;
+    let (beach, land) = (GenBeach::new(3), WorldGen::new(3));
+    let gen = Mask::new(beach.clone(), land, |x: f32, _z: f32| x, 0.0, 1.0);
+    let mut different = 0;
+    for oz in -40..40 {
+        for (ox, own) in [(-3, &beach as &dyn LevelGenerator), (2, &land as &dyn LevelGenerator)] {
+            let surface = ColumnSurface::heights(own, ox, oz);
+            let oy = surface.min.div_euclid(S as f32) as i32;
+            let blocks = gen.generate_blocks(ox, oy, oz);
+            assert_eq!(blocks, own.generate_blocks(ox, oy, oz), "chunk {} {} {}", ox, oy, oz);
+            for idx in 0..S*S*S {
+                let column = (idx / S) % S * S + idx % S;
+                let y = oy*S as i32 + (idx / (S*S)) as i32;
+                different += (blocks[idx] != standard_block(y, surface.height[column] as i32)) as i32;
+            }
+        }
+    }
+    assert!(different > 0, "{}", different);
+}
@@ -0,0 +1,744 @@
+use crate::chunk::*;
+use crate::krand::*;
+use crate::kmath::*;
+use crate::world_gen::*;
+use crate::column_cache::*;
+use crate::gen_combinators::*;
+use crate::settings::*;
+use crate::strata::*;
+use std::sync::Arc;
+
+/*
+generator described as a node graph in a text file, so tweaking a constant doesnt mean touching rust.
+
+    # comments start with a hash
+    base = noise 0.0005
+    hills = noise_exp 0.0025
+    warped = warp hills 0.005 40
+    land = lerp base warped 0.5
+    height = remap land [0 -80 0.5 0 1 120]
+
+    block Air if depth < 0 and y > 0
+    block Water if depth < 0
+    block Grass if depth == 0 and y > 4
+    block Dirt if depth < 4
+    block Stone
+
+nodes:
+    noise freq                  fgrad2_isotropic
+    noise_exp freq              fgrad2_isotropic_exp
+    fbm/ridged/billow freq octaves lacunarity gain      krand Fractal
+    turbulence freq octaves lacunarity gain power
+    warp src freq magnitude     src sampled at a domain warped position
+    saturate src lo hi
+    lerp a b t
+    add/sub/mul/min/max a b
+    smin/smax a b k
+    remap src [x0 y0 x1 y1 ...] piecewise linear curve
+    transect src [intervals] [heights]  bezier_transect of src, with flat tangents
+
+a node argument can be another node or a number. nodes have to be defined before theyre used,
+and the node called height is the output. block rules are checked in order, first match wins,
+conditions can use depth (height - y), y, slope or any node. no block rules means the usual stack.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for GraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+#[derive(Debug, Clone, Copy)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FractalKind {
+    Fbm,
+    Ridged,
+    Billow,
+    Turbulence,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Const(f32),
+    Noise { frequency: f32, salt: u32, exp: bool },
+    Fractal { kind: FractalKind, frequency: f32, fractal: Fractal, power: f32, salt: u32 },
+    Warp { src: usize, frequency: f32, magnitude: f32, salt: u32 },
+    Saturate { src: usize, lo: f32, hi: f32 },
+    Lerp { a: usize, b: usize, t: usize },
+    Binary { op: BinOp, a: usize, b: usize },
+    Smooth { max: bool, a: usize, b: usize, k: f32 },
+    Remap { src: usize, curve: Curve },
+    Transect { src: usize, intervals: Vec<f32>, heights: Vec<f32> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Var {
+    Depth,
+    Y,
+    Slope,
+    Node(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CmpOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug, Clone)]
+struct Cond {
+    var: Var,
+    op: CmpOp,
+    value: f32,
+}
+
+#[derive(Debug, Clone)]
+struct BlockRule {
+    block: Block,
+    conds: Vec<Cond>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Graph {
+    nodes: Vec<Node>,
+    names: Vec<(String, usize)>,
+    height: usize,
+    rules: Vec<BlockRule>,
+    uses_slope: bool,
+}
+
+fn name_salt(name: &str) -> u32 {
+    name.bytes().fold(0x9E3779B9, |acc, b| khash(acc ^ b as u32))
+}
+
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+struct LineParser<'a> {
+    line: usize,
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl<'a> LineParser<'a> {
+    fn err<T>(&self, message: String) -> Result<T, GraphError> {
+        Err(GraphError { line: self.line, message })
+    }
+
+    fn next(&mut self, what: &str) -> Result<&'a str, GraphError> {
+        match self.tokens.get(self.pos) {
+            Some(t) => {
+                self.pos += 1;
+                Ok(*t)
+            },
+            None => self.err(format!("expected {}", what)),
+        }
+    }
+
+    fn num(&mut self, what: &str) -> Result<f32, GraphError> {
+        let t = self.next(what)?;
+        match t.parse::<f32>() {
+            Ok(v) if v.is_finite() => Ok(v),
+            _ => self.err(format!("expected a number for {}, got `{}`", what, t)),
+        }
+    }
+
+    fn list(&mut self, what: &str) -> Result<Vec<f32>, GraphError> {
+        if self.next(what)? != "[" {
+            return self.err(format!("expected [ to start {}", what));
+        }
+        let mut values = Vec::new();
+        loop {
+            let t = self.next(&format!("] to close {}", what))?;
+            if t == "]" {
+                return Ok(values);
+            }
+            match t.parse::<f32>() {
+                Ok(v) if v.is_finite() => values.push(v),
+                _ => return self.err(format!("expected a number in {}, got `{}`", what, t)),
+            }
+        }
+    }
+
+    fn finish(&self) -> Result<(), GraphError> {
+        if self.pos < self.tokens.len() {
+            self.err(format!("unexpected `{}`", self.tokens[self.pos]))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Graph {
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.names.iter().find(|(n, _)| n == name).map(|(_, idx)| *idx)
+    }
+
+    // a node reference or a number, numbers become anonymous constants
+    fn node_arg(&mut self, p: &mut LineParser, what: &str) -> Result<usize, GraphError> {
+        let t = p.next(what)?;
+        if let Ok(v) = t.parse::<f32>() {
+            if !v.is_finite() {
+                return p.err(format!("`{}` isnt a usable number", t));
+            }
+            self.nodes.push(Node::Const(v));
+            return Ok(self.nodes.len() - 1);
+        }
+        match self.lookup(t) {
+            Some(idx) => Ok(idx),
+            None => p.err(format!("unknown node `{}`", t)),
+        }
+    }
+
+    fn parse_node(&mut self, p: &mut LineParser, name: &str) -> Result<Node, GraphError> {
+        let op = p.next("a node type")?;
+        let node = match op {
+            "noise" | "noise_exp" => Node::Noise {
+                frequency: p.num("frequency")?,
+                salt: name_salt(name),
+                exp: op == "noise_exp",
+            },
+            "fbm" | "ridged" | "billow" | "turbulence" => {
+                let kind = match op {
+                    "fbm" => FractalKind::Fbm,
+                    "ridged" => FractalKind::Ridged,
+                    "billow" => FractalKind::Billow,
+                    _ => FractalKind::Turbulence,
+                };
+                let frequency = p.num("frequency")?;
+                let octaves = p.num("octaves")?;
+                if octaves < 1.0 || octaves > 16.0 || octaves.fract() != 0.0 {
+                    return p.err(format!("octaves has to be a whole number from 1 to 16, got {}", octaves));
+                }
+                let fractal = Fractal::new(octaves as u32, p.num("lacunarity")?, p.num("gain")?);
+                let power = match kind {
+                    FractalKind::Turbulence => p.num("turbulence power")?,
+                    _ => 0.0,
+                };
+                Node::Fractal { kind, frequency, fractal, power, salt: name_salt(name) }
+            },
+            "warp" => Node::Warp {
+                src: self.node_arg(p, "warp source")?,
+                frequency: p.num("warp frequency")?,
+                magnitude: p.num("warp magnitude")?,
+                salt: name_salt(name),
+            },
+            "saturate" => {
+                let src = self.node_arg(p, "saturate source")?;
+                let (lo, hi) = (p.num("lower bound")?, p.num("upper bound")?);
+                // saturate divides by hi - lo
+                if hi <= lo {
+                    return p.err(format!("saturate upper bound has to be above the lower bound, got {} to {}", lo, hi));
+                }
+                Node::Saturate { src, lo, hi }
+            },
+            "lerp" => Node::Lerp {
+                a: self.node_arg(p, "lerp a")?,
+                b: self.node_arg(p, "lerp b")?,
+                t: self.node_arg(p, "lerp t")?,
+            },
+            "add" | "sub" | "mul" | "min" | "max" => {
+                let op = match op {
+                    "add" => BinOp::Add,
+                    "sub" => BinOp::Sub,
+                    "mul" => BinOp::Mul,
+                    "min" => BinOp::Min,
+                    _ => BinOp::Max,
+                };
+                Node::Binary { op, a: self.node_arg(p, "first operand")?, b: self.node_arg(p, "second operand")? }
+            },
+            "smin" | "smax" => Node::Smooth {
+                max: op == "smax",
+                a: self.node_arg(p, "first operand")?,
+                b: self.node_arg(p, "second operand")?,
+                k: p.num("smoothing")?,
+            },
+            "remap" => {
+                let src = self.node_arg(p, "remap source")?;
+                let points = p.list("curve points")?;
+                if points.is_empty() || points.len() % 2 != 0 {
+                    return p.err("curve needs pairs of numbers, x y x y ...".to_string());
+                }
+                let curve = Curve::new(points.chunks(2).map(|c| (c[0], c[1])).collect());
+                Node::Remap { src, curve }
+            },
+            "transect" => {
+                let src = self.node_arg(p, "transect source")?;
+                let intervals = p.list("transect intervals")?;
+                let heights = p.list("transect heights")?;
+                if intervals.is_empty() {
+                    return p.err("transect needs at least one interval".to_string());
+                }
+                if intervals.iter().any(|i| *i <= 0.0) {
+                    return p.err("transect intervals have to be positive".to_string());
+                }
+                if heights.len() != intervals.len() + 1 {
+                    return p.err(format!("transect with {} intervals needs {} heights, got {}", intervals.len(), intervals.len() + 1, heights.len()));
+                }
+                Node::Transect { src, intervals, heights }
+            },
+            _ => return p.err(format!("unknown node type `{}`", op)),
+        };
+        Ok(node)
+    }
+
+    fn parse_cond(&self, p: &mut LineParser) -> Result<Cond, GraphError> {
+        let var_name = p.next("a condition")?;
+        let var = match var_name {
+            "depth" => Var::Depth,
+            "y" => Var::Y,
+            "slope" => Var::Slope,
+            name => match self.lookup(name) {
+                Some(idx) => Var::Node(idx),
+                None => return p.err(format!("unknown variable `{}`, expected depth, y, slope or a node", name)),
+            },
+        };
+        let op = match p.next("a comparison")? {
+            "<" => CmpOp::Lt,
+            "<=" => CmpOp::Le,
+            ">" => CmpOp::Gt,
+            ">=" => CmpOp::Ge,
+            "==" => CmpOp::Eq,
+            "!=" => CmpOp::Ne,
+            t => return p.err(format!("expected a comparison, got `{}`", t)),
+        };
+        let value = p.num("comparison value")?;
+        Ok(Cond { var, op, value })
+    }
+
+    fn parse_rule(&self, p: &mut LineParser) -> Result<BlockRule, GraphError> {
+        let block_name = p.next("a block name")?;
+        let block = match Block::from_name(block_name) {
+            Some(b) => b,
+            None => return p.err(format!("unknown block `{}`", block_name)),
+        };
+        let mut conds = Vec::new();
+        if p.pos < p.tokens.len() {
+            let t = p.next("if")?;
+            if t != "if" {
+                return p.err(format!("expected `if` after block, got `{}`", t));
+            }
+            conds.push(self.parse_cond(p)?);
+            while p.pos < p.tokens.len() {
+                let t = p.next("and")?;
+                if t != "and" {
+                    return p.err(format!("expected `and` between conditions, got `{}`", t));
+                }
+                conds.push(self.parse_cond(p)?);
+            }
+        }
+        Ok(BlockRule { block, conds })
+    }
+
+    pub fn parse(src: &str) -> Result<Graph, GraphError> {
+        let mut graph = Graph {
+            nodes: Vec::new(),
+            names: Vec::new(),
+            height: 0,
+            rules: Vec::new(),
+            uses_slope: false,
+        };
+
+        for (i, raw_line) in src.lines().enumerate() {
+            let line = raw_line.split('#').next().unwrap().replace('[', " [ ").replace(']', " ] ");
+            let mut p = LineParser {
+                line: i + 1,
+                tokens: line.split_whitespace().collect(),
+                pos: 0,
+            };
+            if p.tokens.is_empty() {
+                continue;
+            }
+
+            if p.tokens[0] == "block" {
+                p.pos = 1;
+                let rule = graph.parse_rule(&mut p)?;
+                graph.rules.push(rule);
+            } else {
+                let name = p.next("a node name")?;
+                if !valid_name(name) {
+                    return p.err(format!("`{}` isnt a valid node name", name));
+                }
+                if ["depth", "y", "slope", "block", "if", "and"].contains(&name) {
+                    return p.err(format!("`{}` is reserved", name));
+                }
+                if graph.lookup(name).is_some() {
+                    return p.err(format!("node `{}` is already defined", name));
+                }
+                if p.next("=")? != "=" {
+                    return p.err(format!("expected = after `{}`", name));
+                }
+                let node = graph.parse_node(&mut p, name)?;
+                graph.nodes.push(node);
+                graph.names.push((name.to_string(), graph.nodes.len() - 1));
+            }
+            p.finish()?;
+        }
+
+        graph.height = match graph.lookup("height") {
+            Some(idx) => idx,
+            None => return Err(GraphError { line: 0, message: "no node called height".to_string() }),
+        };
+        graph.uses_slope = graph.rules.iter().any(|r| r.conds.iter().any(|c| c.var == Var::Slope));
+        Ok(graph)
+    }
+
+    pub fn load(path: &str) -> Result<Graph, GraphError> {
+        let src = std::fs::read_to_string(path)
+            .map_err(|e| GraphError { line: 0, message: format!("couldnt read {}: {}", path, e) })?;
+        Graph::parse(&src)
+    }
+
+    fn eval(&self, idx: usize, x: f32, z: f32, seed: u32) -> f32 {
+        match &self.nodes[idx] {
+            Node::Const(v) => *v,
+            Node::Noise { frequency, salt, exp } => {
+                if *exp {
+                    fgrad2_isotropic_exp(frequency * x, frequency * z, seed.wrapping_add(*salt))
+                } else {
+                    fgrad2_isotropic(frequency * x, frequency * z, seed.wrapping_add(*salt))
+                }
+            },
+            Node::Fractal { kind, frequency, fractal, power, salt } => {
+                let (px, pz, s) = (frequency * x, frequency * z, seed.wrapping_add(*salt));
+                match kind {
+                    FractalKind::Fbm => fractal.fbm(px, pz, s),
+                    FractalKind::Ridged => fractal.ridged(px, pz, s),
+                    FractalKind::Billow => fractal.billow(px, pz, s),
+                    FractalKind::Turbulence => fractal.turbulence(px, pz, s, *power),
+                }
+            },
+            Node::Warp { src, frequency, magnitude, salt } => {
+                let s = seed.wrapping_add(*salt);
+                let wx = fgrad2_isotropic(frequency * x, frequency * z, s) - 0.5;
+                let wz = fgrad2_isotropic(frequency * x, frequency * z, s.wrapping_add(0x230895F7)) - 0.5;
+                self.eval(*src, x + magnitude * wx, z + magnitude * wz, seed)
+            },
+            Node::Saturate { src, lo, hi } => saturate(self.eval(*src, x, z, seed), *lo, *hi),
+            Node::Lerp { a, b, t } => lerp(self.eval(*a, x, z, seed), self.eval(*b, x, z, seed), self.eval(*t, x, z, seed)),
+            Node::Binary { op, a, b } => {
+                let a = self.eval(*a, x, z, seed);
+                let b = self.eval(*b, x, z, seed);
+                match op {
+                    BinOp::Add => a + b,
+                    BinOp::Sub => a - b,
+                    BinOp::Mul => a * b,
+                    BinOp::Min => a.min(b),
+                    BinOp::Max => a.max(b),
+                }
+            },
+            Node::Smooth { max, a, b, k } => {
+                let a = self.eval(*a, x, z, seed);
+                let b = self.eval(*b, x, z, seed);
+                if *max { smax(a, b, *k) } else { smin(a, b, *k) }
+            },
+            Node::Remap { src, curve } => curve.eval(self.eval(*src, x, z, seed)),
+            Node::Transect { src, intervals, heights } => {
+                let t = self.eval(*src, x, z, seed).clamp(0.0, 1.0);
+                let flat = vec![(Vec2::new(1.0, 0.0), Vec2::new(1.0, 0.0)); intervals.len()];
+                bezier_transect(t, intervals, heights, &flat)
+            },
+        }
+    }
+
+    // eval with the gradient wrt x z carried along. fractals and transects dont have
+    // analytic derivatives so they take a central difference locally
+    fn eval_d(&self, idx: usize, x: f32, z: f32, seed: u32) -> (f32, Vec2) {
+        let eps = 0.05;
+        match &self.nodes[idx] {
+            Node::Const(v) => (*v, Vec2::new(0.0, 0.0)),
+            Node::Noise { frequency, salt, exp } => {
+                let (n, g) = if *exp {
+                    fgrad2_isotropic_exp_d(frequency * x, frequency * z, seed.wrapping_add(*salt))
+                } else {
+                    fgrad2_isotropic_d(frequency * x, frequency * z, seed.wrapping_add(*salt))
+                };
+                (n, g * *frequency)
+            },
+            Node::Fractal { .. } => {
+                let dx = self.eval(idx, x + eps, z, seed) - self.eval(idx, x - eps, z, seed);
+                let dz = self.eval(idx, x, z + eps, seed) - self.eval(idx, x, z - eps, seed);
+                (self.eval(idx, x, z, seed), Vec2::new(dx, dz) / (2.0 * eps))
+            },
+            Node::Warp { src, frequency, magnitude, salt } => {
+                let s = seed.wrapping_add(*salt);
+                let (wx, gx) = fgrad2_isotropic_d(frequency * x, frequency * z, s);
+                let (wz, gz) = fgrad2_isotropic_d(frequency * x, frequency * z, s.wrapping_add(0x230895F7));
+                let j = Mat2::identity().add(Mat2::new(gx, gz).scale(magnitude * frequency));
+                let (v, g) = self.eval_d(*src, x + magnitude * (wx - 0.5), z + magnitude * (wz - 0.5), seed);
+                (v, j.transpose_mul_vec(g))
+            },
+            Node::Saturate { src, lo, hi } => {
+                let (v, g) = self.eval_d(*src, x, z, seed);
+                let (t, dt) = saturate_d(v, *lo, *hi);
+                (t, g * dt)
+            },
+            Node::Lerp { a, b, t } => {
+                let (a, ga) = self.eval_d(*a, x, z, seed);
+                let (b, gb) = self.eval_d(*b, x, z, seed);
+                let (t, gt) = self.eval_d(*t, x, z, seed);
+                (lerp(a, b, t), ga * (1.0 - t) + gb * t + gt * (b - a))
+            },
+            Node::Binary { op, a, b } => {
+                let (a, ga) = self.eval_d(*a, x, z, seed);
+                let (b, gb) = self.eval_d(*b, x, z, seed);
+                match op {
+                    BinOp::Add => (a + b, ga + gb),
+                    BinOp::Sub => (a - b, ga - gb),
+                    BinOp::Mul => (a * b, ga * b + gb * a),
+                    BinOp::Min => if a <= b { (a, ga) } else { (b, gb) },
+                    BinOp::Max => if a >= b { (a, ga) } else { (b, gb) },
+                }
+            },
+            Node::Smooth { max, a, b, k } => {
+                let (a, ga) = self.eval_d(*a, x, z, seed);
+                let (b, gb) = self.eval_d(*b, x, z, seed);
+                if *max {
+                    let (v, t) = smin_h(-a, -b, *k);
+                    (-v, gb * (1.0 - t) + ga * t)
+                } else {
+                    let (v, t) = smin_h(a, b, *k);
+                    (v, gb * (1.0 - t) + ga * t)
+                }
+            },
+            Node::Remap { src, curve } => {
+                let (v, g) = self.eval_d(*src, x, z, seed);
+                let (c, dc) = curve.eval_d(v);
+                (c, g * dc)
+            },
+            Node::Transect { src, intervals, heights } => {
+                let (v, g) = self.eval_d(*src, x, z, seed);
+                let flat = vec![(Vec2::new(1.0, 0.0), Vec2::new(1.0, 0.0)); intervals.len()];
+                let f = |t: f32| bezier_transect(t.clamp(0.0, 1.0), intervals, heights, &flat);
+                let dt = if v <= 0.0 || v >= 1.0 { 0.0 } else { (f(v + 0.001) - f(v - 0.001)) / 0.002 };
+                (f(v), g * dt)
+            },
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GenGraph {
+    graph: Arc<Graph>,
+    seed: u32,
+}
+
+impl GenGraph {
+    pub fn new(graph: Graph, seed: u32) -> GenGraph {
+        GenGraph { graph: Arc::new(graph), seed }
+    }
+
+    fn check(&self, cond: &Cond, x: i32, y: i32, z: i32, height: i32, slope: f32) -> bool {
+        let v = match cond.var {
+            Var::Depth => (height - y) as f32,
+            Var::Y => y as f32,
+            Var::Slope => slope,
+            Var::Node(idx) => self.graph.eval(idx, x as f32, z as f32, self.seed),
+        };
+        match cond.op {
+            CmpOp::Lt => v < cond.value,
+            CmpOp::Le => v <= cond.value,
+            CmpOp::Gt => v > cond.value,
+            CmpOp::Ge => v >= cond.value,
+            CmpOp::Eq => v == cond.value,
+            CmpOp::Ne => v != cond.value,
+        }
+    }
+
+    fn rule_block(&self, x: i32, y: i32, z: i32, height: i32, slope: f32) -> Block {
+        if self.graph.rules.is_empty() {
+            return standard_block(y, height);
+        }
+        for rule in self.graph.rules.iter() {
+            if rule.conds.iter().all(|c| self.check(c, x, y, z, height, slope)) {
+                return rule.block;
+            }
+        }
+        Block::Air
+    }
+}
+
+impl LevelGenerator for GenGraph {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        self.graph.eval(self.graph.height, x, z, self.seed)
+    }
+
+    fn height_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        self.graph.eval_d(self.graph.height, x, z, self.seed)
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        self.generate_blocks_with(ox, oy, oz, &self.column_surface(ox, oz).unwrap())
+    }
+
+    // gradients only if a rule looks at slope
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        if self.graph.uses_slope {
+            Some(ColumnSurface::gradients(self, ox, oz))
+        } else {
+            Some(ColumnSurface::heights(self, ox, oz))
+        }
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        if !self.graph.uses_slope {
+            return heightmap_blocks_with(self, ox, oy, oz, surface);
+        }
+        let mut blocks = vec![Block::Air; S*S*S];
+        for k in 0..S {
+            let z = oz*S as i32 + k as i32;
+
+            for i in 0..S {
+                let x = ox*S as i32 + i as i32;
+                let height = surface.height[k*S + i] as i32;
+                let slope = surface.grad[k*S + i].magnitude();
+
+                for j in 0..S {
+                    let idx = k*S + j*S*S + i;
+                    let y = oy*S as i32 + j as i32;
+                    blocks[idx] = self.rule_block(x, y, z, height, slope);
+                }
+            }
+        }
+        blocks
+    }
+
+    // block rules can put anything anywhere
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        if self.graph.rules.is_empty() {
+            standard_uniform_block(oy, surface)
+        } else {
+            None
+        }
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        let slope = if self.graph.uses_slope {
+            self.height_gradient(x as f32, z as f32).1.magnitude()
+        } else {
+            0.0
+        };
+        self.rule_block(x, y, z, height, slope)
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        let slope = if self.graph.uses_slope {
+            self.height_gradient(x as f32, z as f32).1.magnitude()
+        } else {
+            0.0
+        };
+        std::array::from_fn(|j| self.rule_block(x, oy*S as i32 + j as i32, z, height, slope))
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::standard(self.seed)
+    }
+}
+
+#[test]
+fn test_graph_parse() {
+    let graph = Graph::parse("
+        # rolling hills
+        base = noise 0.001
+        peaks = ridged 0.002 5 2 0.5
+        hills = warp peaks 0.01 20   # warped
+        mixed = lerp base hills 0.5
+        height = remap mixed [0 -50 1 100]
+        block Water if depth < 0 and y <= 0
+        block Air if depth < 0
+        block Stone if slope > 1.5
+        block Grass if depth == 0
+        block Stone
+    ").unwrap();
+    let gen = GenGraph::new(graph, 69);
+    for i in 0..20 {
+        let h = gen.height(i as f32 * 100.0, i as f32 * -30.0);
+        assert!(h >= -50.0 && h <= 100.0);
+    }
+    let blocks = gen.generate_blocks(0, 0, 0);
+    assert_eq!(blocks.len(), S*S*S);
+}
+
+#[test]
+fn test_graph_errors() {
+    let err = |src: &str| Graph::parse(src).unwrap_err();
+
+    assert_eq!(err("height = noise").line, 1);
+    assert_eq!(err("a = noise 0.1\n\nheight = wobble a").line, 3);
+    assert_eq!(err("height = lerp a 1 0.5").message, "unknown node `a`");
+    assert_eq!(err("a = noise 0.1\na = noise 0.2").line, 2);
+    assert_eq!(err("height = remap 1 [0 1 2]").line, 1);
+    assert_eq!(err("height = transect 0.5 [1 1] [0 1]").line, 1);
+    assert_eq!(err("height = noise 0.1\nblock Cheese").line, 2);
+    assert_eq!(err("height = noise 0.1\nblock Stone if depth ~ 3").line, 2);
+    assert_eq!(err("height = noise 0.1 0.2").message, "unexpected `0.2`");
+    assert_eq!(err("base = noise 0.1").line, 0);
+    assert_eq!(err("height = ridged 0.01 2.5 2 0.5").line, 1);
+    assert_eq!(err("height = turbulence 0.01 4 2 0.5").line, 1);
+    assert_eq!(err("a = noise 0.1\nheight = saturate a 0.7 0.3").line, 2);
+    assert_eq!(err("a = noise 0.1\nheight = saturate a 0.5 0.5").message, "saturate upper bound has to be above the lower bound, got 0.5 to 0.5");
+}
+
+#[test]
+fn test_shipped_graphs() {
+    for entry in std::fs::read_dir("graphs").unwrap() {
+        let path = entry.unwrap().path();
+        if let Err(e) = Graph::load(path.to_str().unwrap()) {
+            panic!("{}: {}", path.display(), e);
+        }
+    }
+}
+
+#[test]
+fn test_graph_gradient() {
+    let graph = Graph::parse("
+        base = noise 0.002
+        hills = noise_exp 0.01
+        mask = saturate base 0.3 0.7
+        mixed = lerp base hills mask
+        warped = warp mixed 0.004 40
+        sharp = smax warped base 0.1
+        height = remap sharp [0 -50 1 100]
+    ").unwrap();
+    let gen = GenGraph::new(graph, 12);
+    let eps = 0.01;
+    for i in 0..100 {
+        let x = i as f32 * 31.1 - 1500.0;
+        let z = i as f32 * -17.7 + 300.0;
+        let (h, g) = gen.height_gradient(x, z);
+        assert!((h - gen.height(x, z)).abs() < 1e-3);
+        let fdx = (gen.height(x + eps, z) - gen.height(x - eps, z)) / (2.0 * eps);
+        let fdz = (gen.height(x, z + eps) - gen.height(x, z - eps)) / (2.0 * eps);
+        let tol = 0.05 + 0.05 * g.magnitude();
+        assert!((g.x - fdx).abs() < tol && (g.y - fdz).abs() < tol, "gradient at {} {}: {:?} vs {} {}", x, z, g, fdx, fdz);
+    }
+}
@@ -0,0 +1,415 @@
+use crate::chunk::*;
+use crate::krand::*;
+use crate::kmath::*;
+use crate::settings::*;
+use crate::world_gen::*;
+use crate::strata::*;
+use crate::climate::*;
+use crate::rivers::*;
+use crate::column_cache::*;
+
+/*
+glacial valleys and fjords from erosion.md. same coarse drainage map the rivers use but with bigger cells,
+a drainage path is a glacier if enough cells drain through it and some of them are up above the snowline.
+the ice carves a U: flat floor, steep walls, and it keeps carving a long way below where it started so valleys
+coming off high ground near the coast get cut below the sea and flood into fjords.
+wherever its still cold enough the floor keeps a tongue of ice and the walls get snow, thats the residual glacier.
+like the rivers, carving is per column against the segments near the chunk so chunks dont need each other,
+and the troughs get worked out once for a whole chunk or column surface
+*/
+
+const CELL: f32 = 32.0;
+const ICE_MAX: f32 = 10.0;
+// how far out past the trough the walls get blended back into the terrain
+const WALL: f32 = 32.0;
+// biggest glacier relative to a min_acc one
+const MAX_SIZE: f32 = 2.5;
+// biggest the glacier_source slider goes times MAX_SIZE squared, past that they dont get any bigger
+const MAX_ACC: f32 = 512.0;
+
+#[derive(Clone, Copy, Debug)]
+struct Trough {
+    a: Vec2,
+    b: Vec2,
+    ha: f32,
+    hb: f32,
+    // 0..MAX_SIZE, scales the width and depth
+    sa: f32,
+    sb: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Column {
+    ground: f32,
+    // top of the ice if theres a glacier on the floor here
+    ice: Option<f32>,
+    // on a glaciers floor or walls and cold enough to keep snow
+    snowy: bool,
+}
+
+#[derive(Clone)]
+pub struct Glaciated<G: LevelGenerator> {
+    inner: G,
+    seed: u32,
+    // drainage that gets above this feeds a glacier
+    snowline: f32,
+    // cells draining at least this many cells are glaciers
+    min_acc: f32,
+    // trough half width and depth for a min_acc glacier
+    width: f32,
+    depth: f32,
+    // how far below the snowline a glacier still carves
+    reach: f32,
+    drainage: Drainage,
+}
+
+impl<G: LevelGenerator> Glaciated<G> {
+    pub fn new(inner: G, seed: u32) -> Glaciated<G> {
+        Glaciated {
+            inner,
+            seed,
+            snowline: 90.0,
+            min_acc: 6.0,
+            width: 12.0,
+            depth: 24.0,
+            reach: 150.0,
+            drainage: Self::drainage(seed),
+        }
+    }
+
+    fn size(&self, cell: &DrainCell) -> f32 {
+        if cell.acc < self.min_acc || cell.peak < self.snowline {
+            return 0.0;
+        }
+        let power = smoothstep(1.0 - (self.snowline - cell.height) / self.reach);
+        power * (cell.acc / self.min_acc).sqrt().min(MAX_SIZE)
+    }
+
+    // ice stays where the climate says its freezing, or above the snowline for generators without one
+    fn frozen(&self, x: f32, z: f32, ground: f32) -> bool {
+        match self.inner.climate(x, z) {
+            Some(c) => c.temperature < 0.0,
+            None => ground > self.snowline,
+        }
+    }
+
+    fn drainage(seed: u32) -> Drainage {
+        // ice doesnt stop for lakes
+        Drainage::new(seed.wrapping_add(0x6C078965), CELL, 0.0, MAX_ACC)
+    }
+
+    // every trough that could touch the box
+    fn nearby(&self, x0: f32, z0: f32, x1: f32, z1: f32) -> Vec<Trough> {
+        let reach = self.width * MAX_SIZE + WALL + 24.0 + CELL;
+        let (cx0, cz0) = (((x0 - reach) / CELL).floor() as i32, ((z0 - reach) / CELL).floor() as i32);
+        let (cx1, cz1) = (((x1 + reach) / CELL).floor() as i32, ((z1 + reach) / CELL).floor() as i32);
+        // one more all round for where the edge ones drain to
+        let w = (cx1 - cx0 + 3) as usize;
+        let cells = self.drainage.cells(&self.inner, cx0 - 1, cz0 - 1, cx1 + 1, cz1 + 1);
+        let cell = |cx: i32, cz: i32| cells[(cz - cz0 + 1) as usize * w + (cx - cx0 + 1) as usize];
+        let mut troughs = Vec::new();
+        for cz in cz0..=cz1 {
+            for cx in cx0..=cx1 {
+                let a = cell(cx, cz);
+                let sa = self.size(&a);
+                // the last one starting on land runs out into the sea, thats the mouth of the fjord
+                if sa <= 0.0 || a.height < SEA_LEVEL_F32 {
+                    continue;
+                }
+                if let Some((dx, dz)) = a.down {
+                    let b = cell(dx, dz);
+                    troughs.push(Trough {
+                        a: self.drainage.node(cx, cz),
+                        b: self.drainage.node(dx, dz),
+                        ha: a.height,
+                        hb: b.height,
+                        sa,
+                        // carries on at full size into the sea
+                        sb: if b.height < SEA_LEVEL_F32 { sa } else { self.size(&b) },
+                    });
+                }
+            }
+        }
+        troughs
+    }
+
+    fn column(&self, troughs: &[Trough], x: f32, z: f32) -> Column {
+        let h = self.inner.height(x, z);
+        if troughs.is_empty() {
+            return Column { ground: h, ice: None, snowy: false };
+        }
+        let p = Vec2::new(x, z) + 24.0 * Vec2::new(
+            fgrad2_isotropic(0.004 * x, 0.004 * z, self.seed.wrapping_add(0x2545F491)) - 0.5,
+            fgrad2_isotropic(0.004 * x, 0.004 * z, self.seed.wrapping_add(0x4F6CDD1D)) - 0.5,
+        );
+        let mut ground = h;
+        // thickest ice over the deepest floor wins
+        let mut ice: Option<(f32, f32)> = None;
+        let mut walls = false;
+        for t in troughs.iter() {
+            let ab = t.b - t.a;
+            let u = ((p - t.a).dot(ab) / ab.dot(ab)).clamp(0.0, 1.0);
+            let d = (p - (t.a + ab * u)).magnitude();
+            let size = lerp(t.sa, t.sb, u);
+            let w = self.width * size;
+            if size <= 0.0 || d > w + WALL {
+                continue;
+            }
+            let depth = self.depth * size;
+            let floor = lerp(t.ha, t.hb, u) - depth;
+            if d < w {
+                // flat in the middle, steep at the sides
+                let trough = floor + depth * (d / w).powi(4);
+                ground = ground.min(trough);
+                walls = true;
+                let thickness = ICE_MAX * size.min(1.0) * (1.0 - (d / w) * (d / w));
+                if ice.is_none_or(|(f, _)| floor < f) {
+                    ice = Some((floor, thickness));
+                }
+            } else {
+                let wall = floor + depth + 2.0 * (d - w);
+                let fade = smoothstep((d - w) / WALL);
+                let cut = lerp(h.min(wall), h, fade);
+                walls |= cut < h;
+                ground = ground.min(cut);
+            }
+        }
+        let cold = walls && self.frozen(x, z, ground);
+        let ice = ice
+            .filter(|_| cold)
+            .map(|(_, thickness)| ground + thickness.floor())
+            .filter(|top| *top > ground && *top > SEA_LEVEL_F32);
+        Column { ground, ice, snowy: cold }
+    }
+
+    // the inner generators chunk with the troughs cut into it and the ice put on,
+    // the troughs and the inner heights all come from the surface
+    fn carve(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        let mut blocks = match &surface.inner {
+            Some(inner) => self.inner.generate_blocks_with(ox, oy, oz, inner),
+            None => self.inner.generate_blocks(ox, oy, oz),
+        };
+        for k in 0..S {
+            let z = oz*S as i32 + k as i32;
+
+            for i in 0..S {
+                let x = ox*S as i32 + i as i32;
+                let h = surface.inner.as_ref().map_or_else(|| self.inner.height(x as f32, z as f32), |inner| inner.height[k*S + i]) as i32;
+                let height = surface.height[k*S + i] as i32;
+                let c = surface.cover[k*S + i];
+                if height == h && c.top.is_none() && !c.snowy {
+                    continue;
+                }
+                // whats left above the cut is air or sea, the same as the inner generator would put there
+                let column = self.inner.column_blocks(x, z, oy, height);
+                let top = c.top.map_or(h, |top| h.max(top as i32));
+
+                for j in 0..S {
+                    let idx = k*S + j*S*S + i;
+                    let y = oy*S as i32 + j as i32;
+                    if y < height || y > top {
+                        continue;
+                    }
+                    blocks[idx] = match c.top {
+                        Some(top) if y > height && y <= top as i32 => Block::Ice,
+                        _ => match column[j] {
+                            Block::Grass | Block::Dirt | Block::Stone if c.snowy && y == height => Block::Snow,
+                            b => b,
+                        },
+                    };
+                }
+            }
+        }
+        blocks
+    }
+}
+
+impl<G: LevelGenerator> LevelGenerator for Glaciated<G> {
+    // the rock under the ice
+    fn height(&self, x: f32, z: f32) -> f32 {
+        self.column(&self.nearby(x, z, x, z), x, z).ground
+    }
+
+    fn height_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        let troughs = self.nearby(x, z, x + 1.0, z + 1.0);
+        let h = self.column(&troughs, x, z).ground;
+        let hx = self.column(&troughs, x + 1.0, z).ground;
+        let hz = self.column(&troughs, x, z + 1.0).ground;
+        (h, Vec2::new(hx - h, hz - h))
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        self.carve(ox, oy, oz, &self.column_surface(ox, oz).unwrap())
+    }
+
+    // the troughs near the chunk worked out once for all its columns and border.
+    // ice counts towards the top so a glacier above the ground isnt taken for air.
+    // the inner surface goes along for the inner generators part of the chunk
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        let (x0, z0) = (ox*S as i32 - 1, oz*S as i32 - 1);
+        let w = S + 2;
+        let troughs = self.nearby(x0 as f32, z0 as f32, (x0 + w as i32) as f32, (z0 + w as i32) as f32);
+        let mut top = f32::NEG_INFINITY;
+        let mut cover = vec![Cover::default(); S*S];
+        let all: Vec<f32> = (0..w*w).map(|idx| {
+            let (i, k) = (idx % w, idx / w);
+            let c = self.column(&troughs, (x0 + i as i32) as f32, (z0 + k as i32) as f32);
+            if (1..=S).contains(&i) && (1..=S).contains(&k) {
+                cover[(k - 1)*S + i - 1] = Cover { top: c.ice, snowy: c.snowy };
+            }
+            top = top.max(c.ice.unwrap_or(c.ground));
+            c.ground
+        }).collect();
+        let mut surface = ColumnSurface::bordered(&all);
+        surface.max = surface.max.max(top);
+        surface.inner = self.inner.column_surface(ox, oz).map(Box::new);
+        surface.cover = cover;
+        Some(surface)
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        self.carve(ox, oy, oz, surface)
+    }
+
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        self.inner.uniform_block(oy, surface)
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        self.inner.surface_block(x, y, z, height)
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        self.inner.column_blocks(x, z, oy, height)
+    }
+
+    fn params(&self) -> Vec<GenParam> {
+        let mut params = self.inner.params();
+        params.push(GenParam::new("snowline", self.snowline, 0.0, 300.0));
+        params.push(GenParam::new("glacier_source", self.min_acc, 1.0, 64.0));
+        params.push(GenParam::new("trough_width", self.width, 2.0, 40.0));
+        params.push(GenParam::new("trough_depth", self.depth, 0.0, 80.0));
+        params.push(GenParam::new("glacier_reach", self.reach, 10.0, 600.0));
+        params
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "snowline" => self.snowline = value,
+            "glacier_source" => self.min_acc = value,
+            "trough_width" => self.width = value,
+            "trough_depth" => self.depth = value,
+            "glacier_reach" => self.reach = value,
+            // the drainage was worked out from the old inner heights
+            _ => {
+                self.inner.set_param(name, value);
+                self.drainage = Self::drainage(self.seed);
+            },
+        }
+    }
+
+    fn geology(&self) -> Geology {
+        self.inner.geology()
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Option<Climate> {
+        self.inner.climate(x, z)
+    }
+}
+
+// a valley coming down off a high plateau along +x into the sea
+#[cfg(test)]
+#[derive(Clone)]
+struct Coastline;
+
+#[cfg(test)]
+impl LevelGenerator for Coastline {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        (260.0 - 0.2 * x).max(-40.0) + 0.01 * z * z
+    }
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+}
+
+#[test]
+fn test_glaciers() {
+    let gen = Glaciated::new(Coastline, 3);
+    let floor = |x: f32| {
+        let troughs = gen.nearby(x, -60.0, x, 60.0);
+        (-60..60).map(|z| gen.column(&troughs, x, z as f32)).min_by(|a, b| a.ground.total_cmp(&b.ground)).unwrap()
+    };
+    // cut well down into the valley up high, with ice on it
+    let high = floor(400.0);
+    assert!(high.ground < Coastline.height(400.0, 0.0) - 10.0, "{:?}", high);
+    assert!(high.ice.is_some() && high.snowy, "{:?}", high);
+    // below the snowline its bare, and where the valley reaches the coast its cut under the sea
+    let low = floor(1200.0);
+    assert!(low.ice.is_none() && !low.snowy, "{:?}", low);
+    assert!(Coastline.height(1200.0, 0.0) > SEA_LEVEL_F32 && low.ground < SEA_LEVEL_F32 - 5.0, "{:?}", low);
+
+    // chunks agree with the point queries, across a drainage tile border too
+    let border = (64.0 * CELL) as i32 / S as i32;
+    let mut water = 0;
+    let mut ice = 0;
+    for (ox, oz) in [(25, -1), (25, 0), (75, 0), (border - 1, 0), (border, 0)] {
+        let tops: Vec<i32> = (0..S*S).map(|idx| {
+            let (x, z) = ((ox*S as i32 + (idx % S) as i32) as f32, (oz*S as i32 + (idx / S) as i32) as f32);
+            let c = gen.column(&gen.nearby(x, z, x, z), x, z);
+            c.ice.unwrap_or(c.ground) as i32
+        }).collect();
+        for oy in -4..14 {
+            let blocks = gen.generate_blocks(ox, oy, oz);
+            for idx in 0..S*S*S {
+                let y = oy*S as i32 + (idx / (S*S)) as i32;
+                let top = tops[(idx / S) % S * S + idx % S];
+                assert_eq!(blocks[idx].is_opaque(), y <= top, "chunk {} {} {} at {}", ox, oy, oz, idx);
+                water += (blocks[idx] == Block::Water) as i32;
+                ice += (blocks[idx] == Block::Ice) as i32;
+            }
+        }
+    }
+    assert!(water > 0 && ice > 0, "{} {}", water, ice);
+}
+
+#[test]
+fn test_glacier_columns() {
+    // through the column cache its the same as making chunks straight, and what gets skipped is all one thing
+    let gen = Glaciated::new(Coastline, 3);
+    let cache = ColumnCache::new();
+    let mut skipped = 0;
+    for (ox, oz) in [(25, -1), (25, 0), (75, 0)] {
+        let surface = gen.column_surface(ox, oz).unwrap();
+        assert_eq!(surface.inner.as_ref().unwrap().height, ColumnSurface::heights(&Coastline, ox, oz).height);
+        for oy in -4..14 {
+            let full = chunk_blocks(&gen, ox, oy, oz);
+            assert_eq!(gen.generate_blocks_with(ox, oy, oz, &surface), gen.generate_blocks(ox, oy, oz));
+            match cached_blocks(&gen, &cache, 0, ox, oy, oz) {
+                (blocks, None) => assert_eq!(blocks, full, "chunk {} {} {}", ox, oy, oz),
+                (_, Some(Block::Air)) => {
+                    skipped += 1;
+                    assert!(full.iter().all(|b| *b == Block::Air), "chunk {} {} {}", ox, oy, oz);
+                },
+                (_, Some(_)) => {
+                    skipped += 1;
+                    assert!(full.iter().all(|b| b.is_opaque()), "chunk {} {} {}", ox, oy, oz);
+                },
+            }
+        }
+    }
+    assert!(skipped > 0);
+
+    // the inner generators sliders come through
+    let mut gen = Glaciated::new(Climatic::new(Coastline, 3), 3);
+    gen.set_param("warmth", 12.0);
+    gen.set_param("snowline", 40.0);
+    let params = gen.params();
+    assert!(params.iter().any(|p| p.name == "warmth" && p.value == 12.0));
+    assert!(params.iter().any(|p| p.name == "snowline" && p.value == 40.0));
+}
@@ -0,0 +1,374 @@
+use std::sync::Arc;
+use crate::chunk::*;
+use crate::krand::*;
+use crate::kmath::*;
+use crate::settings::*;
+use crate::world_gen::*;
+use crate::column_cache::*;
+use crate::tile_cache::*;
+use crate::strata::*;
+use crate::climate::*;
+
+/*
+droplet hydraulic erosion on top of any generator.
+the world is cut into TILE sized tiles, each simulated on a coarse grid that hangs OVERLAP past its edges.
+a tile only depends on its coords, the seed and the wrapped heights, so chunks stay independent.
+near a border the two tiles results get crossfaded over the overlap, the weights add up to 1 so theres no seam.
+what comes out is a height delta added onto the wrapped height, so its detail survives and the tile res doesnt show much
+*/
+
+const TILE: i32 = 256;
+const OVERLAP: i32 = 64;
+const CELL: i32 = 4;
+const GRID: usize = ((TILE + 2*OVERLAP) / CELL) as usize + 1;
+const MAX_TILES: usize = 64;
+
+// eroded minus original height on the tile grid
+struct Tile {
+    delta: Vec<f32>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DropletParams {
+    pub droplets_per_cell: f32,
+    pub inertia: f32,
+    pub capacity: f32,
+    pub min_slope: f32,
+    pub erode: f32,
+    pub deposit: f32,
+    pub evaporate: f32,
+    pub gravity: f32,
+    pub max_steps: usize,
+}
+
+impl Default for DropletParams {
+    fn default() -> DropletParams {
+        DropletParams {
+            droplets_per_cell: 1.0,
+            inertia: 0.3,
+            capacity: 8.0,
+            min_slope: 0.05,
+            erode: 0.3,
+            deposit: 0.3,
+            evaporate: 0.02,
+            gravity: 4.0,
+            max_steps: 80,
+        }
+    }
+}
+
+// height and gradient of the grid at a fractional position, in blocks per cell
+fn grid_sample(h: &[f32], px: f32, pz: f32) -> (f32, Vec2) {
+    let (i, k) = (px as usize, pz as usize);
+    let (u, v) = (px - i as f32, pz - k as f32);
+    let h00 = h[k*GRID + i];
+    let h10 = h[k*GRID + i + 1];
+    let h01 = h[(k + 1)*GRID + i];
+    let h11 = h[(k + 1)*GRID + i + 1];
+    let height = lerp(lerp(h00, h10, u), lerp(h01, h11, u), v);
+    let grad = Vec2::new(
+        lerp(h10 - h00, h11 - h01, v),
+        lerp(h01 - h00, h11 - h10, u),
+    );
+    (height, grad)
+}
+
+// spread amount over the 4 grid points around the position
+fn grid_add(h: &mut [f32], px: f32, pz: f32, amount: f32) {
+    let (i, k) = (px as usize, pz as usize);
+    let (u, v) = (px - i as f32, pz - k as f32);
+    h[k*GRID + i] += amount * (1.0 - u) * (1.0 - v);
+    h[k*GRID + i + 1] += amount * u * (1.0 - v);
+    h[(k + 1)*GRID + i] += amount * (1.0 - u) * v;
+    h[(k + 1)*GRID + i + 1] += amount * u * v;
+}
+
+// the usual droplet sim, see Hans Beyer's thesis. works on h in place
+pub fn erode_grid(h: &mut [f32], p: &DropletParams, seed: u32) {
+    let max = (GRID - 1) as f32;
+    let n_droplets = (p.droplets_per_cell * (GRID * GRID) as f32) as u32;
+    for d in 0..n_droplets {
+        let mut px = (khash_2float(d, 0, seed) * max).min(max - 0.001);
+        let mut pz = (khash_2float(d, 1, seed) * max).min(max - 0.001);
+        let mut dir = Vec2::new(0.0, 0.0);
+        let mut speed = 1.0;
+        let mut water = 1.0;
+        let mut sediment = 0.0;
+
+        for _ in 0..p.max_steps {
+            let (height, grad) = grid_sample(h, px, pz);
+            dir = dir * p.inertia - grad * (1.0 - p.inertia);
+            let len = dir.magnitude();
+            if len < 1e-6 {
+                break;
+            }
+            dir = dir / len;
+            let (nx, nz) = (px + dir.x, pz + dir.y);
+            if nx < 0.0 || nz < 0.0 || nx >= max || nz >= max {
+                break;
+            }
+            let dh = grid_sample(h, nx, nz).0 - height;
+
+            let capacity = (-dh).max(p.min_slope) * speed * water * p.capacity;
+            if dh > 0.0 || sediment > capacity {
+                // uphill fills the pit, otherwise drop the excess
+                let amount = if dh > 0.0 { dh.min(sediment) } else { (sediment - capacity) * p.deposit };
+                sediment -= amount;
+                grid_add(h, px, pz, amount);
+            } else {
+                let amount = ((capacity - sediment) * p.erode).min(-dh);
+                sediment += amount;
+                grid_add(h, px, pz, -amount);
+            }
+
+            speed = (speed * speed - dh * p.gravity).max(0.0).sqrt();
+            water *= 1.0 - p.evaporate;
+            px = nx;
+            pz = nz;
+        }
+    }
+}
+
+// 1 in the tile, 0 at the far edge of its overlap, the neighbours weights make up the rest
+fn tile_weight(u: f32) -> f32 {
+    let o = OVERLAP as f32;
+    let t = TILE as f32;
+    let ramp = |x: f32| {
+        let x = x.clamp(0.0, 1.0);
+        x * x * (3.0 - 2.0 * x)
+    };
+    ramp((u + o) / (2.0 * o)) * (1.0 - ramp((u - t + o) / (2.0 * o)))
+}
+
+#[derive(Clone)]
+pub struct Eroded<G: LevelGenerator> {
+    inner: G,
+    seed: u32,
+    params: DropletParams,
+    // shared by every clone handed to the workers
+    tiles: Arc<TileCache<Tile>>,
+}
+
+impl<G: LevelGenerator> Eroded<G> {
+    pub fn new(inner: G, seed: u32) -> Eroded<G> {
+        Eroded { inner, seed, params: DropletParams::default(), tiles: Arc::new(TileCache::new(MAX_TILES)) }
+    }
+
+    fn simulate(&self, tx: i32, tz: i32) -> Tile {
+        let (x0, z0) = (tx * TILE - OVERLAP, tz * TILE - OVERLAP);
+        let mut h = Vec::with_capacity(GRID * GRID);
+        for k in 0..GRID {
+            for i in 0..GRID {
+                h.push(self.inner.height((x0 + i as i32 * CELL) as f32, (z0 + k as i32 * CELL) as f32));
+            }
+        }
+        let original = h.clone();
+        let tile_seed = khash(self.seed.wrapping_add(khash(tx as u32).wrapping_add(khash(tz as u32 ^ 0x5F3759DF))));
+        erode_grid(&mut h, &self.params, tile_seed);
+        Tile { delta: h.iter().zip(original.iter()).map(|(a, b)| a - b).collect() }
+    }
+
+    // how much erosion moved the ground at x z, positive is sediment
+    pub fn delta(&self, x: f32, z: f32) -> f32 {
+        let t = TILE as f32;
+        let o = OVERLAP as f32;
+        let mut total = 0.0;
+        for tz in ((z - o) / t).floor() as i32..=((z + o) / t).floor() as i32 {
+            for tx in ((x - o) / t).floor() as i32..=((x + o) / t).floor() as i32 {
+                if tile_weight(z - (tz * TILE) as f32) * tile_weight(x - (tx * TILE) as f32) > 0.0 {
+                    total += self.tiles.with(tx, tz, || self.simulate(tx, tz), |tile| tile_delta(tile, tx, tz, x, z));
+                }
+            }
+        }
+        total
+    }
+
+    // delta over a w by w square of blocks from x0 z0, indexed k*w + i.
+    // each tile gets locked once for the lot, and the sums go in the same order as delta so they match it exactly
+    pub fn deltas(&self, x0: i32, z0: i32, w: usize) -> Vec<f32> {
+        let t = TILE as f32;
+        let o = OVERLAP as f32;
+        let (x1, z1) = ((x0 + w as i32 - 1) as f32, (z0 + w as i32 - 1) as f32);
+        let mut total = vec![0.0; w*w];
+        for tz in ((z0 as f32 - o) / t).floor() as i32..=((z1 + o) / t).floor() as i32 {
+            for tx in ((x0 as f32 - o) / t).floor() as i32..=((x1 + o) / t).floor() as i32 {
+                self.tiles.with(tx, tz, || self.simulate(tx, tz), |tile| {
+                    for k in 0..w {
+                        let z = (z0 + k as i32) as f32;
+                        for i in 0..w {
+                            let x = (x0 + i as i32) as f32;
+                            if tile_weight(z - (tz * TILE) as f32) * tile_weight(x - (tx * TILE) as f32) > 0.0 {
+                                total[k*w + i] += tile_delta(tile, tx, tz, x, z);
+                            }
+                        }
+                    }
+                });
+            }
+        }
+        total
+    }
+
+    fn eroded_column(&self, x: i32, z: i32, oy: i32, height: i32, d: f32) -> [Block; S] {
+        let mut column = self.inner.column_blocks(x, z, oy, height);
+        let top = (height - oy*S as i32) as usize;
+        if top < S && height > SEA_LEVEL_I32 + 4 {
+            if let Some(b) = eroded_top(d) {
+                column[top] = b;
+            }
+        }
+        column
+    }
+}
+
+// one tiles weighted share of the delta at x z
+fn tile_delta(tile: &Tile, tx: i32, tz: i32, x: f32, z: f32) -> f32 {
+    let w = tile_weight(z - (tz * TILE) as f32) * tile_weight(x - (tx * TILE) as f32);
+    let gx = ((x - (tx * TILE - OVERLAP) as f32) / CELL as f32).clamp(0.0, (GRID - 1) as f32 - 0.001);
+    let gz = ((z - (tz * TILE - OVERLAP) as f32) / CELL as f32).clamp(0.0, (GRID - 1) as f32 - 0.001);
+    w * grid_sample(&tile.delta, gx, gz).0
+}
+
+// fresh sediment on top of fans, bare stone down scoured channels
+fn eroded_top(d: f32) -> Option<Block> {
+    if d > 1.5 {
+        Some(Block::Dirt)
+    } else if d < -3.0 {
+        Some(Block::Stone)
+    } else {
+        None
+    }
+}
+
+impl<G: LevelGenerator> LevelGenerator for Eroded<G> {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        self.inner.height(x, z) + self.delta(x, z)
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+
+    // the deltas for the column and its border in one go instead of a tile lookup per height
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        let (x0, z0) = (ox*S as i32 - 1, oz*S as i32 - 1);
+        let w = S + 2;
+        let delta = self.deltas(x0, z0, w);
+        let all: Vec<f32> = (0..w*w)
+            .map(|idx| self.inner.height((x0 + (idx % w) as i32) as f32, (z0 + (idx / w) as i32) as f32) + delta[idx])
+            .collect();
+        Some(ColumnSurface::bordered(&all))
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        // only the chunk with the ground in it needs the deltas
+        let y_lo = oy*S as i32;
+        let delta = if y_lo <= surface.max as i32 && y_lo + S as i32 > surface.min as i32 {
+            self.deltas(ox*S as i32, oz*S as i32, S)
+        } else {
+            vec![0.0; S*S]
+        };
+        let mut blocks = vec![Block::Air; S*S*S];
+        for k in 0..S {
+            let z = oz*S as i32 + k as i32;
+            for i in 0..S {
+                let x = ox*S as i32 + i as i32;
+                let column = self.eroded_column(x, z, oy, surface.height[k*S + i] as i32, delta[k*S + i]);
+                for j in 0..S {
+                    blocks[k*S + j*S*S + i] = column[j];
+                }
+            }
+        }
+        blocks
+    }
+
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        self.inner.uniform_block(oy, surface)
+    }
+
+    fn geology(&self) -> Geology {
+        self.inner.geology()
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Option<Climate> {
+        self.inner.climate(x, z)
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        if y == height && y > SEA_LEVEL_I32 + 4 {
+            if let Some(b) = eroded_top(self.delta(x as f32, z as f32)) {
+                return b;
+            }
+        }
+        self.inner.surface_block(x, y, z, height)
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        let top = height - oy*S as i32;
+        let d = if (0..S as i32).contains(&top) { self.delta(x as f32, z as f32) } else { 0.0 };
+        self.eroded_column(x, z, oy, height, d)
+    }
+
+    fn params(&self) -> Vec<GenParam> {
+        vec![
+            GenParam::new("droplets", self.params.droplets_per_cell, 0.0, 4.0),
+            GenParam::new("erode", self.params.erode, 0.0, 1.0),
+            GenParam::new("deposit", self.params.deposit, 0.0, 1.0),
+            GenParam::new("capacity", self.params.capacity, 0.0, 32.0),
+        ]
+    }
+
+    // tiles made with the old numbers are no good anymore
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "droplets" => self.params.droplets_per_cell = value,
+            "erode" => self.params.erode = value,
+            "deposit" => self.params.deposit = value,
+            "capacity" => self.params.capacity = value,
+            _ => return,
+        }
+        self.tiles = Arc::new(TileCache::new(MAX_TILES));
+    }
+}
+
+#[test]
+fn test_tile_weights() {
+    for i in -200..600 {
+        let x = i as f32 * 0.7;
+        let sum: f32 = (-2..3).map(|t| tile_weight(x - (t * TILE) as f32)).sum();
+        assert!((sum - 1.0).abs() < 1e-5, "weights at {} add to {}", x, sum);
+    }
+}
+
+#[test]
+fn test_erosion_seams() {
+    let gen = Eroded::new(GenCrag::new(3), 3);
+    let other = Eroded::new(GenCrag::new(3), 3);
+    let mut moved = 0.0f32;
+    // walk across the border between tiles 0 and 1, no jumps allowed
+    let mut last = gen.delta(TILE as f32 - 80.0, 40.0);
+    for i in 1..160 {
+        let x = TILE as f32 - 80.0 + i as f32;
+        let d = gen.delta(x, 40.0);
+        assert!((d - last).abs() < 2.0, "seam jump at {}: {} -> {}", x, last, d);
+        moved = moved.max(d.abs());
+        last = d;
+    }
+    assert!(moved > 0.1);
+    // same answer whichever order the tiles got made in
+    assert_eq!(other.height(TILE as f32 + 3.0, 40.0), gen.height(TILE as f32 + 3.0, 40.0));
+}
+
+#[test]
+fn test_eroded_columns() {
+    // the whole column at once comes out the same as height by height, across a tile border too
+    let gen = Eroded::new(GenCrag::new(3), 3);
+    for (ox, oz) in [(15, 2), (16, -1), (-1, 0)] {
+        let surface = gen.column_surface(ox, oz).unwrap();
+        let plain = ColumnSurface::heights(&gen, ox, oz);
+        assert_eq!(surface.height, plain.height);
+        assert_eq!((surface.min, surface.max), (plain.min, plain.max));
+        for oy in -4..6 {
+            assert_eq!(gen.generate_blocks_with(ox, oy, oz, &surface), heightmap_blocks(&gen, ox, oy, oz));
+        }
+    }
+}
@@ -15,6 +15,29 @@ pub fn saturate(x: f32, a: f32, b: f32) -> f32 {
     remap(x, a, b, 0.0, 1.0).clamp(0.0, 1.0)
 }
 
+// 0 below 0, 1 above 1 and an s curve with flat ends in between
+pub fn smoothstep(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// polynomial smooth min, k is roughly the width of the blend in output units
+pub fn smin(a: f32, b: f32, k: f32) -> f32 {
+    smin_h(a, b, k).0
+}
+
+pub fn smax(a: f32, b: f32, k: f32) -> f32 {
+    -smin(-a, -b, k)
+}
+
+#[test]
+pub fn test_smin() {
+    assert_eq!(smin(1.0, 5.0, 0.5), 1.0);
+    assert_eq!(smax(1.0, 5.0, 0.5), 5.0);
+    assert!(smin(2.0, 2.0, 1.0) < 2.0);
+    assert!(smax(2.0, 2.0, 1.0) > 2.0);
+}
+
 #[test]
 pub fn test_lerpy() {
     assert_eq!(0.5, lerp(0.0, 1.0, 0.5));
@@ -94,6 +117,52 @@ impl std::ops::Neg for Vec2 {
     }
 }
 
+impl Vec2 {
+    pub fn dot(&self, other: Vec2) -> f32 { self.x*other.x + self.y*other.y }
+}
+
+// 2x2 matrix as rows, for jacobians of 2d warps
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mat2 {
+    pub r0: Vec2,
+    pub r1: Vec2,
+}
+
+impl Mat2 {
+    pub fn new(r0: Vec2, r1: Vec2) -> Mat2 { Mat2 { r0, r1 } }
+    pub fn identity() -> Mat2 { Mat2::new(Vec2::new(1.0, 0.0), Vec2::new(0.0, 1.0)) }
+    pub fn scale(&self, s: f32) -> Mat2 { Mat2::new(self.r0 * s, self.r1 * s) }
+    pub fn add(&self, other: Mat2) -> Mat2 { Mat2::new(self.r0 + other.r0, self.r1 + other.r1) }
+    pub fn transpose_mul_vec(&self, v: Vec2) -> Vec2 { self.r0 * v.x + self.r1 * v.y }
+    pub fn mul(&self, other: Mat2) -> Mat2 {
+        let c0 = Vec2::new(other.r0.x, other.r1.x);
+        let c1 = Vec2::new(other.r0.y, other.r1.y);
+        Mat2::new(
+            Vec2::new(self.r0.dot(c0), self.r0.dot(c1)),
+            Vec2::new(self.r1.dot(c0), self.r1.dot(c1)),
+        )
+    }
+}
+
+// saturate and its slope, 0 outside the ramp
+pub fn saturate_d(x: f32, a: f32, b: f32) -> (f32, f32) {
+    let t = remap(x, a, b, 0.0, 1.0);
+    if t <= 0.0 || t >= 1.0 {
+        (t.clamp(0.0, 1.0), 0.0)
+    } else {
+        (t, 1.0 / (b - a))
+    }
+}
+
+// smin and the blend factor h, the gradient of smin is lerp(grad b, grad a, h)
+pub fn smin_h(a: f32, b: f32, k: f32) -> (f32, f32) {
+    if k <= 0.0 {
+        return if a < b { (a, 1.0) } else { (b, 0.0) };
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    (lerp(b, a, h) - k * h * (1.0 - h), h)
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
 pub struct Vec3 {
     pub x: f32,
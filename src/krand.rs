@@ -1,4 +1,7 @@
+use std::collections::HashMap;
+use std::sync::Arc;
 use crate::kmath::*;
+use crate::tile_cache::*;
 
 pub fn khash(seed: u32) -> u32 {
     let n1 = 0xB5297A4D;
@@ -23,7 +26,7 @@ pub fn khash_float2(seed: u32, x: f32, y: f32) -> u32 {
 
 // 0..1
 pub fn khash_2float(x: u32, y: u32, seed: u32) -> f32 {
-    khash(x + y * 0xA341316C + seed * 0xF73DB187) as f32 / std::u32::MAX as f32
+    khash(x.wrapping_add(y.wrapping_mul(0xA341316C)).wrapping_add(seed.wrapping_mul(0xF73DB187))) as f32 / std::u32::MAX as f32
 }
 
 pub fn khash_2fi_fo(x: f32, y: f32, seed: u32) -> f32 {
@@ -38,7 +41,7 @@ pub fn khash_2fi_fo_exp(x: f32, y: f32, seed: u32) -> f32 {
 
 // 0..1
 pub fn khash_3float(x: u32, y: u32, z: u32, seed: u32) -> f32 {
-    khash(x + y * 0xA341316C + seed * 0xF73DB187 + z * 0x412439CC) as f32 / std::u32::MAX as f32
+    khash(x.wrapping_add(y.wrapping_mul(0xA341316C)).wrapping_add(seed.wrapping_mul(0xF73DB187)).wrapping_add(z.wrapping_mul(0x412439CC))) as f32 / std::u32::MAX as f32
 }
 
 fn bilinear(a: f32, b: f32, c: f32, d: f32, t1: f32, t2: f32) -> f32 {
@@ -53,14 +56,16 @@ fn bilinear(a: f32, b: f32, c: f32, d: f32, t1: f32, t2: f32) -> f32 {
 const ROOT3ON2: f32 = 0.8660254037844386467637231707529361834714026269051903140279034897;
 const ROOT2INV: f32 = 0.70710678118;
 
+// gradients for all the 2d noise and its _d versions, indexed by a hash & 15
+const GRADS2: [(f32, f32); 16] = [(-1.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.0, -1.0), (ROOT2INV, ROOT2INV), (-ROOT2INV, ROOT2INV), (ROOT2INV, -ROOT2INV), (-ROOT2INV, -ROOT2INV),
+    (0.5, ROOT3ON2), (0.5, ROOT3ON2), (-0.5, -ROOT3ON2), (-ROOT3ON2, -0.5), (-0.5, ROOT3ON2), (-ROOT3ON2, 0.5), (0.5, -ROOT3ON2), (ROOT3ON2, -0.5)
+];
+
 pub fn grad2_isotropic(x: f32, y: f32, seed: u32) -> f32 {
     let (xfloor, xfrac) = floorfrac(x);
     let (yfloor, yfrac) = floorfrac(y);
     // also why not use a bigger gradient table
     //let grads = [(-1.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.0, -1.0), (root2, root2), (-root2, root2), (root2, -root2), (-root2, -root2)];
-    let grads = [(-1.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.0, -1.0), (ROOT2INV, ROOT2INV), (-ROOT2INV, ROOT2INV), (ROOT2INV, -ROOT2INV), (-ROOT2INV, -ROOT2INV),
-        (0.5, ROOT3ON2), (0.5, ROOT3ON2), (-0.5, -ROOT3ON2), (-ROOT3ON2, -0.5), (-0.5, ROOT3ON2), (-ROOT3ON2, 0.5), (0.5, -ROOT3ON2), (ROOT3ON2, -0.5)
-    ];
     // idk whystefan gustavson does the below and not the above. it kinda does look better lol
     // also why not more gradients?
     //let grads = [(-1.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.0, -1.0), (1.0, 1.0), (-1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)];
@@ -68,7 +73,7 @@ pub fn grad2_isotropic(x: f32, y: f32, seed: u32) -> f32 {
     let cf = |corner_x: f32, corner_y: f32| {
         let g_idx = khash_float2(seed, corner_x + xfloor, corner_y + yfloor) & 15;
         //let g_idx = khash(xu + corner_x + (yu + corner_y) * 0xA341316C + seed * 0xF73DB187) & 15;
-        let (dx, dy) = grads[g_idx as usize];
+        let (dx, dy) = GRADS2[g_idx as usize];
         // println!("dx {} dy {}", dx, dy);
         // println!("xfrac {} yfrac {}", x.fract(), y.fract());
         dx * (xfrac - corner_x as f32) + dy * (yfrac - corner_y as f32)
@@ -85,6 +90,51 @@ pub fn grad2_isotropic(x: f32, y: f32, seed: u32) -> f32 {
     (result + 1.0) / 2.0
 }
 
+fn fade_d(t: f32) -> f32 {
+    30.0*t*t*(t - 1.0)*(t - 1.0)
+}
+
+// bilinear blend of the 4 corner dot products plus its derivative, corners are (value, gradient)
+fn bilinear_d(c: [(f32, Vec2); 4], tx: f32, ty: f32) -> (f32, Vec2) {
+    let (u, v) = (fade(tx), fade(ty));
+    let (du, dv) = (fade_d(tx), fade_d(ty));
+    let [(c1, g1), (c2, g2), (c3, g3), (c4, g4)] = c;
+    let k = c1 - c2 - c3 + c4;
+    let gk = g1 - g2 - g3 + g4;
+    let value = bilinear(c1, c2, c3, c4, tx, ty);
+    let grad = g1 + u*(g2 - g1) + v*(g3 - g1) + (u*v)*gk
+        + Vec2::new(du * ((c2 - c1) + v*k), dv * ((c3 - c1) + u*k));
+    (value, grad)
+}
+
+// grad2_isotropic and its derivative wrt x and y
+pub fn grad2_isotropic_d(x: f32, y: f32, seed: u32) -> (f32, Vec2) {
+    let (xfloor, xfrac) = floorfrac(x);
+    let (yfloor, yfrac) = floorfrac(y);
+
+    let cf = |corner_x: f32, corner_y: f32| {
+        let g_idx = khash_float2(seed, corner_x + xfloor, corner_y + yfloor) & 15;
+        let (dx, dy) = GRADS2[g_idx as usize];
+        (dx * (xfrac - corner_x) + dy * (yfrac - corner_y), Vec2::new(dx, dy))
+    };
+
+    let (n, g) = bilinear_d([cf(0.0, 0.0), cf(1.0, 0.0), cf(0.0, 1.0), cf(1.0, 1.0)], xfrac, yfrac);
+    ((n + 1.0) / 2.0, g / 2.0)
+}
+
+// fgrad2_isotropic and its derivative
+pub fn fgrad2_isotropic_d(x: f32, y: f32, seed: u32) -> (f32, Vec2) {
+    let octaves = [(1.0, 0x3523423), (2.0, 0xF73DB187), (4.0, 0x159CBAFE), (8.0, 0x83242364)];
+    let mut value = 0.0;
+    let mut grad = Vec2::new(0.0, 0.0);
+    for (freq, mul) in octaves {
+        let (n, g) = grad2_isotropic_d(x * freq, y * freq, seed.wrapping_mul(mul));
+        value += n / freq;
+        grad = grad + g;
+    }
+    (value / 1.675, grad / 1.675)
+}
+
 pub fn fgrad2_isotropic(x: f32, y: f32, seed: u32) -> f32 {
     (1.000 * grad2_isotropic(x, y, seed.wrapping_mul(0x3523423)) +
     0.500 * grad2_isotropic(x * 2.0, y * 2.0, seed.wrapping_mul(0xF73DB187)) + 
@@ -98,9 +148,6 @@ pub fn grad2_isotropic_exp(x: f32, y: f32, seed: u32) -> f32 {
     let (yfloor, yfrac) = floorfrac(y);
     // also why not use a bigger gradient table
     //let grads = [(-1.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.0, -1.0), (root2, root2), (-root2, root2), (root2, -root2), (-root2, -root2)];
-    let grads = [(-1.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.0, -1.0), (ROOT2INV, ROOT2INV), (-ROOT2INV, ROOT2INV), (ROOT2INV, -ROOT2INV), (-ROOT2INV, -ROOT2INV),
-        (0.5, ROOT3ON2), (0.5, ROOT3ON2), (-0.5, -ROOT3ON2), (-ROOT3ON2, -0.5), (-0.5, ROOT3ON2), (-ROOT3ON2, 0.5), (0.5, -ROOT3ON2), (ROOT3ON2, -0.5)
-    ];
     // idk whystefan gustavson does the below and not the above. it kinda does look better lol
     // also why not more gradients?
     //let grads = [(-1.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.0, -1.0), (1.0, 1.0), (-1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)];
@@ -109,7 +156,7 @@ pub fn grad2_isotropic_exp(x: f32, y: f32, seed: u32) -> f32 {
         let g_idx = khash_float2(seed, corner_x + xfloor, corner_y + yfloor) & 15;
         let magnitude = khash_2fi_fo_exp(corner_x + xfloor, corner_y + yfloor, seed);
         //let g_idx = khash(xu + corner_x + (yu + corner_y) * 0xA341316C + seed * 0xF73DB187) & 15;
-        let (dx, dy) = grads[g_idx as usize];
+        let (dx, dy) = GRADS2[g_idx as usize];
         // println!("dx {} dy {}", dx, dy);
         // println!("xfrac {} yfrac {}", x.fract(), y.fract());
         magnitude * dx * (xfrac - corner_x as f32) + dy * (yfrac - corner_y as f32)
@@ -126,6 +173,34 @@ pub fn grad2_isotropic_exp(x: f32, y: f32, seed: u32) -> f32 {
     (result + 1.0) / 2.0
 }
 
+pub fn grad2_isotropic_exp_d(x: f32, y: f32, seed: u32) -> (f32, Vec2) {
+    let (xfloor, xfrac) = floorfrac(x);
+    let (yfloor, yfrac) = floorfrac(y);
+
+    // same as grad2_isotropic_exp, magnitude only scales the x part
+    let cf = |corner_x: f32, corner_y: f32| {
+        let g_idx = khash_float2(seed, corner_x + xfloor, corner_y + yfloor) & 15;
+        let magnitude = khash_2fi_fo_exp(corner_x + xfloor, corner_y + yfloor, seed);
+        let (dx, dy) = GRADS2[g_idx as usize];
+        (magnitude * dx * (xfrac - corner_x) + dy * (yfrac - corner_y), Vec2::new(magnitude * dx, dy))
+    };
+
+    let (n, g) = bilinear_d([cf(0.0, 0.0), cf(1.0, 0.0), cf(0.0, 1.0), cf(1.0, 1.0)], xfrac, yfrac);
+    ((n + 1.0) / 2.0, g / 2.0)
+}
+
+pub fn fgrad2_isotropic_exp_d(x: f32, y: f32, seed: u32) -> (f32, Vec2) {
+    let octaves = [(1.0, 0x3523423), (2.0, 0xF73DB187), (4.0, 0x159CBAFE), (8.0, 0x83242364)];
+    let mut value = 0.0;
+    let mut grad = Vec2::new(0.0, 0.0);
+    for (freq, mul) in octaves {
+        let (n, g) = grad2_isotropic_exp_d(x * freq, y * freq, seed.wrapping_mul(mul));
+        value += n / freq;
+        grad = grad + g;
+    }
+    (value / 1.675, grad / 1.675)
+}
+
 pub fn fgrad2_isotropic_exp(x: f32, y: f32, seed: u32) -> f32 {
     (1.000 * grad2_isotropic_exp(x, y, seed.wrapping_mul(0x3523423)) +
     0.500 * grad2_isotropic_exp(x * 2.0, y * 2.0, seed.wrapping_mul(0xF73DB187)) + 
@@ -134,6 +209,393 @@ pub fn fgrad2_isotropic_exp(x: f32, y: f32, seed: u32) -> f32 {
     1.675
 }
 
+fn fade(t: f32) -> f32 {
+    ((6.0*t - 15.0)*t + 10.0)*t*t*t
+}
+
+fn trilinear(c: [f32; 8], tx: f32, ty: f32, tz: f32) -> f32 {
+    let (u, v, w) = (fade(tx), fade(ty), fade(tz));
+    let x00 = lerp(c[0], c[1], u);
+    let x10 = lerp(c[2], c[3], u);
+    let x01 = lerp(c[4], c[5], u);
+    let x11 = lerp(c[6], c[7], u);
+    lerp(lerp(x00, x10, v), lerp(x01, x11, v), w)
+}
+
+// cube edge midpoints like perlins improved noise
+const GRADS3: [(f32, f32, f32); 12] = [
+    (1.0, 1.0, 0.0), (-1.0, 1.0, 0.0), (1.0, -1.0, 0.0), (-1.0, -1.0, 0.0),
+    (1.0, 0.0, 1.0), (-1.0, 0.0, 1.0), (1.0, 0.0, -1.0), (-1.0, 0.0, -1.0),
+    (0.0, 1.0, 1.0), (0.0, -1.0, 1.0), (0.0, 1.0, -1.0), (0.0, -1.0, -1.0),
+];
+
+// 3d gradient noise, 0..1
+pub fn grad3(x: f32, y: f32, z: f32, seed: u32) -> f32 {
+    let (xfloor, xfrac) = floorfrac(x);
+    let (yfloor, yfrac) = floorfrac(y);
+    let (zfloor, zfrac) = floorfrac(z);
+    let (xi, yi, zi) = (xfloor as i32, yfloor as i32, zfloor as i32);
+
+    let mut c = [0.0; 8];
+    for corner in 0..8 {
+        let (cx, cy, cz) = ((corner & 1) as i32, ((corner >> 1) & 1) as i32, ((corner >> 2) & 1) as i32);
+        let h = khash_3float((xi + cx) as u32, (yi + cy) as u32, (zi + cz) as u32, seed);
+        let (gx, gy, gz) = GRADS3[((h * 12.0) as usize).min(11)];
+        c[corner] = gx * (xfrac - cx as f32) + gy * (yfrac - cy as f32) + gz * (zfrac - cz as f32);
+    }
+
+    // edge gradients have length root 2 so the raw value is within about +-1
+    (trilinear(c, xfrac, yfrac, zfrac) + 1.0) / 2.0
+}
+
+// 3d value noise, 0..1, blobbier than gradient noise
+pub fn value3(x: f32, y: f32, z: f32, seed: u32) -> f32 {
+    let (xfloor, xfrac) = floorfrac(x);
+    let (yfloor, yfrac) = floorfrac(y);
+    let (zfloor, zfrac) = floorfrac(z);
+    let (xi, yi, zi) = (xfloor as i32, yfloor as i32, zfloor as i32);
+
+    let mut c = [0.0; 8];
+    for corner in 0..8 {
+        let (cx, cy, cz) = ((corner & 1) as i32, ((corner >> 1) & 1) as i32, ((corner >> 2) & 1) as i32);
+        c[corner] = khash_3float((xi + cx) as u32, (yi + cy) as u32, (zi + cz) as u32, seed);
+    }
+    trilinear(c, xfrac, yfrac, zfrac)
+}
+
+// what worley (cellular) noise knows about a point. one feature point per unit cell, jittered
+// jitter 0 is a plain grid and 1 is anywhere in the cell. its just hashes of integer cells so any chunk gets the same answer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Worley2 {
+    // distance to the nearest and second nearest feature point
+    pub f1: f32,
+    pub f2: f32,
+    // the nearest points cell, and a hash of it for picking things per cell
+    pub cell: (i32, i32),
+    pub id: u32,
+    pub point: Vec2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Worley3 {
+    pub f1: f32,
+    pub f2: f32,
+    pub cell: (i32, i32, i32),
+    pub id: u32,
+    pub point: Vec3,
+}
+
+impl Worley2 {
+    // 0 on the cell borders, cracks and ridges
+    pub fn edge(&self) -> f32 {
+        self.f2 - self.f1
+    }
+}
+
+impl Worley3 {
+    pub fn edge(&self) -> f32 {
+        self.f2 - self.f1
+    }
+}
+
+// cells either side to search. 2 is enough for f1 and f2 with any jitter up to 1
+const WORLEY_REACH: i32 = 2;
+
+pub fn worley_point2(cx: i32, cy: i32, seed: u32, jitter: f32) -> Vec2 {
+    let (ux, uy) = (cx as u32, cy as u32);
+    Vec2::new(
+        cx as f32 + 0.5 + jitter * (khash_2float(ux, uy, seed) - 0.5),
+        cy as f32 + 0.5 + jitter * (khash_2float(ux, uy, seed.wrapping_add(1)) - 0.5),
+    )
+}
+
+// the hash a worley cell gets for its id
+pub fn worley_id2(cx: i32, cy: i32, seed: u32) -> u32 {
+    khash((cx as u32).wrapping_add((cy as u32).wrapping_mul(0xA341316C)).wrapping_add(seed.wrapping_mul(0xF73DB187)))
+}
+
+pub fn worley_point3(cx: i32, cy: i32, cz: i32, seed: u32, jitter: f32) -> Vec3 {
+    let (ux, uy, uz) = (cx as u32, cy as u32, cz as u32);
+    Vec3::new(
+        cx as f32 + 0.5 + jitter * (khash_3float(ux, uy, uz, seed) - 0.5),
+        cy as f32 + 0.5 + jitter * (khash_3float(ux, uy, uz, seed.wrapping_add(1)) - 0.5),
+        cz as f32 + 0.5 + jitter * (khash_3float(ux, uy, uz, seed.wrapping_add(2)) - 0.5),
+    )
+}
+
+fn worley2_reach(x: f32, y: f32, seed: u32, jitter: f32, reach: i32) -> Worley2 {
+    let (xi, yi) = (x.floor() as i32, y.floor() as i32);
+    let mut best = Worley2 { f1: f32::INFINITY, f2: f32::INFINITY, cell: (xi, yi), id: 0, point: Vec2::new(x, y) };
+    for cy in yi - reach..=yi + reach {
+        for cx in xi - reach..=xi + reach {
+            let p = worley_point2(cx, cy, seed, jitter);
+            let d = (p - Vec2::new(x, y)).magnitude();
+            if d < best.f1 {
+                best.f2 = best.f1;
+                best.f1 = d;
+                best.cell = (cx, cy);
+                best.point = p;
+            } else if d < best.f2 {
+                best.f2 = d;
+            }
+        }
+    }
+    best.id = worley_id2(best.cell.0, best.cell.1, seed);
+    best
+}
+
+fn worley3_reach(x: f32, y: f32, z: f32, seed: u32, jitter: f32, reach: i32) -> Worley3 {
+    let (xi, yi, zi) = (x.floor() as i32, y.floor() as i32, z.floor() as i32);
+    let mut best = Worley3 { f1: f32::INFINITY, f2: f32::INFINITY, cell: (xi, yi, zi), id: 0, point: Vec3::new(x, y, z) };
+    for cz in zi - reach..=zi + reach {
+        for cy in yi - reach..=yi + reach {
+            for cx in xi - reach..=xi + reach {
+                let p = worley_point3(cx, cy, cz, seed, jitter);
+                let d = (p - Vec3::new(x, y, z)).magnitude();
+                if d < best.f1 {
+                    best.f2 = best.f1;
+                    best.f1 = d;
+                    best.cell = (cx, cy, cz);
+                    best.point = p;
+                } else if d < best.f2 {
+                    best.f2 = d;
+                }
+            }
+        }
+    }
+    let (cx, cy, cz) = best.cell;
+    best.id = khash((cx as u32).wrapping_add((cy as u32).wrapping_mul(0xA341316C)).wrapping_add((cz as u32).wrapping_mul(0x412439CC)).wrapping_add(seed.wrapping_mul(0xF73DB187)));
+    best
+}
+
+// fully jittered, the usual look
+pub fn worley2(x: f32, y: f32, seed: u32) -> Worley2 {
+    worley2_jitter(x, y, seed, 1.0)
+}
+
+// less jitter is more regular, like cracked mud instead of random cells
+pub fn worley2_jitter(x: f32, y: f32, seed: u32, jitter: f32) -> Worley2 {
+    worley2_reach(x, y, seed, jitter.clamp(0.0, 1.0), WORLEY_REACH)
+}
+
+pub fn worley3(x: f32, y: f32, z: f32, seed: u32) -> Worley3 {
+    worley3_jitter(x, y, z, seed, 1.0)
+}
+
+pub fn worley3_jitter(x: f32, y: f32, z: f32, seed: u32, jitter: f32) -> Worley3 {
+    worley3_reach(x, y, z, seed, jitter.clamp(0.0, 1.0), WORLEY_REACH)
+}
+
+// candidates per grid cell for PoissonDisk, more fills the gaps better
+const DISK_CANDIDATES: u32 = 6;
+
+#[derive(Clone, Copy, Debug)]
+struct DiskCandidate {
+    x: f32,
+    z: f32,
+    spacing: f32,
+    // hash first, the cell is just a tie break
+    priority: (u32, i32, i32, u32),
+}
+
+// blue noise points over the whole plane. every min_spacing cell has a few hashed candidates with hashed priorities
+// and a candidate is kept if no kept candidate with a higher priority is too close to it. thats dart throwing in
+// priority order, but any one candidate can be worked out by following the chain of higher priority ones near it,
+// which is short, so whichever chunk asks first gets the same points.
+// spacing is how far apart points need to be around a position, clamped to min..max. two points are never closer
+// than the bigger of their spacings. which candidates in a cell got kept goes in cells, hand the same one to every
+// disk with the same seed and spacing so the chains only get followed once
+pub struct PoissonDisk<F: Fn(f32, f32) -> f32> {
+    pub seed: u32,
+    pub min_spacing: f32,
+    pub max_spacing: f32,
+    pub spacing: F,
+    pub cells: Arc<TileCache<DiskCell>>,
+}
+
+type DiskKey = (i32, i32, u32);
+
+// how far x is from cell c along one axis, 0 inside it
+fn cell_gap(x: f32, c: i32, size: f32) -> f32 {
+    (c as f32 * size - x).max(x - (c + 1) as f32 * size).max(0.0)
+}
+
+// candidates already hashed up, by cell
+type Seen = HashMap<(i32, i32), [DiskCandidate; DISK_CANDIDATES as usize]>;
+
+// kept or not for each candidate in a cell
+pub type DiskCell = [bool; DISK_CANDIDATES as usize];
+
+impl<F: Fn(f32, f32) -> f32> PoissonDisk<F> {
+    pub fn new(seed: u32, min_spacing: f32, max_spacing: f32, spacing: F, cells: Arc<TileCache<DiskCell>>) -> PoissonDisk<F> {
+        PoissonDisk { seed, min_spacing, max_spacing: max_spacing.max(min_spacing), spacing, cells }
+    }
+
+    // every candidate in a cell
+    fn candidates(&self, cx: i32, cz: i32, seen: &mut Seen) -> [DiskCandidate; DISK_CANDIDATES as usize] {
+        *seen.entry((cx, cz)).or_insert_with(|| std::array::from_fn(|n| {
+            let n = n as u32;
+            let seed = self.seed.wrapping_add(n.wrapping_mul(0x9E3779B9));
+            let (ux, uz) = (cx as u32, cz as u32);
+            let x = (cx as f32 + khash_2float(ux, uz, seed)) * self.min_spacing;
+            let z = (cz as f32 + khash_2float(ux, uz, seed.wrapping_add(1))) * self.min_spacing;
+            let spacing = (self.spacing)(x, z).clamp(self.min_spacing, self.max_spacing);
+            let hash = khash(ux.wrapping_add(uz.wrapping_mul(0xA341316C)).wrapping_add(seed.wrapping_mul(0xF73DB187)));
+            DiskCandidate { x, z, spacing, priority: (hash, cx, cz, n) }
+        }))
+    }
+
+    // kept if no higher priority candidate thats too close is kept. the chain of those gets followed with a stack,
+    // priorities only go up along it so it always ends. cells already finished come out of the cache
+    fn kept(&self, key: DiskKey, seen: &mut Seen, kept: &mut HashMap<DiskKey, bool>) -> bool {
+        let reach = (self.max_spacing / self.min_spacing).ceil() as i32;
+        let mut stack = vec![key];
+        let mut waiting = Vec::new();
+        while let Some(&top) = stack.last() {
+            if kept.contains_key(&top) {
+                stack.pop();
+                continue;
+            }
+            let c = self.candidates(top.0, top.1, seen)[top.2 as usize];
+            let mut keep = true;
+            waiting.clear();
+            'search: for cz in top.1 - reach..=top.1 + reach {
+                for cx in top.0 - reach..=top.0 + reach {
+                    // cells out of reach of even the widest spacing
+                    let (dx, dz) = (cell_gap(c.x, cx, self.min_spacing), cell_gap(c.z, cz, self.min_spacing));
+                    if dx * dx + dz * dz >= self.max_spacing * self.max_spacing {
+                        continue;
+                    }
+                    for (n, o) in self.candidates(cx, cz, seen).iter().enumerate() {
+                        let n = n as u32;
+                        if o.priority <= c.priority {
+                            continue;
+                        }
+                        let d2 = (o.x - c.x) * (o.x - c.x) + (o.z - c.z) * (o.z - c.z);
+                        let s = c.spacing.max(o.spacing);
+                        if d2 >= s * s {
+                            continue;
+                        }
+                        let known = kept.get(&(cx, cz, n)).copied().or_else(|| self.cells.peek(cx, cz, |cell| cell[n as usize]));
+                        match known {
+                            Some(true) => {
+                                keep = false;
+                                break 'search;
+                            },
+                            Some(false) => {},
+                            None => waiting.push((cx, cz, n)),
+                        }
+                    }
+                }
+            }
+            // come back to this one once the ones its waiting on are known
+            if keep && !waiting.is_empty() {
+                stack.extend(waiting.iter());
+            } else {
+                kept.insert(top, keep);
+                stack.pop();
+            }
+        }
+        kept[&key]
+    }
+
+    // kept holds the answers for candidates in other cells found on the way, for when their cell comes up
+    fn cell(&self, cx: i32, cz: i32, seen: &mut Seen, kept: &mut HashMap<DiskKey, bool>) -> DiskCell {
+        self.cells.with(cx, cz, || std::array::from_fn(|n| self.kept((cx, cz, n as u32), seen, kept)), |cell| *cell)
+    }
+
+    // x, z and a hash for every point in x0..x1 z0..z1, always in the same order
+    pub fn points(&self, x0: f32, z0: f32, x1: f32, z1: f32) -> Vec<(f32, f32, u32)> {
+        let mut seen = HashMap::new();
+        let mut kept = HashMap::new();
+        let mut points = Vec::new();
+        for cz in (z0 / self.min_spacing).floor() as i32..=(z1 / self.min_spacing).floor() as i32 {
+            for cx in (x0 / self.min_spacing).floor() as i32..=(x1 / self.min_spacing).floor() as i32 {
+                let cell = self.cell(cx, cz, &mut seen, &mut kept);
+                for (n, c) in self.candidates(cx, cz, &mut seen).iter().enumerate() {
+                    if cell[n] && c.x >= x0 && c.x < x1 && c.z >= z0 && c.z < z1 {
+                        points.push((c.x, c.z, c.priority.0));
+                    }
+                }
+            }
+        }
+        points
+    }
+}
+
+// proper multi octave noise, each octave gets its own seed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fractal {
+    pub octaves: u32,
+    pub lacunarity: f32,
+    pub gain: f32,
+}
+
+pub fn octave_seed(seed: u32, octave: u32) -> u32 {
+    khash(seed.wrapping_add(octave.wrapping_mul(0x9E3779B9)))
+}
+
+impl Fractal {
+    pub fn new(octaves: u32, lacunarity: f32, gain: f32) -> Fractal {
+        Fractal { octaves, lacunarity, gain }
+    }
+
+    // sums f(octave value) weighted by amplitude and normalizes back to 0..1 given f maps 0..1 to 0..1
+    fn sum(&self, x: f32, y: f32, seed: u32, f: impl Fn(f32) -> f32) -> f32 {
+        let mut freq = 1.0;
+        let mut amp = 1.0;
+        let mut total = 0.0;
+        let mut norm = 0.0;
+        for i in 0..self.octaves.max(1) {
+            total += amp * f(grad2_isotropic(x * freq, y * freq, octave_seed(seed, i)));
+            norm += amp;
+            freq *= self.lacunarity;
+            amp *= self.gain;
+        }
+        total / norm
+    }
+
+    // 0..1, mean 0.5
+    pub fn fbm(&self, x: f32, y: f32, seed: u32) -> f32 {
+        self.sum(x, y, seed, |n| n)
+    }
+
+    // sharp crests where the noise crosses the middle, 0..1
+    pub fn ridged(&self, x: f32, y: f32, seed: u32) -> f32 {
+        self.sum(x, y, seed, |n| {
+            let r = 1.0 - (2.0 * n - 1.0).abs();
+            r * r
+        })
+    }
+
+    // puffy, sharp creases in the valleys, 0..1
+    pub fn billow(&self, x: f32, y: f32, seed: u32) -> f32 {
+        self.sum(x, y, seed, |n| (2.0 * n - 1.0).abs())
+    }
+
+    // 3d fbm of grad3, 0..1
+    pub fn fbm3(&self, x: f32, y: f32, z: f32, seed: u32) -> f32 {
+        let mut freq = 1.0;
+        let mut amp = 1.0;
+        let mut total = 0.0;
+        let mut norm = 0.0;
+        for i in 0..self.octaves.max(1) {
+            total += amp * grad3(x * freq, y * freq, z * freq, octave_seed(seed, i));
+            norm += amp;
+            freq *= self.lacunarity;
+            amp *= self.gain;
+        }
+        total / norm
+    }
+
+    // fbm sampled at a position pushed around by two more fbms, power is in input units
+    pub fn turbulence(&self, x: f32, y: f32, seed: u32, power: f32) -> f32 {
+        let dx = self.fbm(x, y, seed.wrapping_add(0x5BD1E995)) - 0.5;
+        let dy = self.fbm(x, y, seed.wrapping_add(0x1B873593)) - 0.5;
+        self.fbm(x + power * dx, y + power * dy, seed)
+    }
+}
+
 pub fn floorfrac(x: f32) -> (f32, f32) {
     let floor = x.floor();
     if x < 0.0 {
@@ -171,4 +633,186 @@ fn test_grad2() {
     grad2_isotropic(10.5, 10.5, 69);
     grad2_isotropic(10.7, 10.7, 69);
     grad2_isotropic(10.9, 10.9, 69);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+fn fractal_stats(f: impl Fn(f32, f32) -> f32) -> (f32, f32, f32, f32) {
+    let n = 200;
+    let mut samples = Vec::new();
+    for i in 0..n {
+        for j in 0..n {
+            samples.push(f(i as f32 * 0.173 - 17.0, j as f32 * 0.191 - 19.0));
+        }
+    }
+    let min = samples.iter().cloned().fold(f32::MAX, f32::min);
+    let max = samples.iter().cloned().fold(f32::MIN, f32::max);
+    let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+    let var = samples.iter().map(|s| (s - mean) * (s - mean)).sum::<f32>() / samples.len() as f32;
+    (min, max, mean, var.sqrt())
+}
+
+#[test]
+fn test_fractal_range() {
+    let fractal = Fractal::new(6, 2.0, 0.5);
+    let fns: [(&str, Box<dyn Fn(f32, f32) -> f32>); 4] = [
+        ("fbm", Box::new(move |x, y| fractal.fbm(x, y, 69))),
+        ("ridged", Box::new(move |x, y| fractal.ridged(x, y, 69))),
+        ("billow", Box::new(move |x, y| fractal.billow(x, y, 69))),
+        ("turbulence", Box::new(move |x, y| fractal.turbulence(x, y, 69, 2.0))),
+    ];
+    for (name, f) in fns.iter() {
+        let (min, max, _, sd) = fractal_stats(f);
+        assert!(min >= 0.0 && max <= 1.0, "{} out of range {} {}", name, min, max);
+        assert!(sd > 0.01, "{} is flat, sd {}", name, sd);
+    }
+}
+
+#[test]
+fn test_fractal_distribution() {
+    let fractal = Fractal::new(5, 2.0, 0.5);
+
+    // fbm is centered and doesnt spread to the extremes
+    let (_, _, mean, sd) = fractal_stats(|x, y| fractal.fbm(x, y, 123));
+    assert!((mean - 0.5).abs() < 0.05, "fbm mean {}", mean);
+    assert!(sd < 0.2, "fbm sd {}", sd);
+
+    // ridges live near the top, billows near the bottom
+    let (_, _, ridged_mean, _) = fractal_stats(|x, y| fractal.ridged(x, y, 123));
+    let (_, _, billow_mean, _) = fractal_stats(|x, y| fractal.billow(x, y, 123));
+    assert!(ridged_mean > 0.5, "ridged mean {}", ridged_mean);
+    assert!(billow_mean < 0.5, "billow mean {}", billow_mean);
+
+    // more gain means rougher noise, more octaves shouldnt change the mean much
+    let smooth = Fractal::new(5, 2.0, 0.3);
+    let rough = Fractal::new(5, 2.0, 0.8);
+    let step = |f: &Fractal| (0..1000).map(|i| {
+        let x = i as f32 * 0.37;
+        (f.fbm(x + 0.01, 3.0, 7) - f.fbm(x, 3.0, 7)).abs()
+    }).sum::<f32>();
+    assert!(step(&rough) > step(&smooth));
+}
+
+#[test]
+fn test_octave_seeds() {
+    let seeds: Vec<u32> = (0..8).map(|i| octave_seed(69, i)).collect();
+    for i in 0..seeds.len() {
+        for j in i+1..seeds.len() {
+            assert_ne!(seeds[i], seeds[j]);
+        }
+    }
+    let fractal = Fractal::new(4, 2.0, 0.5);
+    assert_eq!(fractal.fbm(1.3, -2.7, 5), fractal.fbm(1.3, -2.7, 5));
+    assert_ne!(fractal.fbm(1.3, -2.7, 5), fractal.fbm(1.3, -2.7, 6));
+}
+
+#[test]
+fn test_noise3() {
+    let mut min = f32::MAX;
+    let mut max = f32::MIN;
+    let mut sum = 0.0;
+    let n = 40;
+    for i in 0..n {
+        for j in 0..n {
+            for k in 0..n {
+                let (x, y, z) = (i as f32 * 0.37 - 7.0, j as f32 * 0.29 - 5.0, k as f32 * 0.31 - 6.0);
+                let g = grad3(x, y, z, 69);
+                let v = value3(x, y, z, 69);
+                assert!(v >= 0.0 && v <= 1.0);
+                min = min.min(g);
+                max = max.max(g);
+                sum += g;
+            }
+        }
+    }
+    assert!(min >= 0.0 && max <= 1.0, "grad3 out of range {} {}", min, max);
+    assert!(max - min > 0.3, "grad3 too flat {} {}", min, max);
+    let mean = sum / (n*n*n) as f32;
+    assert!((mean - 0.5).abs() < 0.05, "grad3 mean {}", mean);
+
+    // lattice points are exactly the middle for gradient noise, continuous across cell borders
+    assert_eq!(grad3(3.0, -2.0, 5.0, 1), 0.5);
+    assert!((grad3(0.9999, 0.5, 0.5, 1) - grad3(1.0001, 0.5, 0.5, 1)).abs() < 0.01);
+    assert!((grad3(-0.0001, 0.5, 0.5, 1) - grad3(0.0001, 0.5, 0.5, 1)).abs() < 0.01);
+}
+
+#[test]
+fn test_noise_derivatives() {
+    let eps = 0.001;
+    let check = |name: &str, f: &dyn Fn(f32, f32) -> (f32, Vec2), plain: &dyn Fn(f32, f32) -> f32| {
+        for i in 0..200 {
+            let x = i as f32 * 0.137 - 13.0;
+            let y = i as f32 * -0.071 + 4.0;
+            let (v, g) = f(x, y);
+            assert!((v - plain(x, y)).abs() < 1e-5, "{} value mismatch at {} {}", name, x, y);
+            let fdx = (plain(x + eps, y) - plain(x - eps, y)) / (2.0 * eps);
+            let fdy = (plain(x, y + eps) - plain(x, y - eps)) / (2.0 * eps);
+            assert!((g.x - fdx).abs() < 0.05 && (g.y - fdy).abs() < 0.05,
+                "{} derivative at {} {}: {:?} vs {} {}", name, x, y, g, fdx, fdy);
+        }
+    };
+    check("grad2", &|x, y| grad2_isotropic_d(x, y, 69), &|x, y| grad2_isotropic(x, y, 69));
+    check("fgrad2", &|x, y| fgrad2_isotropic_d(x, y, 69), &|x, y| fgrad2_isotropic(x, y, 69));
+    check("grad2_exp", &|x, y| grad2_isotropic_exp_d(x, y, 69), &|x, y| grad2_isotropic_exp(x, y, 69));
+    check("fgrad2_exp", &|x, y| fgrad2_isotropic_exp_d(x, y, 69), &|x, y| fgrad2_isotropic_exp(x, y, 69));
+}
+
+#[test]
+fn test_worley() {
+    for n in 0..400 {
+        let (x, y, z) = (n as f32 * 0.731 - 150.0, n as f32 * -0.377 + 20.0, (n % 17) as f32 * 1.13 - 9.0);
+        // the short search finds the same as a much wider one
+        for jitter in [0.0, 0.5, 1.0] {
+            assert_eq!(worley2_jitter(x, y, 3, jitter), worley2_reach(x, y, 3, jitter, 4));
+        }
+        assert_eq!(worley3(x, y, z, 3), worley3_reach(x, y, z, 3, 1.0, 4));
+
+        let w = worley2(x, y, 3);
+        assert!(w.f1 <= w.f2 && w.edge() >= 0.0);
+        assert!(((w.point - Vec2::new(x, y)).magnitude() - w.f1).abs() < 1e-5);
+        assert_eq!(w.point, worley_point2(w.cell.0, w.cell.1, 3, 1.0));
+        // its the same cell from anywhere near its point
+        let near = worley2(w.point.x + 0.01, w.point.y - 0.01, 3);
+        assert_eq!((near.cell, near.id), (w.cell, w.id));
+    }
+
+    // no jitter is a grid, f1 at a cell centre is 0 and f2 is 1
+    let grid = worley2_jitter(4.5, -7.5, 9, 0.0);
+    assert_eq!((grid.f1, grid.f2, grid.cell), (0.0, 1.0, (4, -8)));
+    let grid = worley3_jitter(0.5, 1.5, -0.5, 9, 0.0);
+    assert_eq!((grid.f1, grid.f2, grid.cell), (0.0, 1.0, (0, 1, -1)));
+}
+
+#[test]
+fn test_poisson_disk() {
+    // packed tight on the left, spread out on the right
+    let spacing = |x: f32, _z: f32| if x < 0.0 { 4.0 } else { 12.0 };
+    let disk = PoissonDisk::new(5, 4.0, 12.0, spacing, Arc::new(TileCache::new(4096)));
+    let all = disk.points(-100.0, -100.0, 100.0, 100.0);
+    for (i, a) in all.iter().enumerate() {
+        for b in all[i + 1..].iter() {
+            let s = spacing(a.0, a.1).max(spacing(b.0, b.1));
+            let d = ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+            assert!(d >= s, "{:?} {:?} only {} apart", a, b, d);
+        }
+    }
+    let dense = all.iter().filter(|p| p.0 < -20.0).count();
+    let sparse = all.iter().filter(|p| p.0 >= 20.0).count();
+    assert!(dense > 4 * sparse, "{} vs {}", dense, sparse);
+    // nothing big left empty where its packed
+    for n in 0..50 {
+        let (x, z) = (-90.0 + (n % 10) as f32 * 7.0, -80.0 + (n / 10) as f32 * 30.0);
+        assert!(all.iter().any(|p| (p.0 - x).powi(2) + (p.1 - z).powi(2) < 64.0), "gap at {} {}", x, z);
+    }
+
+    // chunk sized pieces asked for in any order see the same points as the big one,
+    // out of its cache or starting from nothing
+    let fresh = PoissonDisk::new(5, 4.0, 12.0, spacing, Arc::new(TileCache::new(4096)));
+    for (cx, cz) in [(3, 3), (-2, 0), (0, -1), (-6, 5), (0, 0), (5, -6)] {
+        let (x0, z0) = (cx as f32 * 16.0, cz as f32 * 16.0);
+        let inside: Vec<_> = all.iter().copied().filter(|p| p.0 >= x0 && p.0 < x0 + 16.0 && p.1 >= z0 && p.1 < z0 + 16.0).collect();
+        for small in [disk.points(x0, z0, x0 + 16.0, z0 + 16.0), fresh.points(x0, z0, x0 + 16.0, z0 + 16.0)] {
+            assert_eq!(small.len(), inside.len());
+            assert!(small.iter().all(|p| inside.contains(p)));
+        }
+    }
+}
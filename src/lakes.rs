@@ -0,0 +1,234 @@
+use crate::priority_queue::*;
+
+/*
+priority-flood depression filling (Barnes et al) on a coarse grid.
+every pit gets flooded outwards from itself, lowest first, until the water finds a way out: the sea or anywhere
+reach cells away from the pit. the highest it had to climb on the way is the lake level, the cells below that
+connected to the pit are the lake, and the lowest pass it climbed over is where it spills.
+that only depends on the ground round the pit, not on which tile asked, so a lake over a tile border comes out at
+one level on both sides. a lake wider than reach gets cut off at the edge of its flood, those are left dry.
+some lakes dont spill at all, they dry back to somewhere between their bottom and their rim and keep everything
+that drains into them (endorheic, roughly 1 in 5 like in erosion.md)
+*/
+
+const MIN_DEPTH: f32 = 1.0;
+
+const NEIGHBOURS: [(i32, i32); 8] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)];
+
+pub struct Basins {
+    // heights with the depressions filled to where they spill
+    pub filled: Vec<f32>,
+    // water surface for cells under a lake
+    pub lake: Vec<Option<f32>>,
+    // cells of closed lakes, nothing drains out of these
+    pub sink: Vec<bool>,
+    // across a filled depression the water heads for the spill, this is the next cell on the way
+    pub next: Vec<Option<usize>>,
+}
+
+fn neighbours(idx: usize, n: usize) -> impl Iterator<Item = usize> {
+    let (i, k) = ((idx % n) as i32, (idx / n) as i32);
+    NEIGHBOURS.iter().filter_map(move |(di, dk)| {
+        let (ni, nk) = (i + di, k + dk);
+        if ni < 0 || nk < 0 || ni >= n as i32 || nk >= n as i32 {
+            None
+        } else {
+            Some(nk as usize * n + ni as usize)
+        }
+    })
+}
+
+fn apart(a: usize, b: usize, n: usize) -> usize {
+    (a % n).abs_diff(b % n).max((a / n).abs_diff(b / n))
+}
+
+// one filled depression
+struct Depression {
+    level: f32,
+    cells: Vec<usize>,
+    // towards the spill, from a breadth first walk back from it
+    next: Vec<usize>,
+    // reached the edge of the flood, theres more of it than we looked at
+    cut: bool,
+}
+
+// flood out from a pit till the water gets away. stamp marks what this flood has seen with the pit,
+// and the lake and the walk back with the pit plus n*n and 2*n*n, so it never needs clearing
+fn flood(h: &[f32], n: usize, reach: usize, sea: f32, pit: usize, stamp: &mut [usize]) -> Option<Depression> {
+    let mut pq = PriorityQueue::new();
+    let mut level = h[pit];
+    let mut spill = pit;
+    stamp[pit] = pit;
+    pq.set(h[pit], pit);
+    while let Some(idx) = pq.remove_min() {
+        if h[idx] > level {
+            level = h[idx];
+            spill = idx;
+        }
+        if h[idx] < sea || apart(idx, pit, n) >= reach {
+            break;
+        }
+        for nb in neighbours(idx, n) {
+            if stamp[nb] != pit {
+                stamp[nb] = pit;
+                pq.set(h[nb], nb);
+            }
+        }
+    }
+    if spill == pit {
+        return None;
+    }
+
+    // everything under the level joined to the pit
+    let joined = pit + n*n;
+    let mut stack = vec![pit];
+    let mut cut = false;
+    stamp[pit] = joined;
+    while let Some(idx) = stack.pop() {
+        for nb in neighbours(idx, n) {
+            if h[nb] >= level || stamp[nb] == joined {
+                continue;
+            }
+            if apart(nb, pit, n) >= reach {
+                cut = true;
+                continue;
+            }
+            stamp[nb] = joined;
+            stack.push(nb);
+        }
+    }
+
+    // walked back from the spill so each cell knows its way out
+    let walked = pit + 2*n*n;
+    let mut cells = Vec::new();
+    let mut next = Vec::new();
+    let mut queue = vec![spill];
+    let mut head = 0;
+    while head < queue.len() {
+        let from = queue[head];
+        head += 1;
+        for nb in neighbours(from, n) {
+            if stamp[nb] == joined {
+                stamp[nb] = walked;
+                queue.push(nb);
+                cells.push(nb);
+                next.push(from);
+            }
+        }
+    }
+    Some(Depression { level, cells, next, cut })
+}
+
+// h is n*n. a cell is only right if its at least 2*reach from the edge, thats where all the pits that could flood it
+// are reach from the edge and got their whole flood.
+// roll gives a 0..1 number for a cell thats the same whichever tile asks, it decides which lakes are closed
+pub fn fill_basins(h: &[f32], n: usize, reach: usize, sea: f32, endorheic: f32, roll: impl Fn(usize) -> f32) -> Basins {
+    let mut filled = h.to_vec();
+    let mut lake = vec![None; n*n];
+    let mut sink = vec![false; n*n];
+    let mut next = vec![None; n*n];
+    let mut stamp = vec![usize::MAX; n*n];
+
+    for k in reach..n - reach {
+        for i in reach..n - reach {
+            let pit = k*n + i;
+            if h[pit] < sea || neighbours(pit, n).any(|nb| h[nb] < h[pit]) {
+                continue;
+            }
+            let Some(d) = flood(h, n, reach, sea, pit, &mut stamp) else { continue };
+            // a depression inside a bigger one is part of that lake, the highest level wins
+            if d.cells.iter().all(|idx| filled[*idx] >= d.level) {
+                continue;
+            }
+
+            let lowest = *d.cells.iter().min_by(|a, b| h[**a].partial_cmp(&h[**b]).unwrap()).unwrap();
+            let closed = roll(lowest) < endorheic;
+            let surface = if closed { h[lowest] + 0.5 * (d.level - h[lowest]) } else { d.level };
+            for (idx, to) in d.cells.iter().zip(d.next.iter()) {
+                filled[*idx] = d.level;
+                sink[*idx] = closed;
+                next[*idx] = if closed { None } else { Some(*to) };
+                lake[*idx] = if !d.cut && surface - h[*idx] >= MIN_DEPTH { Some(surface) } else { None };
+            }
+        }
+    }
+
+    Basins { filled, lake, sink, next }
+}
+
+#[test]
+fn test_fill_basins() {
+    // a bowl with a notch in its rim and a channel away, and a second bowl with a sealed rim, in a gentle slope
+    let n = 32;
+    let mut h: Vec<f32> = (0..n*n).map(|idx| 10.0 + 0.25 * (idx % n) as f32).collect();
+    for k in 11..16 {
+        for i in 11..16 {
+            h[k*n + i] = 2.0;
+        }
+    }
+    h[13*n + 16] = 6.0;
+    for k in 0..14 {
+        h[k*n + 17] = 5.5 - 0.25 * (13 - k) as f32;
+    }
+    for k in 18..22 {
+        for i in 18..22 {
+            h[k*n + i] = 4.0;
+        }
+    }
+
+    let open = fill_basins(&h, n, 5, 0.0, 0.0, |_| 1.0);
+    // the low bowl fills to the gap in its rim
+    assert_eq!(open.lake[13*n + 13], Some(6.0));
+    assert_eq!(open.lake[13*n + 16], None);
+    assert_eq!(open.lake[20*n + 20], Some(14.25));
+    assert_eq!(open.lake[0], None);
+    assert!(!open.sink.iter().any(|s| *s));
+    // across the lake everything finds its way to the notch
+    let mut idx = 11*n + 11;
+    while let Some(to) = open.next[idx] {
+        assert!(neighbours(idx, n).any(|nb| nb == to));
+        idx = to;
+    }
+    assert_eq!(idx, 13*n + 16);
+    // every cell far enough in thats not under a lake has somewhere lower to go
+    for k in 10..22 {
+        for i in 10..22 {
+            let idx = k*n + i;
+            assert!(open.next[idx].is_some() || neighbours(idx, n).any(|nb| open.filled[nb] < open.filled[idx]), "cell {} {} is a pit", i, k);
+        }
+    }
+
+    let closed = fill_basins(&h, n, 5, 0.0, 1.0, |_| 0.0);
+    assert!(closed.sink[20*n + 20]);
+    assert_eq!(closed.lake[20*n + 20], Some(9.125));
+    assert_eq!(closed.next[20*n + 20], None);
+}
+
+#[test]
+fn test_basins_dont_depend_on_the_grid() {
+    // the same bumpy ground seen through two grids offset from each other
+    let ground = |x: i32, z: i32| {
+        let (x, z) = (x as f32, z as f32);
+        20.0 * (0.31 * x).sin() * (0.23 * z).cos() + 9.0 * (0.11 * x + 0.17 * z).sin() + 0.05 * x
+    };
+    let (n, reach) = (60, 6);
+    let grid = |x0: i32, z0: i32| {
+        let h: Vec<f32> = (0..n*n).map(|idx| ground(x0 + (idx % n) as i32, z0 + (idx / n) as i32)).collect();
+        fill_basins(&h, n, reach, -100.0, 0.0, |_| 1.0)
+    };
+    let (a, b) = (grid(0, 0), grid(17, -9));
+    let r = 2*reach as i32;
+    let mut lakes = 0;
+    for z in r..n as i32 - r - 9 {
+        for x in r + 17..n as i32 - r {
+            let ia = z as usize*n + x as usize;
+            let ib = (z + 9) as usize*n + (x - 17) as usize;
+            assert_eq!(a.lake[ia], b.lake[ib], "lake at {} {}", x, z);
+            assert_eq!(a.filled[ia], b.filled[ib], "fill at {} {}", x, z);
+            let global = |next: Option<usize>, x0: i32, z0: i32| next.map(|idx| (x0 + (idx % n) as i32, z0 + (idx / n) as i32));
+            assert_eq!(global(a.next[ia], 0, 0), global(b.next[ib], 17, -9));
+            lakes += a.lake[ia].is_some() as i32;
+        }
+    }
+    assert!(lakes > 0);
+}
@@ -11,6 +11,22 @@ mod kimg;
 mod game;
 mod world_gen2;
 mod as_bytes;
+mod gen_combinators;
+mod gen_graph;
+mod column_cache;
+mod tile_cache;
+mod hydraulic;
+mod rivers;
+mod lakes;
+mod decorate;
+mod caves;
+mod strata;
+mod climate;
+mod classify;
+mod regions;
+mod tectonics;
+mod glaciers;
+mod mesa;
 
 use kimg::*;
 use glow::*;
@@ -32,7 +48,7 @@ use game::*;
 
 fn main() {
     let event_loop = glutin::event_loop::EventLoop::new();
-    let mut game = Game::new(&event_loop);
+    let mut game = Game::new(&event_loop, "WorldGen", 69);
     let mut held_keys: HashSet<glutin::event::VirtualKeyCode> = HashSet::new();
     let mut dt = 1.0f64 / 60.0f64;
 
@@ -0,0 +1,237 @@
+use std::sync::Arc;
+use crate::chunk::*;
+use crate::krand::*;
+use crate::kmath::*;
+use crate::world_gen::*;
+use crate::column_cache::*;
+use crate::tile_cache::*;
+use crate::strata::*;
+
+/*
+tablelands, buttes and hoodoos like erosion.md wants, without the anti-buttes you get from just remapping noise.
+a domain warped low frequency field is thresholded into tiers on a coarse CELL grid, then a neighbourhood rule
+runs over it a few times: a cell with 3 or more sides higher gets filled up, a cell naked on 3 or more sides
+gets worn down a tier unless its capped with hard rock. so notches fill in and the plateaus get clean escarpments,
+spurs wear back from the tip, and where a cap is left standing on its own you get a butte or a hoodoo.
+the rule only looks one cell out per pass, so a tile with an apron of ITERATIONS cells works out its core exactly.
+heights are the tiers interpolated and sharpened into cliffs, the flats get worley cracks, and the rock is banded flat by elevation
+*/
+
+const CELL: f32 = 4.0;
+const CORE: i32 = 64;
+const ITERATIONS: usize = 6;
+const APRON: i32 = ITERATIONS as i32 + 1;
+const MAX_TILES: usize = 64;
+// field values where each tier starts
+const TIERS: [f32; 3] = [0.42, 0.52, 0.6];
+
+#[derive(Clone)]
+pub struct GenMesa {
+    seed: u32,
+    tier_height: f32,
+    // chance a cell is capped and doesnt wear down
+    caprock: f32,
+    tiles: Arc<TileCache<Vec<u8>>>,
+}
+
+// one pass of the rule over an n*n grid, the edge cells are left alone
+fn settle(tiers: &[u8], n: usize, capped: impl Fn(usize) -> bool) -> Vec<u8> {
+    let mut out = tiers.to_vec();
+    for k in 1..n - 1 {
+        for i in 1..n - 1 {
+            let idx = k*n + i;
+            let t = tiers[idx];
+            let sides = [tiers[idx - 1], tiers[idx + 1], tiers[idx - n], tiers[idx + n]];
+            let higher = sides.iter().filter(|s| **s > t).count();
+            let lower = sides.iter().filter(|s| **s < t).count();
+            if higher >= 3 {
+                out[idx] = t + 1;
+            } else if lower >= 3 && !capped(idx) {
+                out[idx] = t - 1;
+            }
+        }
+    }
+    out
+}
+
+impl GenMesa {
+    pub fn new(seed: u32) -> GenMesa {
+        GenMesa { seed, tier_height: 24.0, caprock: 0.15, tiles: Arc::new(TileCache::new(MAX_TILES)) }
+    }
+
+    fn field(&self, x: f32, z: f32) -> f32 {
+        Fractal::new(4, 2.0, 0.5).turbulence(0.0025 * x, 0.0025 * z, self.seed, 0.8)
+    }
+
+    // n*n cells from cx0 cz0, settled. only the ones at least ITERATIONS in from the edge are right
+    fn grid(&self, cx0: i32, cz0: i32, n: usize) -> Vec<u8> {
+        let mut tiers = Vec::with_capacity(n*n);
+        for k in 0..n {
+            for i in 0..n {
+                let (x, z) = ((cx0 + i as i32) as f32 * CELL, (cz0 + k as i32) as f32 * CELL);
+                let f = self.field(x, z);
+                tiers.push(TIERS.iter().filter(|t| f > **t).count() as u8);
+            }
+        }
+        let capped = |idx: usize| {
+            let (cx, cz) = (cx0 + (idx % n) as i32, cz0 + (idx / n) as i32);
+            khash_2float(cx as u32, cz as u32, self.seed.wrapping_add(0x3C6EF372)) < self.caprock
+        };
+        for _ in 0..ITERATIONS {
+            tiers = settle(&tiers, n, capped);
+        }
+        tiers
+    }
+
+    fn tier(&self, cx: i32, cz: i32) -> u8 {
+        let (tx, tz) = (cx.div_euclid(CORE), cz.div_euclid(CORE));
+        let n = (CORE + 2*APRON) as usize;
+        let (i, k) = ((cx.rem_euclid(CORE) + APRON) as usize, (cz.rem_euclid(CORE) + APRON) as usize);
+        self.tiles.with(tx, tz, || self.grid(tx*CORE - APRON, tz*CORE - APRON, n), |tile| tile[k*n + i])
+    }
+}
+
+impl LevelGenerator for GenMesa {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        // wobble the lookup so the cliffs dont follow the grid
+        let wx = x + 6.0 * (fgrad2_isotropic(0.05 * x, 0.05 * z, self.seed.wrapping_add(1)) - 0.5);
+        let wz = z + 6.0 * (fgrad2_isotropic(0.05 * x, 0.05 * z, self.seed.wrapping_add(2)) - 0.5);
+        let (gx, gz) = (wx / CELL - 0.5, wz / CELL - 0.5);
+        let (cx, cz) = (gx.floor() as i32, gz.floor() as i32);
+        let (u, v) = (gx - cx as f32, gz - cz as f32);
+        let t = lerp(
+            lerp(self.tier(cx, cz) as f32, self.tier(cx + 1, cz) as f32, u),
+            lerp(self.tier(cx, cz + 1) as f32, self.tier(cx + 1, cz + 1) as f32, u),
+            v,
+        );
+        // the bit between two tiers is nearly all cliff
+        let sharp = t.floor() + saturate(t.fract(), 0.35, 0.65);
+        let ground = 3.0 * (fgrad2_isotropic(0.02 * x, 0.02 * z, self.seed.wrapping_add(3)) - 0.5);
+        // cracked mud out on the flats
+        let cracks = worley2_jitter(x / 6.0, z / 6.0, self.seed.wrapping_add(4), 0.7);
+        let crack = if t < 0.2 && cracks.edge() < 0.1 { 2.0 } else { 0.0 };
+        SEA_LEVEL_F32 + 6.0 + self.tier_height * sharp + ground - crack
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+
+    // bare rock all the way up so the cliffs show the bands, sand on top
+    fn surface_block(&self, _x: i32, y: i32, _z: i32, height: i32) -> Block {
+        match y - height {
+            dh if dh > 0 => standard_block(y, height),
+            0 => Block::Sand,
+            _ => Block::Stone,
+        }
+    }
+
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    fn params(&self) -> Vec<GenParam> {
+        vec![
+            GenParam::new("tier_height", self.tier_height, 4.0, 80.0),
+            GenParam::new("caprock", self.caprock, 0.0, 1.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "tier_height" => self.tier_height = value,
+            // changes the tiles themselves
+            "caprock" => {
+                self.caprock = value;
+                self.tiles = Arc::new(TileCache::new(MAX_TILES));
+            },
+            _ => {},
+        }
+    }
+
+    // flat bands, red and yellow like a canyon wall
+    fn geology(&self) -> Geology {
+        Geology {
+            seed: self.seed,
+            host: Block::Stone,
+            strata: vec![
+                Stratum { block: Block::RedRock, thickness: 6.0 },
+                Stratum { block: Block::Sandstone, thickness: 3.0 },
+                Stratum { block: Block::RedRock, thickness: 4.0 },
+                Stratum { block: Block::Limestone, thickness: 2.0 },
+                Stratum { block: Block::Sandstone, thickness: 5.0 },
+            ],
+            follow: 0.0,
+            ores: Geology::standard(self.seed).ores,
+        }
+    }
+}
+
+#[test]
+fn test_mesa_rule() {
+    // a plateau along the top with a spur hanging off it, capped part way down, and a notch in the plateau edge
+    let n = 9;
+    let mut tiers = vec![0u8; n*n];
+    for i in 0..n {
+        tiers[i] = 1;
+        tiers[n + i] = 1;
+    }
+    tiers[n + 6] = 0;
+    for k in 2..7 {
+        tiers[k*n + 2] = 1;
+    }
+    let capped = |idx: usize| idx == 4*n + 2;
+    for _ in 0..ITERATIONS {
+        tiers = settle(&tiers, n, capped);
+    }
+    assert_eq!(tiers[n + 6], 1);
+    // worn back to the cap and no further
+    assert_eq!((2..7).map(|k| tiers[k*n + 2]).collect::<Vec<_>>(), vec![1, 1, 1, 0, 0]);
+
+    // uncapped its gone, capped its a hoodoo
+    let mut lone = vec![0u8; n*n];
+    lone[4*n + 4] = 1;
+    assert!(settle(&lone, n, |_| false).iter().all(|t| *t == 0));
+    assert_eq!(settle(&lone, n, |idx| idx == 4*n + 4), lone);
+}
+
+#[test]
+fn test_mesa_tiles() {
+    let gen = GenMesa::new(11);
+    // tiles agree with settling one big grid, across a tile corner
+    let n = 2*CORE as usize;
+    let big = gen.grid(CORE / 2 - APRON, CORE / 2 - APRON, n + 2*APRON as usize);
+    let m = n + 2*APRON as usize;
+    let mut seen = [false; TIERS.len() + 1];
+    for k in 0..n {
+        for i in 0..n {
+            let t = gen.tier(CORE / 2 + i as i32, CORE / 2 + k as i32);
+            assert_eq!(t, big[(k + APRON as usize)*m + i + APRON as usize], "cell {} {}", i, k);
+            seen[t as usize] = true;
+        }
+    }
+    assert!(seen.iter().filter(|s| **s).count() >= 2);
+
+    // theres cliffs, and they show more than one band of rock
+    let (mut cliff, mut bands) = (false, Vec::new());
+    for x in 0..2*CORE*CELL as i32 {
+        let (a, b) = (gen.height(x as f32, 100.0), gen.height(x as f32 + 1.0, 100.0));
+        if (b - a).abs() > 8.0 && !cliff {
+            cliff = true;
+            let (ox, oz) = (x.div_euclid(16), 100 / 16);
+            for oy in 0..8 {
+                for block in chunk_blocks(&gen, ox, oy, oz) {
+                    if block.is_opaque() && !bands.contains(&block) {
+                        bands.push(block);
+                    }
+                }
+            }
+        }
+    }
+    assert!(cliff);
+    assert!(bands.contains(&Block::RedRock) && bands.contains(&Block::Sandstone), "{:?}", bands);
+}
@@ -30,10 +30,10 @@ impl<P: PartialOrd + Copy + std::fmt::Debug, V: Copy + Eq + std::hash::Hash+ std
 
     fn upheap(&mut self, mut i: usize) {
         while i > 0 {
-            let parent = self.elems[i/2];
+            let parent = self.elems[(i - 1)/2];
             if parent.0 > self.elems[i].0 {
-                self.swap(i, i/2);
-                i /= 2;
+                self.swap(i, (i - 1)/2);
+                i = (i - 1)/2;
             } else {
                 return
             }
@@ -124,10 +124,14 @@ impl<P: PartialOrd + Copy + std::fmt::Debug, V: Copy + Eq + std::hash::Hash+ std
 
     pub fn remove(&mut self, value: V) {
         if let Some(idx) = self.value_map.get(&value).map(|u| *u) {
+            let last = self.len() - 1;
+            self.swap(idx, last);
+            self.elems.truncate(last);
             self.value_map.remove(&value);
-            self.swap(idx, self.len());
-            self.elems.truncate(self.elems.len()-1);
-            self.downheap(idx);
+            if idx < last {
+                self.downheap(idx);
+                self.upheap(idx);
+            }
         };
     }
 }
@@ -211,4 +215,24 @@ fn pq_test() {
     assert_eq!(pq.remove_min().unwrap(), "asdasd");
     assert_eq!(pq.remove_min().unwrap(), "hhh");
     assert_eq!(pq.remove_min(), None);
-}
\ No newline at end of file
+}
+#[test]
+fn pq_test_many() {
+    let mut pq = PriorityQueue::new();
+    for i in 0..500u32 {
+        pq.set(crate::krand::khash(i) % 1000, i);
+    }
+    for i in 0..100u32 {
+        pq.remove(i * 3);
+    }
+    let mut last = 0;
+    let mut n = 0;
+    while let Some(v) = pq.remove_min() {
+        let p = crate::krand::khash(v) % 1000;
+        assert!(p >= last);
+        assert!(v % 3 != 0 || v >= 300, "{} was removed", v);
+        last = p;
+        n += 1;
+    }
+    assert_eq!(n, 400);
+}
@@ -0,0 +1,266 @@
+use crate::chunk::*;
+use crate::krand::*;
+use crate::kmath::*;
+use crate::world_gen::*;
+use crate::column_cache::*;
+use crate::strata::*;
+use crate::climate::*;
+use crate::settings::*;
+
+/*
+one world, several generators. the plane is cut into jittered voronoi cells, each cell hashed to one of the
+generators, and the lookup point is domain warped first so the borders wander instead of being straight lines.
+within border of the bisector between two cells both generators count, weighted by how much further the other
+site is, so heights meet smoothly. surface blocks cant be averaged so near a border each column picks one
+generator at random by the same weights, which dithers one look into the other.
+everything is a function of position, so like the rest a chunk doesnt need its neighbours
+*/
+
+// cells either side to look at for sites, warped points can land a fair way from their own cell
+const REACH: i32 = 2;
+
+#[derive(Clone)]
+pub struct Regions {
+    gens: Vec<SharedGen>,
+    seed: u32,
+    pub cell: f32,
+    pub border: f32,
+    pub warp: f32,
+}
+
+impl Regions {
+    pub fn new(gens: Vec<SharedGen>, seed: u32) -> Regions {
+        Regions { gens, seed, cell: 768.0, border: 96.0, warp: 160.0 }
+    }
+
+    // where cell cx cz has its site and which generator it belongs to
+    fn site(&self, cx: i32, cz: i32) -> (f32, f32, usize) {
+        let p = worley_point2(cx, cz, self.seed, 1.0) * self.cell;
+        let n = ((khash_2float(cx as u32, cz as u32, self.seed ^ 2) * self.gens.len() as f32) as usize).min(self.gens.len() - 1);
+        (p.x, p.y, n)
+    }
+
+    // (generator, weight) for everything with a say here, weights add to 1, in generator order
+    pub fn weights(&self, x: f32, z: f32) -> Vec<(usize, f32)> {
+        let f = 1.0 / self.cell;
+        let px = x + self.warp * (fgrad2_isotropic(f * x, f * z, self.seed.wrapping_add(3)) - 0.5);
+        let pz = z + self.warp * (fgrad2_isotropic(f * x, f * z, self.seed.wrapping_add(4)) - 0.5);
+        let (cx, cz) = ((px / self.cell).floor() as i32, (pz / self.cell).floor() as i32);
+
+        let mut sites = Vec::with_capacity(((2*REACH + 1) * (2*REACH + 1)) as usize);
+        for dz in -REACH..=REACH {
+            for dx in -REACH..=REACH {
+                let (sx, sz, n) = self.site(cx + dx, cz + dz);
+                sites.push((((sx - px).powi(2) + (sz - pz).powi(2)).sqrt(), n));
+            }
+        }
+        let nearest = sites.iter().map(|(d, _)| *d).fold(f32::INFINITY, f32::min);
+
+        let mut totals = vec![0.0; self.gens.len()];
+        for (d, n) in sites {
+            totals[n] += smoothstep(1.0 - (d - nearest) / self.border);
+        }
+        let sum: f32 = totals.iter().sum();
+        totals.iter().enumerate().filter(|(_, w)| **w > 0.0).map(|(n, w)| (n, w / sum)).collect()
+    }
+
+    fn dominant(&self, x: f32, z: f32) -> usize {
+        self.weights(x, z).into_iter().fold((0, 0.0), |best, w| if w.1 > best.1 { w } else { best }).0
+    }
+
+    // whose blocks go in column x z, picked at random by the weights so the borders dither
+    fn pick(&self, x: i32, z: i32) -> usize {
+        let weights = self.weights(x as f32, z as f32);
+        let mut r = khash_2float(x as u32, z as u32, self.seed.wrapping_add(5));
+        for (n, w) in weights.iter() {
+            if r < *w {
+                return *n;
+            }
+            r -= w;
+        }
+        // rounding left r just over, its the last one
+        weights[weights.len() - 1].0
+    }
+}
+
+impl LevelGenerator for Regions {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        self.weights(x, z).iter().map(|(n, w)| w * self.gens[*n].height(x, z)).sum()
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+
+    // dithered across the border
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        self.gens[self.pick(x, z)].surface_block(x, y, z, height)
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        self.gens[self.pick(x, z)].column_blocks(x, z, oy, height)
+    }
+
+    // then the generators own, once per name, the first one to have it says what it is now
+    fn params(&self) -> Vec<GenParam> {
+        let mut params = vec![
+            GenParam::new("cell", self.cell, 128.0, 4096.0),
+            GenParam::new("border", self.border, 1.0, 512.0),
+            GenParam::new("warp", self.warp, 0.0, 1024.0),
+        ];
+        for gen in self.gens.iter() {
+            for p in gen.params() {
+                if !params.iter().any(|q| q.name == p.name) {
+                    params.push(p);
+                }
+            }
+        }
+        params
+    }
+
+    // anything else goes to every generator that has it. they only take it while these are the only handles on them,
+    // which they are straight out of new
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "cell" => self.cell = value,
+            "border" => self.border = value,
+            "warp" => self.warp = value,
+            _ => {
+                for gen in self.gens.iter_mut() {
+                    gen.set_param(name, value);
+                }
+            },
+        }
+    }
+
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    // any of them could be the one picked, so they all have to agree
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        let first = self.gens[0].uniform_block(oy, surface);
+        if self.gens[1..].iter().all(|g| g.uniform_block(oy, surface) == first) { first } else { None }
+    }
+
+    // geology doesnt get told where it is, so the rock is the first generators everywhere
+    fn geology(&self) -> Geology {
+        self.gens[0].geology()
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Option<Climate> {
+        self.gens[self.dominant(x, z)].climate(x, z)
+    }
+}
+
+// flat ground at some height with its own top block
+#[cfg(test)]
+struct Level(f32, Block);
+
+#[cfg(test)]
+impl LevelGenerator for Level {
+    fn height(&self, _x: f32, _z: f32) -> f32 {
+        self.0
+    }
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+    fn surface_block(&self, _x: i32, y: i32, _z: i32, height: i32) -> Block {
+        if y == height { self.1 } else { standard_block(y, height) }
+    }
+}
+
+#[test]
+fn test_regions() {
+    use std::sync::Arc;
+    let levels = [(10.0, Block::Grass), (50.0, Block::Sand), (90.0, Block::Snow)];
+    let gen = Regions::new(levels.iter().map(|(h, b)| Arc::new(Level(*h, *b)) as SharedGen).collect(), 7);
+
+    // every region turns up somewhere, and the heights in between never jump
+    let mut pure = [false; 3];
+    let mut dithered = false;
+    for row in 0..4 {
+        let z = row as f32 * 1000.0;
+        let mut last = gen.height(-4000.0, z);
+        for x in -4000..4000 {
+            let h = gen.height(x as f32, z);
+            assert!((h - last).abs() < 3.0, "jump {} -> {} at {}", last, h, x);
+            last = h;
+            let weights = gen.weights(x as f32, z);
+            assert!((weights.iter().map(|w| w.1).sum::<f32>() - 1.0).abs() < 1e-4);
+            if let [(n, _)] = weights[..] {
+                let (level, block) = levels[n];
+                pure[n] = true;
+                assert_eq!(h, level);
+                assert_eq!(gen.surface_block(x, level as i32, z as i32, level as i32), block);
+            }
+            dithered |= weights.len() > 1;
+        }
+    }
+    assert_eq!(pure, [true; 3]);
+    assert!(dithered);
+
+    // the column cache gets the same chunks as generating them directly
+    let cache = ColumnCache::new();
+    for oz in 0..3 {
+        for oy in 0..4 {
+            match cached_blocks(&gen, &cache, 0, 20, oy, oz) {
+                (blocks, None) => assert_eq!(blocks, chunk_blocks(&gen, 20, oy, oz)),
+                (blocks, Some(b)) => assert!(blocks.iter().all(|x| *x == b)),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_region_looks() {
+    // beaches next to worldgen, the columns deep in each should look like that generator and not like each other
+    use std::sync::Arc;
+    use crate::world_gen2::WorldGen;
+    let gens: Vec<SharedGen> = vec![Arc::new(GenBeach::new(2)), Arc::new(WorldGen::new(2))];
+    let gen = Regions::new(gens.clone(), 2);
+    let mut tops = [Vec::new(), Vec::new()];
+    let mut high_sand = [false; 2];
+    for z in (-6000..6000).step_by(61) {
+        for x in (-6000..6000).step_by(59) {
+            if let [(n, _)] = gen.weights(x as f32, z as f32)[..] {
+                let height = gen.height(x as f32, z as f32) as i32;
+                let oy = height.div_euclid(S as i32);
+                let column = gen.column_blocks(x, z, oy, height);
+                assert_eq!(column, gens[n].column_blocks(x, z, oy, height));
+                let top = column[height.rem_euclid(S as i32) as usize];
+                high_sand[n] |= top == Block::Sand && height > 4;
+                if !tops[n].contains(&top) {
+                    tops[n].push(top);
+                }
+            }
+        }
+    }
+    // beaches have sand up high where worldgen would have grass, worldgen has bare rock where its steep
+    assert_eq!(high_sand, [true, false]);
+    assert!(tops[1].contains(&Block::Stone), "{:?}", tops);
+    assert_ne!(tops[0], tops[1]);
+}
+
+#[test]
+fn test_region_params() {
+    // the generators sliders show up and reach them
+    use std::sync::Arc;
+    use crate::world_gen2::WorldGen;
+    let mut gen = Regions::new(vec![Arc::new(GenBeach::new(2)), Arc::new(WorldGen::new(2))], 2);
+    gen.set_param("beach_peak", 0.6);
+    gen.set_param("ocean_lo", 0.2);
+    gen.set_param("border", 50.0);
+    let params = gen.params();
+    for (name, value) in [("beach_peak", 0.6), ("ocean_lo", 0.2), ("border", 50.0)] {
+        assert!(params.iter().any(|p| p.name == name && p.value == value), "{}", name);
+    }
+    let mut world = WorldGen::new(2);
+    world.set_param("ocean_lo", 0.2);
+    let (x, z) = (0..).map(|i| (i as f32 * 97.0, 0.0)).find(|(x, z)| gen.weights(*x, *z)[..] == [(1, 1.0)]).unwrap();
+    assert_eq!(gen.height(x, z), world.height(x, z));
+}
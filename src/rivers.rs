@@ -0,0 +1,769 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use crate::chunk::*;
+use crate::krand::*;
+use crate::kmath::*;
+use crate::settings::*;
+use crate::world_gen::*;
+use crate::column_cache::*;
+use crate::tile_cache::*;
+use crate::strata::*;
+use crate::climate::*;
+use crate::lakes::*;
+
+/*
+rivers from a coarse drainage map like in erosion.md.
+the world is a grid of CELL sized cells, each with a jittered node. every node drains to its steepest lower neighbour
+and flow accumulation counts how many cells drain through it. that gets worked out per tile with an apron around it,
+and a cells numbers only ever come from the tile that owns it so neighbouring tiles agree.
+a tile only counts its own cells, whatever drains in over its edge gets followed back upstream through the
+neighbouring tiles and added on, so a river keeps its size crossing a border.
+a river segment runs from a node to the one it drains to, owned by the upstream cell.
+depressions get priority-flood filled first so rivers run through lakes and out their outlets.
+each pit is flooded out to REACH around itself, so a lake comes out the same from whichever tile and the apron
+only has to be big enough for that.
+carving happens per column against the segments and lakes nearby so chunks dont need each other,
+the segments get found once per chunk and cut into the inner generators chunk
+*/
+
+const CELL: f32 = 16.0;
+const CORE: i32 = 64;
+// how far a pit gets flooded looking for a way out, lakes are at most this far across from their lowest cell
+const REACH: i32 = 24;
+// enough for the pits that could flood the ring round the core to get their whole flood
+const APRON: i32 = 2*REACH + 1;
+const N: usize = (CORE + 2*APRON) as usize;
+const MAX_TILES: usize = 16;
+// the upstream search wants the tiles round the ones in use too
+const MAX_LOCAL_TILES: usize = 64;
+const MAX_HEIGHT_TILES: usize = 128;
+// past this a river is as wide as it gets for any of the sliders
+const MAX_ACC: f32 = 8192.0;
+const MAX_HOPS: usize = 4;
+const MAX_WIDTH: f32 = 16.0;
+// how far out the valley walls get blended back into the terrain
+const VALLEY: f32 = 24.0;
+
+const NEIGHBOURS: [(i32, i32); 8] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)];
+
+// the core part of an apron sized grid
+fn core<T: Copy>(v: &[T]) -> Vec<T> {
+    let mut out = Vec::with_capacity((CORE*CORE) as usize);
+    for k in APRON..APRON + CORE {
+        for i in APRON..APRON + CORE {
+            out.push(v[k as usize*N + i as usize]);
+        }
+    }
+    out
+}
+
+// core cells of one tile, the accumulation only counting what drains inside the core
+struct LocalTile {
+    // filled, so its the water level under lakes
+    height: Vec<f32>,
+    acc: Vec<f32>,
+    peak: Vec<f32>,
+    // index into NEIGHBOURS, None at the grid edge, in the sea and in closed lakes
+    down: Vec<Option<u8>>,
+    lake: Vec<Option<f32>>,
+    // cells just outside the core that drain into it,
+    // and for each core cell the ones whose water passes through it on the way down
+    inlets: Vec<(i32, i32)>,
+    through: Vec<Vec<u16>>,
+}
+
+// a tiles accumulation with whatever flows in from the neighbours added on
+struct FlowTile {
+    acc: Vec<f32>,
+    peak: Vec<f32>,
+}
+
+// whats near a chunk
+struct Nearby {
+    segments: Vec<Segment>,
+    // node and water level of lake cells
+    lakes: Vec<(Vec2, f32)>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+    a: Vec2,
+    b: Vec2,
+    ha: f32,
+    hb: f32,
+    wa: f32,
+    wb: f32,
+}
+
+// what the drainage map knows about one cell
+#[derive(Clone, Copy, Debug)]
+pub struct DrainCell {
+    pub height: f32,
+    // how many cells drain through here
+    pub acc: f32,
+    // highest ground that drains through here
+    pub peak: f32,
+    pub down: Option<(i32, i32)>,
+    pub lake: Option<f32>,
+}
+
+// the drainage on its own so other things can follow water downhill too.
+// like a ClimateMap it goes with one generator, its tiles are that generators heights
+#[derive(Clone)]
+pub struct Drainage {
+    seed: u32,
+    cell: f32,
+    // fraction of lakes with no outlet
+    endorheic: f32,
+    // where acc stops counting, once whatever uses it cant get any bigger
+    max_acc: f32,
+    heights: Arc<TileCache<Vec<f32>>>,
+    tiles: Arc<TileCache<LocalTile>>,
+    flow: Arc<TileCache<FlowTile>>,
+}
+
+impl Drainage {
+    pub fn new(seed: u32, cell: f32, endorheic: f32, max_acc: f32) -> Drainage {
+        Drainage {
+            seed,
+            cell,
+            endorheic,
+            max_acc,
+            heights: Arc::new(TileCache::new(MAX_HEIGHT_TILES)),
+            tiles: Arc::new(TileCache::new(MAX_LOCAL_TILES)),
+            flow: Arc::new(TileCache::new(MAX_TILES)),
+        }
+    }
+
+    pub fn node(&self, cx: i32, cz: i32) -> Vec2 {
+        let jx = khash_2float(cx as u32, cz as u32, self.seed) - 0.5;
+        let jz = khash_2float(cx as u32, cz as u32, self.seed ^ 0x9E3779B9) - 0.5;
+        Vec2::new((cx as f32 + 0.5 + 0.6 * jx) * self.cell, (cz as f32 + 0.5 + 0.6 * jz) * self.cell)
+    }
+
+    // heights at the nodes of one tiles core
+    fn core_heights<G: LevelGenerator + ?Sized>(&self, gen: &G, tx: i32, tz: i32) -> Vec<f32> {
+        (0..CORE*CORE).map(|idx| {
+            let p = self.node(tx * CORE + idx % CORE, tz * CORE + idx / CORE);
+            gen.height(p.x, p.y)
+        }).collect()
+    }
+
+    fn local_tile<G: LevelGenerator + ?Sized>(&self, gen: &G, tx: i32, tz: i32) -> LocalTile {
+        let (cx0, cz0) = (tx * CORE - APRON, tz * CORE - APRON);
+        let pos: Vec<Vec2> = (0..N*N).map(|idx| self.node(cx0 + (idx % N) as i32, cz0 + (idx / N) as i32)).collect();
+        // the apron is most of the grid, its heights get shared with the neighbours rather than sampled again
+        let mut h = vec![0.0; N*N];
+        let last = N as i32 - 1;
+        for bz in cz0.div_euclid(CORE)..=(cz0 + last).div_euclid(CORE) {
+            for bx in cx0.div_euclid(CORE)..=(cx0 + last).div_euclid(CORE) {
+                self.heights.with(bx, bz, || self.core_heights(gen, bx, bz), |block| {
+                    for cz in (bz * CORE).max(cz0)..(bz * CORE + CORE).min(cz0 + last + 1) {
+                        for cx in (bx * CORE).max(cx0)..(bx * CORE + CORE).min(cx0 + last + 1) {
+                            h[(cz - cz0) as usize*N + (cx - cx0) as usize] = block[((cz - bz * CORE) * CORE + cx - bx * CORE) as usize];
+                        }
+                    }
+                });
+            }
+        }
+
+        let roll = |idx: usize| khash_2float((cx0 + (idx % N) as i32) as u32, (cz0 + (idx / N) as i32) as u32, self.seed ^ 0x1A4E5);
+        let basins = fill_basins(&h, N, REACH as usize, SEA_LEVEL_F32, self.endorheic, roll);
+        let h = basins.filled;
+
+        let mut down = vec![None; N*N];
+        for k in 0..N {
+            for i in 0..N {
+                let idx = k*N + i;
+                if basins.sink[idx] {
+                    continue;
+                }
+                // across a lake its the way to the spill, everywhere else its steepest
+                if let Some(to) = basins.next[idx] {
+                    let step = ((to % N) as i32 - i as i32, (to / N) as i32 - k as i32);
+                    down[idx] = NEIGHBOURS.iter().position(|d| *d == step).map(|n| n as u8);
+                    continue;
+                }
+                let mut best = 0.0;
+                for (n, (di, dk)) in NEIGHBOURS.iter().enumerate() {
+                    let (ni, nk) = (i as i32 + di, k as i32 + dk);
+                    if ni < 0 || nk < 0 || ni >= N as i32 || nk >= N as i32 {
+                        continue;
+                    }
+                    let nidx = nk as usize*N + ni as usize;
+                    let drop = (h[idx] - h[nidx]) / (pos[idx] - pos[nidx]).magnitude();
+                    if drop > best {
+                        best = drop;
+                        down[idx] = Some(n as u8);
+                    }
+                }
+            }
+        }
+        let in_core = |i: i32, k: i32| i >= APRON && k >= APRON && i < APRON + CORE && k < APRON + CORE;
+        let below = |idx: usize| down[idx].map(|n| {
+            let (di, dk) = NEIGHBOURS[n as usize];
+            ((idx % N) as i32 + di, (idx / N) as i32 + dk)
+        });
+
+        // a cell gets passed on once everything draining into it is done, only between core cells.
+        // lakes are flat so going by height wouldnt do
+        let below_core = |idx: usize| below(idx).filter(|(ni, nk)| in_core(*ni, *nk)).map(|(ni, nk)| nk as usize*N + ni as usize);
+        let core_cells = (0..N*N).filter(|idx| in_core((idx % N) as i32, (idx / N) as i32));
+        let mut waiting = vec![0; N*N];
+        for idx in core_cells.clone() {
+            if let Some(nidx) = below_core(idx) {
+                waiting[nidx] += 1;
+            }
+        }
+        let mut ready: Vec<usize> = core_cells.filter(|idx| waiting[*idx] == 0).collect();
+        let mut acc = vec![1.0; N*N];
+        let mut peak = h.clone();
+        while let Some(idx) = ready.pop() {
+            if let Some(nidx) = below_core(idx) {
+                acc[nidx] += acc[idx];
+                peak[nidx] = peak[nidx].max(peak[idx]);
+                waiting[nidx] -= 1;
+                if waiting[nidx] == 0 {
+                    ready.push(nidx);
+                }
+            }
+        }
+
+        // the ring round the core, and where its water goes once its in.
+        // the filling only depends on the ground near each pit, so this tiles view of the ring agrees with its owners
+        let mut inlets = Vec::new();
+        let mut through = vec![Vec::new(); (CORE*CORE) as usize];
+        for k in APRON - 1..=APRON + CORE {
+            for i in APRON - 1..=APRON + CORE {
+                if in_core(i, k) {
+                    continue;
+                }
+                let mut next = below(k as usize*N + i as usize);
+                if !next.is_some_and(|(ni, nk)| in_core(ni, nk)) {
+                    continue;
+                }
+                while let Some((ni, nk)) = next.filter(|(ni, nk)| in_core(*ni, *nk)) {
+                    through[((nk - APRON) * CORE + ni - APRON) as usize].push(inlets.len() as u16);
+                    next = below(nk as usize*N + ni as usize);
+                }
+                inlets.push((cx0 + i, cz0 + k));
+            }
+        }
+
+        LocalTile {
+            height: core(&h),
+            acc: core(&acc),
+            peak: core(&peak),
+            down: core(&down),
+            lake: core(&basins.lake),
+            inlets,
+            through,
+        }
+    }
+
+    // local acc and peak of a cell and the cells outside its tile that drain through it
+    fn upstream_of<G: LevelGenerator + ?Sized>(&self, gen: &G, cx: i32, cz: i32) -> (f32, f32, Vec<(i32, i32)>) {
+        let (tx, tz) = (cx.div_euclid(CORE), cz.div_euclid(CORE));
+        let idx = (cz.rem_euclid(CORE) * CORE + cx.rem_euclid(CORE)) as usize;
+        self.tiles.with(tx, tz, || self.local_tile(gen, tx, tz), |tile| {
+            (tile.acc[idx], tile.peak[idx], tile.through[idx].iter().map(|j| tile.inlets[*j as usize]).collect())
+        })
+    }
+
+    // acc and peak of a cell counting everything upstream, over as many tiles as it takes to reach max_acc.
+    // the inflows get added in the order the tile lists them and stop once its full, same as flow_tile does,
+    // so a cell comes out the same whichever tile asked.
+    // only something long and thin that never gathers max_acc, like water running along a ridge, gets MAX_HOPS tiles away,
+    // past that it stops looking and the numbers can be a bit short at the next border down
+    fn upstream<G: LevelGenerator + ?Sized>(&self, gen: &G, cx: i32, cz: i32) -> (f32, f32) {
+        struct Frame {
+            acc: f32,
+            peak: f32,
+            sources: Vec<(i32, i32)>,
+            next: usize,
+        }
+        let frame = |(cx, cz): (i32, i32)| {
+            let (acc, peak, sources) = self.upstream_of(gen, cx, cz);
+            Frame { acc, peak, sources, next: 0 }
+        };
+        // only a tile border the two sides disagree about could loop, dont go round
+        let mut seen = HashSet::from([(cx, cz)]);
+        let mut stack = vec![frame((cx, cz))];
+        loop {
+            let top = stack.last_mut().unwrap();
+            if top.acc < self.max_acc && top.next < top.sources.len() {
+                let source = top.sources[top.next];
+                top.next += 1;
+                if stack.len() < MAX_HOPS && seen.insert(source) {
+                    stack.push(frame(source));
+                }
+                continue;
+            }
+            let done = stack.pop().unwrap();
+            let acc = done.acc.min(self.max_acc);
+            match stack.last_mut() {
+                Some(parent) => {
+                    parent.acc += acc;
+                    parent.peak = parent.peak.max(done.peak);
+                },
+                None => return (acc, done.peak),
+            }
+        }
+    }
+
+    fn flow_tile<G: LevelGenerator + ?Sized>(&self, gen: &G, tx: i32, tz: i32) -> FlowTile {
+        let inlets = self.tiles.with(tx, tz, || self.local_tile(gen, tx, tz), |tile| tile.inlets.clone());
+        let inflow: Vec<(f32, f32)> = inlets.iter().map(|(cx, cz)| self.upstream(gen, *cx, *cz)).collect();
+        self.tiles.with(tx, tz, || self.local_tile(gen, tx, tz), |tile| {
+            let mut acc = tile.acc.clone();
+            let mut peak = tile.peak.clone();
+            for idx in 0..acc.len() {
+                for j in tile.through[idx].iter() {
+                    if acc[idx] >= self.max_acc {
+                        break;
+                    }
+                    let (a, p) = inflow[*j as usize];
+                    acc[idx] += a;
+                    peak[idx] = peak[idx].max(p);
+                }
+                acc[idx] = acc[idx].min(self.max_acc);
+            }
+            FlowTile { acc, peak }
+        })
+    }
+
+    // from the tile that owns the cell
+    #[cfg(test)]
+    pub fn cell<G: LevelGenerator + ?Sized>(&self, gen: &G, cx: i32, cz: i32) -> DrainCell {
+        self.cells(gen, cx, cz, cx, cz)[0]
+    }
+
+    // every cell from cx0 cz0 to cx1 cz1, indexed (cz - cz0)*w + cx - cx0, looking each tile up once for the lot
+    pub fn cells<G: LevelGenerator + ?Sized>(&self, gen: &G, cx0: i32, cz0: i32, cx1: i32, cz1: i32) -> Vec<DrainCell> {
+        let w = (cx1 - cx0 + 1) as usize;
+        let mut out = vec![None; w * (cz1 - cz0 + 1) as usize];
+        for tz in cz0.div_euclid(CORE)..=cz1.div_euclid(CORE) {
+            let (k0, k1) = (cz0.max(tz * CORE), cz1.min(tz * CORE + CORE - 1));
+            for tx in cx0.div_euclid(CORE)..=cx1.div_euclid(CORE) {
+                let (i0, i1) = (cx0.max(tx * CORE), cx1.min(tx * CORE + CORE - 1));
+                let cells = move || (k0..=k1).flat_map(move |cz| (i0..=i1).map(move |cx| (cx, cz)));
+                let idx = |cx: i32, cz: i32| (cz.rem_euclid(CORE) * CORE + cx.rem_euclid(CORE)) as usize;
+                let flow: Vec<(f32, f32)> = self.flow.with(tx, tz, || self.flow_tile(gen, tx, tz), |tile| {
+                    cells().map(|(cx, cz)| (tile.acc[idx(cx, cz)], tile.peak[idx(cx, cz)])).collect()
+                });
+                self.tiles.with(tx, tz, || self.local_tile(gen, tx, tz), |tile| {
+                    for ((cx, cz), (acc, peak)) in cells().zip(flow) {
+                        let i = idx(cx, cz);
+                        out[(cz - cz0) as usize * w + (cx - cx0) as usize] = Some(DrainCell {
+                            height: tile.height[i],
+                            acc,
+                            peak,
+                            down: tile.down[i].map(|n| (cx + NEIGHBOURS[n as usize].0, cz + NEIGHBOURS[n as usize].1)),
+                            lake: tile.lake[i],
+                        });
+                    }
+                });
+            }
+        }
+        out.into_iter().map(|c| c.unwrap()).collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct Rivers<G: LevelGenerator> {
+    inner: G,
+    seed: u32,
+    // cells draining at least this many cells become rivers
+    min_acc: f32,
+    width: f32,
+    // how steep the banks are, high makes canyons
+    bank_slope: f32,
+    drainage: Drainage,
+}
+
+impl<G: LevelGenerator> Rivers<G> {
+    pub fn new(inner: G, seed: u32) -> Rivers<G> {
+        Rivers {
+            inner,
+            seed,
+            min_acc: 48.0,
+            width: 2.0,
+            bank_slope: 0.5,
+            drainage: Drainage::new(seed, CELL, 0.18, MAX_ACC),
+        }
+    }
+
+    #[cfg(test)]
+    fn cell(&self, cx: i32, cz: i32) -> DrainCell {
+        self.drainage.cell(&self.inner, cx, cz)
+    }
+
+    fn river_width(&self, acc: f32) -> f32 {
+        (self.width * (acc / self.min_acc).sqrt()).min(MAX_WIDTH)
+    }
+
+    // every river segment and lake cell that could touch the box
+    fn nearby(&self, x0: f32, z0: f32, x1: f32, z1: f32) -> Nearby {
+        let reach = MAX_WIDTH + VALLEY + 12.0 + CELL;
+        let (cx0, cz0) = (((x0 - reach) / CELL).floor() as i32, ((z0 - reach) / CELL).floor() as i32);
+        let (cx1, cz1) = (((x1 + reach) / CELL).floor() as i32, ((z1 + reach) / CELL).floor() as i32);
+        // one more all round for where the edge ones drain to
+        let w = (cx1 - cx0 + 3) as usize;
+        let cells = self.drainage.cells(&self.inner, cx0 - 1, cz0 - 1, cx1 + 1, cz1 + 1);
+        let cell = |cx: i32, cz: i32| cells[(cz - cz0 + 1) as usize * w + (cx - cx0 + 1) as usize];
+        let mut segments = Vec::new();
+        let mut lakes = Vec::new();
+        for cz in cz0..=cz1 {
+            for cx in cx0..=cx1 {
+                let a = cell(cx, cz);
+                if let Some(level) = a.lake {
+                    lakes.push((self.drainage.node(cx, cz), level));
+                }
+                if a.acc < self.min_acc || a.height < SEA_LEVEL_F32 {
+                    continue;
+                }
+                if let Some((dx, dz)) = a.down {
+                    let b = cell(dx, dz);
+                    segments.push(Segment {
+                        a: self.drainage.node(cx, cz),
+                        b: self.drainage.node(dx, dz),
+                        ha: a.height,
+                        hb: b.height,
+                        wa: self.river_width(a.acc),
+                        wb: self.river_width(b.acc),
+                    });
+                }
+            }
+        }
+        Nearby { segments, lakes }
+    }
+
+    // uncarved and carved ground height and the water surface for a column
+    fn column(&self, nearby: &Nearby, x: f32, z: f32) -> (f32, f32, Option<f32>) {
+        let h = self.inner.height(x, z);
+        // lakes fill whatever ground is under their level near their cells
+        let mut water: Option<f32> = None;
+        for (node, level) in nearby.lakes.iter() {
+            if h < *level && (*node - Vec2::new(x, z)).magnitude() < 1.5 * CELL {
+                water = Some(water.map_or(*level, |old: f32| old.max(*level)));
+            }
+        }
+        if nearby.segments.is_empty() {
+            return (h, h, water);
+        }
+        // wobble the query point so rivers dont run dead straight between nodes
+        let p = Vec2::new(x, z) + 12.0 * Vec2::new(
+            fgrad2_isotropic(0.01 * x, 0.01 * z, self.seed.wrapping_add(0x1F2E3D4C)) - 0.5,
+            fgrad2_isotropic(0.01 * x, 0.01 * z, self.seed.wrapping_add(0x5B6A7988)) - 0.5,
+        );
+        let mut ground = h;
+        for s in nearby.segments.iter() {
+            let ab = s.b - s.a;
+            let t = ((p - s.a).dot(ab) / ab.dot(ab)).clamp(0.0, 1.0);
+            let d = (p - (s.a + ab * t)).magnitude();
+            let w = lerp(s.wa, s.wb, t);
+            if d > w + VALLEY {
+                continue;
+            }
+            let level = lerp(s.ha, s.hb, t) - 1.0;
+            if d < w {
+                let bed = level - (1.0 + 0.3 * w) * (1.0 - (d / w) * (d / w));
+                ground = ground.min(bed);
+                let top = level.min(h);
+                water = Some(water.map_or(top, |old: f32| old.max(top)));
+            } else {
+                let bank = level + (d - w) * self.bank_slope;
+                let fade = smoothstep((d - w) / VALLEY);
+                ground = ground.min(lerp(h.min(bank), h, fade));
+            }
+        }
+        (h, ground, water.filter(|top| *top > ground))
+    }
+
+    // the inner generators chunk with the channels cut into it and the water put in,
+    // everything per column comes from the surface so nothing gets worked out again per chunk
+    fn carve(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        let mut blocks = match &surface.inner {
+            Some(inner) => self.inner.generate_blocks_with(ox, oy, oz, inner),
+            None => self.inner.generate_blocks(ox, oy, oz),
+        };
+        for k in 0..S {
+            let z = oz*S as i32 + k as i32;
+
+            for i in 0..S {
+                let x = ox*S as i32 + i as i32;
+                let h = surface.inner.as_ref().map_or_else(|| self.inner.height(x as f32, z as f32), |inner| inner.height[k*S + i]) as i32;
+                let ground = surface.height[k*S + i] as i32;
+                let water = surface.cover[k*S + i].top;
+                if ground == h && water.is_none() {
+                    continue;
+                }
+                // the banks get whatever the inner generator puts on top, the bed is sand
+                let column = self.inner.column_blocks(x, z, oy, ground);
+
+                for j in 0..S {
+                    let idx = k*S + j*S*S + i;
+                    let y = oy*S as i32 + j as i32;
+                    match water {
+                        Some(top) if y > ground && y <= top as i32 => blocks[idx] = Block::Water,
+                        Some(_) if y == ground => blocks[idx] = Block::Sand,
+                        _ if y > ground && y <= h => blocks[idx] = Block::Air,
+                        _ if y == ground => blocks[idx] = column[j],
+                        _ => {},
+                    }
+                }
+            }
+        }
+        blocks
+    }
+}
+
+impl<G: LevelGenerator> LevelGenerator for Rivers<G> {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        self.column(&self.nearby(x, z, x, z), x, z).1
+    }
+
+    fn height_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        let nearby = self.nearby(x, z, x + 1.0, z + 1.0);
+        let h = self.column(&nearby, x, z).1;
+        let hx = self.column(&nearby, x + 1.0, z).1;
+        let hz = self.column(&nearby, x, z + 1.0).1;
+        (h, Vec2::new(hx - h, hz - h))
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        self.carve(ox, oy, oz, &self.column_surface(ox, oz).unwrap())
+    }
+
+    // the segments near the chunk worked out once for all its columns and border.
+    // water counts towards the top so a lake above the ground isnt taken for air.
+    // the inner surface goes along for the inner generators part of the chunk
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        let (x0, z0) = (ox*S as i32 - 1, oz*S as i32 - 1);
+        let w = S + 2;
+        let nearby = self.nearby(x0 as f32, z0 as f32, (x0 + w as i32) as f32, (z0 + w as i32) as f32);
+        let mut top = f32::NEG_INFINITY;
+        let mut cover = vec![Cover::default(); S*S];
+        let all: Vec<f32> = (0..w*w).map(|idx| {
+            let (i, k) = (idx % w, idx / w);
+            let (_, ground, water) = self.column(&nearby, (x0 + i as i32) as f32, (z0 + k as i32) as f32);
+            if (1..=S).contains(&i) && (1..=S).contains(&k) {
+                cover[(k - 1)*S + i - 1].top = water;
+            }
+            top = top.max(water.unwrap_or(ground));
+            ground
+        }).collect();
+        let mut surface = ColumnSurface::bordered(&all);
+        surface.max = surface.max.max(top);
+        surface.inner = self.inner.column_surface(ox, oz).map(Box::new);
+        surface.cover = cover;
+        Some(surface)
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        self.carve(ox, oy, oz, surface)
+    }
+
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        self.inner.uniform_block(oy, surface)
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        self.inner.surface_block(x, y, z, height)
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        self.inner.column_blocks(x, z, oy, height)
+    }
+
+    fn params(&self) -> Vec<GenParam> {
+        vec![
+            GenParam::new("river_source", self.min_acc, 8.0, 256.0),
+            GenParam::new("river_width", self.width, 0.5, 8.0),
+            GenParam::new("bank_slope", self.bank_slope, 0.1, 4.0),
+            GenParam::new("endorheic", self.drainage.endorheic, 0.0, 1.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "river_source" => self.min_acc = value,
+            "river_width" => self.width = value,
+            "bank_slope" => self.bank_slope = value,
+            // changes the drainage itself
+            "endorheic" => self.drainage = Drainage::new(self.seed, CELL, value, MAX_ACC),
+            _ => {},
+        }
+    }
+
+    fn geology(&self) -> Geology {
+        self.inner.geology()
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Option<Climate> {
+        self.inner.climate(x, z)
+    }
+}
+
+// a valley running along +x and falling gently that way, with ridges 1600 apart so its sides only drain so far
+#[cfg(test)]
+#[derive(Clone)]
+struct Valley;
+
+#[cfg(test)]
+impl LevelGenerator for Valley {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        250.0 - 50.0 * (z * std::f32::consts::PI / 800.0).cos() - 0.1 * x
+    }
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+}
+
+// a crater with its rim at 80, centre x z and radius
+#[cfg(test)]
+#[derive(Clone)]
+struct Crater(f32, f32, f32);
+
+#[cfg(test)]
+impl LevelGenerator for Crater {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        let r = (Vec2::new(x, z) - Vec2::new(self.0, self.1)).magnitude();
+        if r < self.2 { 80.0 - 0.2 * (self.2 - r) } else { 80.0 - 0.05 * (r - self.2) }
+    }
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+}
+
+#[test]
+fn test_crater_lake() {
+    // in the middle of tile 0
+    let mut lakes = Rivers::new(Crater(512.0, 512.0, 200.0), 5);
+    lakes.set_param("endorheic", 0.0);
+    // fills right up to the rim, well above sea level
+    let blocks = lakes.generate_blocks(32, 4, 32);
+    assert!(blocks.iter().all(|b| *b == Block::Water));
+
+    // with no outlet it only gets halfway
+    lakes.set_param("endorheic", 1.0);
+    let blocks = lakes.generate_blocks(32, 4, 32);
+    assert!(blocks.iter().all(|b| *b == Block::Air));
+    let blocks = lakes.generate_blocks(32, 3, 32);
+    assert!(blocks.iter().any(|b| *b == Block::Water));
+}
+
+#[test]
+fn test_lake_over_border() {
+    // mostly in tile 1 and too wide for tile 0 to see across, both sides still come out at the same level
+    let border = CORE as f32 * CELL;
+    let mut lakes = Rivers::new(Crater(border + 240.0, 512.0, 320.0), 5);
+    lakes.set_param("endorheic", 0.0);
+    for cz in 28..36 {
+        let (a, b) = (lakes.cell(CORE - 1, cz), lakes.cell(CORE, cz));
+        assert!(a.lake.is_some());
+        assert_eq!(a.lake, b.lake);
+        assert_eq!(a.height, b.height);
+    }
+    // so the water in the chunks either side of it is level, no wall of water at the seam
+    let ox = border as i32 / S as i32;
+    let (west, east) = (lakes.generate_blocks(ox - 1, 4, 32), lakes.generate_blocks(ox, 4, 32));
+    for k in 0..S {
+        let surface = |blocks: &[Block], i: usize| (0..S).rev().find(|j| blocks[k*S + j*S*S + i] == Block::Water);
+        assert!(surface(&west, S - 1).is_some());
+        assert_eq!(surface(&west, S - 1), surface(&east, 0));
+    }
+}
+
+#[test]
+fn test_river_flow() {
+    let rivers = Rivers::new(Valley, 5);
+    // the valley floor drains a lot more than the slopes
+    let acc_floor = rivers.cell(20, 0).acc.max(rivers.cell(20, -1).acc);
+    let acc_slope = rivers.cell(20, 20).acc;
+    assert!(acc_floor > 10.0 * acc_slope, "{} vs {}", acc_floor, acc_slope);
+
+    // theres water along the floor on both sides of a tile border
+    let border = CORE as f32 * CELL;
+    for x in [border - 200.0, border - 8.0, border + 8.0, border + 200.0] {
+        let nearby = rivers.nearby(x, -40.0, x, 40.0);
+        let wet = (-40..40).any(|z| rivers.column(&nearby, x, z as f32).2.is_some());
+        assert!(wet, "no river at x {}", x);
+    }
+}
+
+#[test]
+fn test_river_chunks_agree() {
+    let rivers = Rivers::new(Valley, 5);
+    // a chunk sees the same ground as the point query, so neighbouring chunks line up
+    for (ox, oz) in [(40, -1), (40, 0), (41, 0), (64, 0)] {
+        let blocks = rivers.generate_blocks(ox, 2, oz);
+        for k in 0..S {
+            for i in 0..S {
+                let (x, z) = (ox*S as i32 + i as i32, oz*S as i32 + k as i32);
+                let height = rivers.height(x as f32, z as f32) as i32;
+                for j in 0..S {
+                    let y = 2*S as i32 + j as i32;
+                    let solid = blocks[k*S + j*S*S + i].is_opaque();
+                    assert_eq!(solid, y <= height, "column {} {} at {}", x, z, y);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_river_crosses_tiles() {
+    let rivers = Rivers::new(Valley, 5);
+    // follow the valley floor downstream over the border between tiles 0 and 1,
+    // it only ever picks up water so it never gets narrower or drops out
+    let mut c = if rivers.cell(48, 0).acc > rivers.cell(48, -1).acc { (48, 0) } else { (48, -1) };
+    let mut last = rivers.cell(c.0, c.1);
+    let mut crossed = false;
+    while c.0 < CORE + 16 {
+        let (dx, dz) = last.down.expect("river stopped");
+        let next = rivers.cell(dx, dz);
+        assert!(next.acc >= last.acc, "acc drops from {} to {} at {:?}", last.acc, next.acc, (dx, dz));
+        assert!(rivers.river_width(next.acc) >= rivers.river_width(last.acc));
+        assert!(next.acc >= rivers.min_acc);
+        crossed |= c.0 < CORE && dx >= CORE;
+        c = (dx, dz);
+        last = next;
+    }
+    assert!(crossed);
+}
+
+#[test]
+fn test_river_keeps_inner() {
+    let rivers = Rivers::new(Valley, 5);
+    let cache = ColumnCache::new();
+    let mut carved = false;
+    for (ox, oz) in [(40, -1), (40, 0), (40, 6)] {
+        let surface = cache.get(&rivers, 0, ox, oz).unwrap();
+        carved |= surface.height != ColumnSurface::heights(&Valley, ox, oz).height;
+        assert_eq!(surface.height, ColumnSurface::heights(&rivers, ox, oz).height);
+        assert_eq!(surface.inner.as_ref().unwrap().height, ColumnSurface::heights(&Valley, ox, oz).height);
+        for oy in 0..3 {
+            let blocks = rivers.generate_blocks(ox, oy, oz);
+            assert_eq!(blocks, rivers.generate_blocks_with(ox, oy, oz, &surface));
+            if let (cached, None) = cached_blocks(&rivers, &cache, 0, ox, oy, oz) {
+                assert_eq!(cached, chunk_blocks(&rivers, ox, oy, oz));
+            }
+            // under the carved ground its the inner chunk untouched
+            let inner = Valley.generate_blocks(ox, oy, oz);
+            for idx in 0..S*S*S {
+                let y = oy*S as i32 + (idx / (S*S)) as i32;
+                if y < surface.height[(idx / S) % S * S + idx % S] as i32 {
+                    assert_eq!(blocks[idx], inner[idx]);
+                }
+            }
+        }
+    }
+    assert!(carved);
+}
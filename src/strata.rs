@@ -0,0 +1,233 @@
+use crate::chunk::*;
+use crate::krand::*;
+use crate::settings::*;
+
+/*
+whats under the dirt. each generator hands over a Geology saying which block is its bedrock, what bands of rock
+it gets layered into and which ores are in it at what heights. it runs over every chunk after generate_blocks,
+only touching the host rock so the surface, caves and whatever else the generator did stay put.
+bands follow the terrain part way and wobble a bit, so hills are layered like hills and not like a cake.
+the idea from noise.md is the bands being different hardness, something an erosion pass could read later.
+ore blobs sit one at most per ORE_CELL cell, jittered, so like everything else a chunk can work them out on its own
+*/
+
+const ORE_CELL: i32 = 8;
+
+// a band of rock this many blocks thick
+#[derive(Clone, Copy, Debug)]
+pub struct Stratum {
+    pub block: Block,
+    pub thickness: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ore {
+    pub block: Block,
+    // world y the blobs are centred between
+    pub min_y: i32,
+    pub max_y: i32,
+    // blob radius, up to ORE_CELL
+    pub size: f32,
+    // chance of a blob in each ORE_CELL cube
+    pub rarity: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Geology {
+    pub seed: u32,
+    // the rock the generator fills with, nothing else gets replaced
+    pub host: Block,
+    // repeating downwards from the surface
+    pub strata: Vec<Stratum>,
+    // 0 is flat bands, 1 is bands parallel to the surface
+    pub follow: f32,
+    // checked in order, first one wins
+    pub ores: Vec<Ore>,
+}
+
+impl Geology {
+    pub fn standard(seed: u32) -> Geology {
+        Geology {
+            seed,
+            host: Block::Stone,
+            strata: vec![
+                Stratum { block: Block::Stone, thickness: 12.0 },
+                Stratum { block: Block::Slate, thickness: 3.0 },
+                Stratum { block: Block::Stone, thickness: 8.0 },
+                Stratum { block: Block::Limestone, thickness: 5.0 },
+                Stratum { block: Block::Stone, thickness: 10.0 },
+                Stratum { block: Block::Granite, thickness: 6.0 },
+            ],
+            follow: 0.6,
+            ores: vec![
+                Ore { block: Block::Diamond, min_y: -256, max_y: -120, size: 1.5, rarity: 0.01 },
+                Ore { block: Block::Gold, min_y: -200, max_y: -40, size: 2.0, rarity: 0.02 },
+                Ore { block: Block::Iron, min_y: -120, max_y: 10, size: 2.5, rarity: 0.05 },
+                Ore { block: Block::Coal, min_y: -64, max_y: 80, size: 3.0, rarity: 0.08 },
+            ],
+        }
+    }
+
+    // dark basalt layers and pockets of lava
+    pub fn hell(seed: u32) -> Geology {
+        Geology {
+            seed,
+            host: Block::Hellstone,
+            strata: vec![
+                Stratum { block: Block::Hellstone, thickness: 9.0 },
+                Stratum { block: Block::Basalt, thickness: 4.0 },
+            ],
+            follow: 0.3,
+            ores: vec![
+                Ore { block: Block::Lava, min_y: -200, max_y: -10, size: 4.0, rarity: 0.04 },
+                Ore { block: Block::Gold, min_y: -100, max_y: 20, size: 2.0, rarity: 0.04 },
+            ],
+        }
+    }
+
+    // no layers, just the odd gem
+    pub fn moon(seed: u32) -> Geology {
+        Geology {
+            seed,
+            host: Block::MoonRock,
+            strata: Vec::new(),
+            follow: 0.0,
+            ores: vec![
+                Ore { block: Block::Diamond, min_y: -256, max_y: 40, size: 1.5, rarity: 0.03 },
+            ],
+        }
+    }
+
+    // how far the layers are pushed up in a column, the bit that follows the ground plus a slow wobble
+    fn lift(&self, x: i32, z: i32, height: f32) -> f32 {
+        let wobble = 8.0 * (fgrad2_isotropic(0.02 * x as f32, 0.02 * z as f32, self.seed.wrapping_add(0x4F1BBCDC)) - 0.5);
+        height * self.follow + wobble
+    }
+
+    fn stratum(&self, y: i32, lift: f32) -> Block {
+        let total: f32 = self.strata.iter().map(|s| s.thickness).sum();
+        if total <= 0.0 {
+            return self.host;
+        }
+        let mut t = (lift - y as f32).rem_euclid(total);
+        for s in self.strata.iter() {
+            if t < s.thickness {
+                return s.block;
+            }
+            t -= s.thickness;
+        }
+        self.host
+    }
+
+    // centre, radius and block of every blob that could touch the box
+    fn blobs(&self, x0: i32, y0: i32, z0: i32, x1: i32, y1: i32, z1: i32) -> Vec<([f32; 3], f32, Block)> {
+        let mut blobs = Vec::new();
+        for (n, ore) in self.ores.iter().enumerate() {
+            let seed = khash(self.seed ^ (n as u32).wrapping_mul(0x9E3779B9));
+            let r = ore.size.ceil() as i32;
+            let lo_y = (y0 - r).max(ore.min_y);
+            let hi_y = (y1 + r).min(ore.max_y);
+            if lo_y > hi_y {
+                continue;
+            }
+            for cz in (z0 - r).div_euclid(ORE_CELL)..=(z1 + r).div_euclid(ORE_CELL) {
+                for cy in lo_y.div_euclid(ORE_CELL)..=hi_y.div_euclid(ORE_CELL) {
+                    for cx in (x0 - r).div_euclid(ORE_CELL)..=(x1 + r).div_euclid(ORE_CELL) {
+                        let (ux, uy, uz) = (cx as u32, cy as u32, cz as u32);
+                        if khash_3float(ux, uy, uz, seed) >= ore.rarity {
+                            continue;
+                        }
+                        let c = [
+                            (cx * ORE_CELL) as f32 + khash_3float(ux, uy, uz, seed ^ 1) * ORE_CELL as f32,
+                            (cy * ORE_CELL) as f32 + khash_3float(ux, uy, uz, seed ^ 2) * ORE_CELL as f32,
+                            (cz * ORE_CELL) as f32 + khash_3float(ux, uy, uz, seed ^ 3) * ORE_CELL as f32,
+                        ];
+                        if c[1] < ore.min_y as f32 || c[1] > ore.max_y as f32 {
+                            continue;
+                        }
+                        blobs.push((c, ore.size * (0.6 + 0.4 * khash_3float(ux, uy, uz, seed ^ 4)), ore.block));
+                    }
+                }
+            }
+        }
+        blobs
+    }
+
+    fn ore_at(&self, blobs: &[([f32; 3], f32, Block)], x: i32, y: i32, z: i32) -> Option<Block> {
+        // lumpy edges
+        let bump = 0.7 + 0.6 * khash_3float(x as u32, y as u32, z as u32, self.seed);
+        blobs.iter().find(|(c, r, _)| {
+            let (dx, dy, dz) = (x as f32 - c[0], y as f32 - c[1], z as f32 - c[2]);
+            dx*dx + dy*dy + dz*dz < r*r*bump
+        }).map(|(_, _, block)| *block)
+    }
+
+    // heights is the surface at i k in the chunk
+    pub fn apply(&self, ox: i32, oy: i32, oz: i32, blocks: &mut [Block], heights: impl Fn(usize, usize) -> f32) {
+        if self.strata.is_empty() && self.ores.is_empty() {
+            return;
+        }
+        let (x0, y0, z0) = (ox*S as i32, oy*S as i32, oz*S as i32);
+        let s = S as i32;
+        let blobs = self.blobs(x0, y0, z0, x0 + s - 1, y0 + s - 1, z0 + s - 1);
+        for k in 0..S {
+            let z = z0 + k as i32;
+            for i in 0..S {
+                let x = x0 + i as i32;
+                // worked out once per column and only if something in it needs a layer
+                let mut lift = None;
+                for j in 0..S {
+                    let idx = k*S + j*S*S + i;
+                    if blocks[idx] != self.host {
+                        continue;
+                    }
+                    let y = y0 + j as i32;
+                    blocks[idx] = match self.ore_at(&blobs, x, y, z) {
+                        Some(block) => block,
+                        None => self.stratum(y, *lift.get_or_insert_with(|| self.lift(x, z, heights(i, k)))),
+                    };
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_geology() {
+    let geo = Geology::standard(5);
+    let mut seen = Vec::new();
+    for oy in -10..2 {
+        let mut blocks = vec![Block::Stone; S*S*S];
+        blocks[0] = Block::Air;
+        blocks[1] = Block::Moss;
+        geo.apply(3, oy, -2, &mut blocks, |_, _| 20.0);
+        // only the host gets replaced
+        assert_eq!(blocks[0], Block::Air);
+        assert_eq!(blocks[1], Block::Moss);
+        seen.extend(blocks);
+    }
+    for b in [Block::Stone, Block::Slate, Block::Limestone, Block::Granite, Block::Coal, Block::Iron] {
+        assert!(seen.contains(&b), "no {:?}", b);
+    }
+
+    // ores stay in their band and dont care which chunk asked
+    let ore = Ore { block: Block::Gold, min_y: -40, max_y: -20, size: 3.0, rarity: 0.5 };
+    let geo = Geology { seed: 1, host: Block::Stone, strata: Vec::new(), follow: 0.0, ores: vec![ore] };
+    let s = S as i32;
+    let big = geo.blobs(-s, -6*s, -s, 2*s, 2*s, 2*s);
+    let mut gold = 0;
+    for oy in -5..1 {
+        let small = geo.blobs(0, oy*s, 0, s - 1, oy*s + s - 1, s - 1);
+        let mut blocks = vec![Block::Stone; S*S*S];
+        geo.apply(0, oy, 0, &mut blocks, |_, _| 0.0);
+        for idx in 0..S*S*S {
+            let (x, y, z) = ((idx % S) as i32, oy*s + (idx / (S*S)) as i32, ((idx / S) % S) as i32);
+            assert_eq!(geo.ore_at(&small, x, y, z), geo.ore_at(&big, x, y, z));
+            if blocks[idx] == Block::Gold {
+                assert!(y >= -40 - 3 && y <= -20 + 3, "gold at {}", y);
+                gold += 1;
+            }
+        }
+    }
+    assert!(gold > 0);
+}
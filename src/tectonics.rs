@@ -0,0 +1,256 @@
+use std::f32::consts::PI;
+use crate::chunk::*;
+use crate::krand::*;
+use crate::kmath::*;
+use crate::world_gen::*;
+use crate::column_cache::*;
+use crate::strata::*;
+use crate::gen_combinators::*;
+
+/*
+the cheap version of plate tectonics from erosion.md. no simulation, the plates are jittered voronoi cells
+each with a velocity and either continental or oceanic crust, and everything comes from looking at the boundaries
+near a point. where two plates close on each other the ground gets pushed up into a mountain belt,
+where they pull apart it drops into a rift. continental crust sits above the sea and oceanic below,
+blended over the shelf so coasts arent cliffs. an ocean plate going under a continent makes the mountains
+on the continent side and a trench on its own side, like the andes.
+it only hands out low frequency numbers, a generator still has to put actual mountains where the belts are.
+Plates is plain data so any generator can carry one, PlateBelts makes the belts a Field2 for the combinators
+*/
+
+const REACH: i32 = 2;
+// base elevations, blocks
+const CONTINENT: f32 = 16.0;
+const OCEAN: f32 = -50.0;
+
+#[derive(Clone, Copy, Debug)]
+struct Plate {
+    x: f32,
+    z: f32,
+    vx: f32,
+    vz: f32,
+    continental: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlateSample {
+    // height before any mountains
+    pub elevation: f32,
+    // 0..1, how much collision is going on here
+    pub belt: f32,
+    // 0..1, rifts and trenches
+    pub rift: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Plates {
+    pub seed: u32,
+    // plate spacing
+    pub size: f32,
+    // fraction of plates that are land
+    pub continental: f32,
+    pub belt_width: f32,
+    pub rift_width: f32,
+    // how far either side of a boundary the crust elevations blend
+    pub shelf: f32,
+    // wobble on the boundaries
+    pub warp: f32,
+}
+
+impl Plates {
+    pub fn new(seed: u32) -> Plates {
+        Plates { seed, size: 2048.0, continental: 0.5, belt_width: 220.0, rift_width: 90.0, shelf: 320.0, warp: 400.0 }
+    }
+
+    fn plate(&self, cx: i32, cz: i32) -> Plate {
+        let (ux, uz) = (cx as u32, cz as u32);
+        let h = |n: u32| khash_2float(ux, uz, self.seed.wrapping_add(n));
+        let angle = h(2) * 2.0 * PI;
+        let speed = 0.4 + 0.6 * h(3);
+        Plate {
+            // kept off the cell edges a bit so plates dont get too thin
+            x: (cx as f32 + 0.15 + 0.7 * h(0)) * self.size,
+            z: (cz as f32 + 0.15 + 0.7 * h(1)) * self.size,
+            vx: angle.cos() * speed,
+            vz: angle.sin() * speed,
+            continental: h(4) < self.continental,
+        }
+    }
+
+    fn warped(&self, x: f32, z: f32) -> (f32, f32) {
+        let f = 2.0 / self.size;
+        (
+            x + self.warp * (fgrad2_isotropic(f * x, f * z, self.seed.wrapping_add(5)) - 0.5),
+            z + self.warp * (fgrad2_isotropic(f * x, f * z, self.seed.wrapping_add(6)) - 0.5),
+        )
+    }
+
+    pub fn sample(&self, x: f32, z: f32) -> PlateSample {
+        let (px, pz) = self.warped(x, z);
+        let (cx, cz) = ((px / self.size).floor() as i32, (pz / self.size).floor() as i32);
+        let mut plates = Vec::with_capacity(((2*REACH + 1) * (2*REACH + 1)) as usize);
+        for dz in -REACH..=REACH {
+            for dx in -REACH..=REACH {
+                plates.push(self.plate(cx + dx, cz + dz));
+            }
+        }
+        self.sample_plates(&plates, px, pz)
+    }
+
+    // every pair of plates counts for as long as both are nearly the nearest, which is only near their boundary.
+    // going by pairs rather than from whichever plate the point is in keeps it continuous across triple junctions
+    fn sample_plates(&self, plates: &[Plate], px: f32, pz: f32) -> PlateSample {
+        let dists: Vec<f32> = plates.iter().map(|p| ((p.x - px).powi(2) + (p.z - pz).powi(2)).sqrt()).collect();
+        let nearest = dists.iter().copied().fold(f32::INFINITY, f32::min);
+        // the gap to the boundary is half the difference in distance
+        let near = |d: f32, width: f32| smoothstep(1.0 - (d - nearest) / (2.0 * width));
+
+        let mut elevation = 0.0;
+        let mut total = 0.0;
+        for (p, d) in plates.iter().zip(dists.iter()) {
+            let w = near(*d, 0.5 * self.shelf);
+            elevation += w * if p.continental { CONTINENT } else { OCEAN };
+            total += w;
+        }
+
+        let mut belt: f32 = 0.0;
+        let mut rift: f32 = 0.0;
+        for i in 0..plates.len() {
+            for j in i + 1..plates.len() {
+                let (a, b) = (&plates[i], &plates[j]);
+                let (da, db) = (dists[i], dists[j]);
+                let len = ((b.x - a.x).powi(2) + (b.z - a.z).powi(2)).sqrt();
+                let (nx, nz) = ((b.x - a.x) / len, (b.z - a.z) / len);
+                let closing = (a.vx - b.vx) * nx + (a.vz - b.vz) * nz;
+                if closing > 0.0 {
+                    let push = closing * near(da, self.belt_width) * near(db, self.belt_width);
+                    if a.continental == b.continental {
+                        belt += push;
+                    } else {
+                        // how far onto the continent side of the boundary
+                        let (dc, dsea) = if a.continental { (da, db) } else { (db, da) };
+                        let t = smoothstep(0.5 + (dsea - dc) / self.rift_width);
+                        belt += push * t;
+                        rift += push * (1.0 - t);
+                    }
+                } else {
+                    rift += -closing * near(da, self.rift_width) * near(db, self.rift_width);
+                }
+            }
+        }
+        PlateSample { elevation: elevation / total, belt: belt.min(1.0), rift: rift.min(1.0) }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct PlateBelts(pub Plates);
+
+impl Field2 for PlateBelts {
+    fn sample(&self, x: f32, z: f32) -> f32 {
+        self.0.sample(x, z).belt
+    }
+}
+
+// the plates with ridged mountains along the belts and some rolling hills on top
+#[derive(Clone)]
+pub struct GenPlates {
+    seed: u32,
+    plates: Plates,
+    mountains: f32,
+    rifts: f32,
+}
+
+impl GenPlates {
+    pub fn new(seed: u32) -> GenPlates {
+        GenPlates { seed, plates: Plates::new(seed), mountains: 220.0, rifts: 40.0 }
+    }
+}
+
+impl LevelGenerator for GenPlates {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        let s = self.plates.sample(x, z);
+        let ridges = Fractal::new(5, 2.0, 0.5).ridged(0.003 * x, 0.003 * z, self.seed.wrapping_add(7));
+        let hills = Fractal::new(4, 2.0, 0.5).fbm(0.006 * x, 0.006 * z, self.seed.wrapping_add(8)) - 0.5;
+        s.elevation + self.mountains * s.belt * (0.3 + 0.7 * ridges) - self.rifts * s.rift + 24.0 * hills
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        heightmap_blocks(self, ox, oy, oz)
+    }
+
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    fn params(&self) -> Vec<GenParam> {
+        vec![
+            GenParam::new("plate_size", self.plates.size, 256.0, 8192.0),
+            GenParam::new("continental", self.plates.continental, 0.0, 1.0),
+            GenParam::new("mountains", self.mountains, 0.0, 400.0),
+            GenParam::new("rifts", self.rifts, 0.0, 150.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "plate_size" => self.plates.size = value,
+            "continental" => self.plates.continental = value,
+            "mountains" => self.mountains = value,
+            "rifts" => self.rifts = value,
+            _ => {},
+        }
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::standard(self.seed)
+    }
+}
+
+#[test]
+fn test_plates() {
+    let plates = Plates::new(3);
+    let land = |vx: f32| [
+        Plate { x: -1000.0, z: 0.0, vx, vz: 0.0, continental: true },
+        Plate { x: 1000.0, z: 0.0, vx: -vx, vz: 0.0, continental: true },
+    ];
+    // head on collision is mountains on the boundary and nothing far from it
+    let colliding = land(0.5);
+    assert!(plates.sample_plates(&colliding, 0.0, 300.0).belt > 0.9);
+    assert_eq!(plates.sample_plates(&colliding, -600.0, 0.0).belt, 0.0);
+    assert_eq!(plates.sample_plates(&colliding, 0.0, 0.0).rift, 0.0);
+    // pulling apart is a rift
+    let parting = land(-0.5);
+    assert!(plates.sample_plates(&parting, 10.0, 0.0).rift > 0.9);
+    assert_eq!(plates.sample_plates(&parting, 0.0, 0.0).belt, 0.0);
+    // ocean under continent, mountains on the land side and a trench on the sea side
+    let mut subducting = land(0.5);
+    subducting[0].continental = false;
+    let sea = plates.sample_plates(&subducting, -50.0, 0.0);
+    let shore = plates.sample_plates(&subducting, 50.0, 0.0);
+    assert!(sea.rift > 0.5 && sea.belt == 0.0, "{:?}", sea);
+    assert!(shore.belt > 0.5 && shore.rift == 0.0, "{:?}", shore);
+    assert!(plates.sample_plates(&subducting, -900.0, 0.0).elevation == OCEAN);
+    assert!(plates.sample_plates(&subducting, 900.0, 0.0).elevation == CONTINENT);
+
+    // the real thing is continuous and has some of everything
+    let (mut belts, mut rifts, mut land, mut sea) = (0, 0, 0, 0);
+    for row in 0..4 {
+        let z = row as f32 * 1500.0;
+        let mut last = plates.sample(-8000.0, z);
+        for x in (-8000..8000).step_by(2) {
+            let s = plates.sample(x as f32, z);
+            assert!((s.elevation - last.elevation).abs() < 2.0, "{:?} {:?}", last, s);
+            assert!((s.belt - last.belt).abs() < 0.05 && (s.rift - last.rift).abs() < 0.05, "{:?} {:?}", last, s);
+            belts += (s.belt > 0.5) as i32;
+            rifts += (s.rift > 0.5) as i32;
+            land += (s.elevation == CONTINENT) as i32;
+            sea += (s.elevation == OCEAN) as i32;
+            last = s;
+        }
+    }
+    assert!(belts > 0 && rifts > 0 && land > 0 && sea > 0, "{} {} {} {}", belts, rifts, land, sea);
+}
@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+// big generator tiles (erosion, drainage) made lazily by whichever worker needs them first.
+// a tile is made outside the lock, anyone else after the same one waits for it. the least recently used go past max
+pub struct TileCache<T> {
+    tiles: Mutex<(HashMap<(i32, i32), (Arc<OnceLock<T>>, u64)>, u64)>,
+    max: usize,
+}
+
+impl<T> TileCache<T> {
+    pub fn new(max: usize) -> TileCache<T> {
+        TileCache { tiles: Mutex::new((HashMap::new(), 0)), max }
+    }
+
+    pub fn with<R>(&self, tx: i32, tz: i32, make: impl FnOnce() -> T, f: impl FnOnce(&T) -> R) -> R {
+        let cell = {
+            let mut guard = self.tiles.lock().unwrap();
+            let (map, tick) = &mut *guard;
+            *tick += 1;
+            let now = *tick;
+            let entry = map.entry((tx, tz)).or_insert_with(|| (Arc::new(OnceLock::new()), now));
+            entry.1 = now;
+            let cell = entry.0.clone();
+            if map.len() > self.max {
+                let oldest = *map.iter().min_by_key(|(_, (_, t))| *t).unwrap().0;
+                map.remove(&oldest);
+            }
+            cell
+        };
+        f(cell.get_or_init(make))
+    }
+
+    // only if its already made, never makes it or waits for it
+    pub fn peek<R>(&self, tx: i32, tz: i32, f: impl FnOnce(&T) -> R) -> Option<R> {
+        let cell = self.tiles.lock().unwrap().0.get(&(tx, tz))?.0.clone();
+        cell.get().map(f)
+    }
+}
@@ -3,6 +3,21 @@ use crate::krand::*;
 use crate::settings::*;
 use crate::kmath::*;
 use crate::kimg::*;
+use crate::world_gen2::*;
+use crate::gen_combinators::*;
+use crate::column_cache::*;
+use crate::hydraulic::*;
+use crate::rivers::*;
+use crate::decorate::*;
+use crate::caves::*;
+use crate::strata::*;
+use crate::climate::*;
+use crate::classify::*;
+use crate::regions::*;
+use crate::tectonics::*;
+use crate::glaciers::*;
+use crate::mesa::*;
+use std::sync::Arc;
 
 /*
 lets give this trait thing a try
@@ -14,21 +29,277 @@ i wonder if some fancy combinator is possible... sounds slow lol
 pub const SEA_LEVEL_F32: f32 = 0.0;
 pub const SEA_LEVEL_I32: i32 = 0;
 
-pub trait LevelGenerator: Clone + Send + Sync + Sized + 'static {
+// a named tuning knob a generator exposes, the F3 menu turns these into sliders
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GenParam {
+    pub name: &'static str,
+    pub value: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl GenParam {
+    pub fn new(name: &'static str, value: f32, min: f32, max: f32) -> GenParam {
+        GenParam { name, value, min, max }
+    }
+}
+
+pub trait LevelGenerator: Send + Sync + 'static {
 
     fn height(&self, x: f32, z: f32) -> f32;
     fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block>;
 
+    // what goes at y in a column whose surface is at height. combinators use this to keep the look of what they wrap
+    fn surface_block(&self, _x: i32, y: i32, _z: i32, height: i32) -> Block {
+        standard_block(y, height)
+    }
+
+    // surface_block for the S blocks of column x z in chunk oy, bottom up. anything the look needs besides the height
+    // (slope, how beachy it is) gets worked out once here instead of once a block, so generators with a look of their
+    // own override this and have surface_block pick out of it
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        std::array::from_fn(|j| self.surface_block(x, oy*S as i32 + j as i32, z, height))
+    }
+
+    // where in its block a column takes its height, ColumnSurface samples at x + this, z + this.
+    // the older generators use the middle of the block
+    fn sample_offset(&self) -> f32 {
+        0.0
+    }
+
+    // height and (dh/dx, dh/dz). this is a forward difference costing 3 heights,
+    // generators built on the _d noise functions should override it with the exact one
     fn height_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
         let h1 = self.height(x,z);
         let hgx = self.height(x + 1.0, z + 0.0);
         let hgz = self.height(x + 0.0, z + 1.0);
 
-        let gradx = h1 - hgx;
-        let gradz = h1 - hgz;
+        let gradx = hgx - h1;
+        let gradz = hgz - h1;
 
         (h1, Vec2{x: gradx, y: gradz})
     }
+
+    // positive is solid, negative is air. heightmaps get this for free, 3d generators override it
+    fn density(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.height(x, z) - y
+    }
+
+    fn params(&self) -> Vec<GenParam> {
+        Vec::new()
+    }
+
+    // unknown names are ignored
+    fn set_param(&mut self, _name: &str, _value: f32) {}
+
+    // the 2d stuff a chunk column needs, the chunk manager works it out once per column
+    // and hands it to generate_blocks_with for every chunk stacked there. None opts out
+    fn column_surface(&self, _ox: i32, _oz: i32) -> Option<ColumnSurface> {
+        None
+    }
+
+    // generate_blocks with the column surface already worked out
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, _surface: &ColumnSurface) -> Vec<Block> {
+        self.generate_blocks(ox, oy, oz)
+    }
+
+    // the block filling the whole chunk at oy, if the column alone proves it and none of its faces could be seen.
+    // those chunks skip generation and meshing. has to agree with surface_block, so override them together
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        standard_uniform_block(oy, surface)
+    }
+
+    // rock layers and ores, run over the chunk after generate_blocks. anything with a seed should override this
+    // with its own, otherwise every seed gets the same ore
+    fn geology(&self) -> Geology {
+        Geology::standard(0)
+    }
+
+    // temperature and moisture, for generators that work one out. see climate.rs
+    fn climate(&self, _x: f32, _z: f32) -> Option<Climate> {
+        None
+    }
+}
+
+// so the chunk manager can be handed a generator picked at runtime
+pub type SharedGen = Arc<dyn LevelGenerator>;
+
+impl<G: LevelGenerator + ?Sized> LevelGenerator for Arc<G> {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        (**self).height(x, z)
+    }
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        (**self).generate_blocks(ox, oy, oz)
+    }
+    fn height_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        (**self).height_gradient(x, z)
+    }
+    fn sample_offset(&self) -> f32 {
+        (**self).sample_offset()
+    }
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        (**self).surface_block(x, y, z, height)
+    }
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        (**self).column_blocks(x, z, oy, height)
+    }
+    fn density(&self, x: f32, y: f32, z: f32) -> f32 {
+        (**self).density(x, y, z)
+    }
+    fn params(&self) -> Vec<GenParam> {
+        (**self).params()
+    }
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        (**self).column_surface(ox, oz)
+    }
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        (**self).generate_blocks_with(ox, oy, oz, surface)
+    }
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        (**self).uniform_block(oy, surface)
+    }
+    fn geology(&self) -> Geology {
+        (**self).geology()
+    }
+    fn climate(&self, x: f32, z: f32) -> Option<Climate> {
+        (**self).climate(x, z)
+    }
+    // only works while nobody else holds the generator, i.e. straight after constructing it
+    fn set_param(&mut self, name: &str, value: f32) {
+        if let Some(gen) = Arc::get_mut(self) {
+            gen.set_param(name, value);
+        }
+    }
+}
+
+// the usual water / grass / sand / dirt / stone stack
+pub fn standard_block(y: i32, height: i32) -> Block {
+    match (y - height, y) {
+        (dh, y) if dh > 0 && y > 0 => Block::Air,
+        (dh, y) if dh > 0 && y <= 0 => Block::Water,
+        (dh, y) if dh == 0 && y > 4 => Block::Grass,
+        (dh, y) if dh == 0 && y > -4 => Block::Sand,
+        (dh, _) if dh > -4 => Block::Dirt,
+        _ => Block::Stone,
+    }
+}
+
+// surface_block for a generator that does its look in column_blocks
+pub fn column_block<G: LevelGenerator + ?Sized>(gen: &G, x: i32, y: i32, z: i32, height: i32) -> Block {
+    gen.column_blocks(x, z, y.div_euclid(S as i32), height)[y.rem_euclid(S as i32) as usize]
+}
+
+// standard_block, except anything steeper than slope is bare rock
+pub fn slope_block(y: i32, height: i32, grad: Vec2, slope: f32) -> Block {
+    match y - height {
+        dh if dh > 0 => standard_block(y, height),
+        _ if grad.magnitude() > slope => Block::Stone,
+        _ => standard_block(y, height),
+    }
+}
+
+// chunk from density, solid where its positive. grass on anything open to the sky,
+// then dirt, then stone, water fills open space below sea level
+pub fn density_blocks<G: LevelGenerator + ?Sized>(gen: &G, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+    let dirt_depth = 3;
+    let mut blocks = vec![Block::Air; S*S*S];
+    let mut column = vec![false; S + dirt_depth];
+    for k in 0..S {
+        let z = oz*S as i32 + k as i32;
+
+        for i in 0..S {
+            let x = ox*S as i32 + i as i32;
+            // a few extra above the chunk so the surface layers line up across chunk borders
+            for j in 0..S + dirt_depth {
+                let y = oy*S as i32 + j as i32;
+                column[j] = gen.density(x as f32, y as f32, z as f32) > 0.0;
+            }
+
+            for j in 0..S {
+                let idx = k*S + j*S*S + i;
+                let y = oy*S as i32 + j as i32;
+                blocks[idx] = if !column[j] {
+                    if y <= SEA_LEVEL_I32 { Block::Water } else { Block::Air }
+                } else {
+                    let open_above = (1..=dirt_depth).position(|d| !column[j + d]);
+                    match open_above {
+                        Some(0) if y > SEA_LEVEL_I32 + 4 => Block::Grass,
+                        Some(_) if y > SEA_LEVEL_I32 - 4 && y <= SEA_LEVEL_I32 + 4 => Block::Sand,
+                        Some(_) => Block::Dirt,
+                        None => Block::Stone,
+                    }
+                };
+            }
+        }
+    }
+    blocks
+}
+
+// plain heightmap chunk from height + surface_block
+pub fn heightmap_blocks<G: LevelGenerator + ?Sized>(gen: &G, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+    heightmap_blocks_with(gen, ox, oy, oz, &ColumnSurface::heights(gen, ox, oz))
+}
+
+pub fn heightmap_blocks_with<G: LevelGenerator + ?Sized>(gen: &G, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+    let mut blocks = vec![Block::Air; S*S*S];
+    for k in 0..S {
+        let z = oz*S as i32 + k as i32;
+
+        for i in 0..S {
+            let x = ox*S as i32 + i as i32;
+            let height = surface.height[k*S + i] as i32;
+            let column = gen.column_blocks(x, z, oy, height);
+
+            for j in 0..S {
+                blocks[k*S + j*S*S + i] = column[j];
+            }
+        }
+    }
+    blocks
+}
+
+pub type GenConstructor = fn(u32) -> SharedGen;
+
+// everything you can switch to from the menu, takes a seed
+pub const GENERATORS: &[(&str, GenConstructor)] = &[
+    ("WorldGen", |seed| Arc::new(WorldGen::new(seed))),
+    ("NormalCliffy", |seed| Arc::new(GenNormalCliffy::new(seed))),
+    ("Hell", |seed| Arc::new(GenHell::new(seed))),
+    ("Exp", |seed| Arc::new(GenExp::new(seed))),
+    ("Erosion", |seed| Arc::new(GenErosion::new(seed))),
+    ("Erosion2", |seed| Arc::new(GenErosion2::new(seed))),
+    ("Crag", |seed| Arc::new(GenCrag::new(seed))),
+    ("Warp", |seed| Arc::new(GenWarp::new(seed))),
+    ("Beach", |seed| Arc::new(GenBeach::new(seed))),
+    ("Classify", |seed| Arc::new(GenClassify::new(seed))),
+    ("Table", |seed| Arc::new(GenTable::new(seed))),
+    ("Blue", |seed| Arc::new(GenBlue::new(seed))),
+    ("Island", |seed| Arc::new(GenIsland::new(seed))),
+    ("MagicMoon", |seed| Arc::new(GenMagicMoon::new(seed))),
+    ("Headland", |seed| Arc::new(headland(seed))),
+    ("Valleys", |seed| Arc::new(valleys(seed))),
+    ("Overhang", |seed| Arc::new(GenOverhang::new(seed))),
+    ("Hydraulic", |seed| Arc::new(Eroded::new(WorldGen::new(seed), seed))),
+    ("Rivers", |seed| Arc::new(Rivers::new(WorldGen::new(seed), seed))),
+    ("Forest", |seed| Arc::new(Decorated::new(Climatic::new(WorldGen::new(seed), seed), seed, default_decorators()))),
+    ("Caves", |seed| Arc::new(Caved::new(WorldGen::new(seed), seed))),
+    ("Climate", |seed| Arc::new(Climatic::new(WorldGen::new(seed), seed))),
+    ("Biomes", |seed| Arc::new(Classified::new(Climatic::new(WorldGen::new(seed), seed), default_biomes()))),
+    ("Continent", |seed| Arc::new(Regions::new(vec![
+        Arc::new(GenIsland::new(seed)),
+        Arc::new(GenCrag::new(seed)),
+        Arc::new(GenBeach::new(seed)),
+        Arc::new(WorldGen::new(seed)),
+    ], seed))),
+    ("Plates", |seed| Arc::new(GenPlates::new(seed))),
+    // crags where the plates collide, normal land everywhere else
+    ("Ranges", |seed| Arc::new(Mask::new(WorldGen::new(seed), GenCrag::new(seed), PlateBelts(Plates::new(seed)), 0.1, 0.6))),
+    ("Fjords", |seed| Arc::new(Glaciated::new(GenPlates::new(seed), seed))),
+    ("Mesa", |seed| Arc::new(GenMesa::new(seed))),
+];
+
+pub fn generator_index(name: &str) -> Option<usize> {
+    GENERATORS.iter().position(|(gen_name, _)| *gen_name == name)
 }
 
 
@@ -39,7 +310,7 @@ fn fbm1(p: Vec2, seed: u32) -> f32 {
 fn fbm2(p: Vec2, seed: u32) -> Vec2 {
     Vec2 {
         x: fgrad2_isotropic(p.x, p.y, seed),
-        y: fgrad2_isotropic(p.x, p.y, seed + 0x230895F7),
+        y: fgrad2_isotropic(p.x, p.y, seed.wrapping_add(0x230895F7)),
     }
 }
 
@@ -59,14 +330,18 @@ impl GenNormalCliffy {
     }
 
 
-    fn block_underground(&self, x: i32, y: i32, z: i32) -> Block {
-        let cavern1_floor_noise = fgrad2_isotropic(0.02 * x as f32, 0.02 * z as f32, self.seed * 23492349);
-        let cavern1_ceiling_noise = fgrad2_isotropic(0.02 * x as f32, 0.02 * z as f32, self.seed * 93471753);
-        let cavern1_sep = fgrad2_isotropic(0.01 * x as f32, 0.01 * z as f32, self.seed * 93471753);
+    // floor and ceiling of the big cavern layer under column x z
+    fn cavern(&self, x: i32, z: i32) -> (i32, i32) {
+        let cavern1_floor_noise = fgrad2_isotropic(0.02 * x as f32, 0.02 * z as f32, self.seed.wrapping_mul(23492349));
+        let cavern1_ceiling_noise = fgrad2_isotropic(0.02 * x as f32, 0.02 * z as f32, self.seed.wrapping_mul(93471753));
+        let cavern1_sep = fgrad2_isotropic(0.01 * x as f32, 0.01 * z as f32, self.seed.wrapping_mul(93471753));
 
         let floor = (-120.0 + cavern1_floor_noise * 60.0) as i32;
         let ceiling = (-120.0 + cavern1_ceiling_noise * 60.0) as i32 + (50.0 * cavern1_sep) as i32;
+        (floor, ceiling)
+    }
 
+    fn block_underground(y: i32, (floor, ceiling): (i32, i32)) -> Block {
         if y >= ceiling || y < floor {
             Block::Stone
         } else if y == floor {
@@ -117,42 +392,56 @@ impl LevelGenerator for GenNormalCliffy {
         height// would like to squish beaches but what can ya do
     }
 
+    fn sample_offset(&self) -> f32 {
+        0.5
+    }
+
     fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
-        let mut blocks = vec![Block::Air; S*S*S];
-        for k in 0..S {
-            let z = oz*S as i32 + k as i32;
-    
-            for i in 0..S {
-                let x = ox*S as i32 + i as i32;
-                let height = self.height(x as f32 + 0.5, z as f32 + 0.5) as i32;
-                for j in 0..S {
-                    let idx = k*S + j*S*S + i;
-                    let y = oy*S as i32 + j as i32;
-    
-    
-                    let block = if y > height {
-                        if y >= SEA_LEVEL_I32 {
-                            Block::Air
-                        } else {
-                            Block::Water
-                        }
-                    } else if y < SEA_LEVEL_I32 + 5 && y > height - 3 {
-                        Block::Sand
-                    } else if y == height {
-                        Block::Grass
-                    } else if y > height - 3 {
-                        Block::Dirt
-                    } else if y <= height - 3 {
-                        self.block_underground(x, y, z)
-                    } else {
-                        Block::Wat
-                    };
-    
-                    blocks[idx] = block;
+        heightmap_blocks(self, ox, oy, oz)
+    }
+
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    // the caverns come up to about -10, so only say stone above that
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        standard_uniform_block(oy, surface).filter(|b| *b == Block::Air || oy >= 0)
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        // the caverns only matter for chunks that reach down under the dirt
+        let cavern = if oy*S as i32 <= height - 3 { self.cavern(x, z) } else { (0, 0) };
+        std::array::from_fn(|j| {
+            let y = oy*S as i32 + j as i32;
+            if y > height {
+                if y >= SEA_LEVEL_I32 {
+                    Block::Air
+                } else {
+                    Block::Water
                 }
+            } else if y < SEA_LEVEL_I32 + 5 && y > height - 3 {
+                Block::Sand
+            } else if y == height {
+                Block::Grass
+            } else if y > height - 3 {
+                Block::Dirt
+            } else {
+                GenNormalCliffy::block_underground(y, cavern)
             }
-        }
-        blocks
+        })
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        column_block(self, x, y, z, height)
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::standard(self.seed)
     }
 }
 
@@ -192,59 +481,69 @@ impl LevelGenerator for GenHell {
         }
     }
 
+    fn sample_offset(&self) -> f32 {
+        0.5
+    }
+
     fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
-        let mut blocks = vec![Block::Air; S*S*S];
-        for k in 0..S {
-            let z = oz*S as i32 + k as i32;
-    
-            for i in 0..S {
-    
-                
-                let x = ox*S as i32 + i as i32;
-                let height = self.height(x as f32 + 0.5, z as f32 + 0.5) as i32;
-                let deep_hole_noise1 = fgrad2_isotropic(0.01 * (x as f32 + 0.5), 0.01 * (z as f32 + 0.5), 123);
-                let deep_hole_noise2 = grad2_isotropic(0.01 * (x as f32 + 0.5), 0.01 * (z as f32 + 0.5), 321);
-                let shallow_hole_noise = grad2_isotropic(0.01 * (x as f32 + 0.5), 0.01 * (z as f32 + 0.5), 123321);
-    
-                let grass = fgrad2_isotropic(0.02 * (x as f32 + 0.5), 0.02 * (z as f32 + 0.5), 76767654) > 0.5;
-            
-                let nearly_deep_hole = deep_hole_noise1 > 0.58 || deep_hole_noise2 > 0.58;
-                let shallow_hole = shallow_hole_noise > 0.5;
-    
-                let do_grass = !nearly_deep_hole && !shallow_hole && grass;
-            
-                for j in 0..S {
-                    let idx = k*S + j*S*S + i;
-                    let y = oy*S as i32 + j as i32;
-    
-    
-                    let block = if y > height {
-                        if y > SEA_LEVEL_I32 {
-                            Block::Air
-                        } else {
-                            Block::Lava
-                        }
-                    } else if y < SEA_LEVEL_I32 + 5 && y > height - 3 {
-                        Block::Sand
-                    } else if y == height {
-                        if do_grass {
-                            Block::DeadGrass
-                        } else {
-                            Block::Air
-                        }
-                    } else if y > height - 3 {
-                        Block::Dirt
-                    } else if y <= height - 3 {
-                        Block::Hellstone
-                    } else {
-                        Block::Wat
-                    };
-    
-                    blocks[idx] = block;
+        heightmap_blocks(self, ox, oy, oz)
+    }
+
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        standard_uniform_block(oy, surface).map(|b| if b == Block::Stone { Block::Hellstone } else { b })
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        let (xf, zf) = (x as f32 + 0.5, z as f32 + 0.5);
+        let deep_hole_noise1 = fgrad2_isotropic(0.01 * xf, 0.01 * zf, 123);
+        let deep_hole_noise2 = grad2_isotropic(0.01 * xf, 0.01 * zf, 321);
+        let shallow_hole_noise = grad2_isotropic(0.01 * xf, 0.01 * zf, 123321);
+
+        let grass = fgrad2_isotropic(0.02 * xf, 0.02 * zf, 76767654) > 0.5;
+
+        let nearly_deep_hole = deep_hole_noise1 > 0.58 || deep_hole_noise2 > 0.58;
+        let shallow_hole = shallow_hole_noise > 0.5;
+
+        let do_grass = !nearly_deep_hole && !shallow_hole && grass;
+
+        std::array::from_fn(|j| {
+            let y = oy*S as i32 + j as i32;
+            if y > height {
+                if y > SEA_LEVEL_I32 {
+                    Block::Air
+                } else {
+                    Block::Lava
                 }
+            } else if y < SEA_LEVEL_I32 + 5 && y > height - 3 {
+                Block::Sand
+            } else if y == height {
+                if do_grass {
+                    Block::DeadGrass
+                } else {
+                    Block::Air
+                }
+            } else if y > height - 3 {
+                Block::Dirt
+            } else {
+                Block::Hellstone
             }
-        }
-        blocks
+        })
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        column_block(self, x, y, z, height)
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::hell(self.seed)
     }
 }
 
@@ -273,33 +572,19 @@ impl LevelGenerator for GenExp {
     }
 
     fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
-        let mut blocks = vec![Block::Air; S*S*S];
-        for k in 0..S {
-            let z = oz*S as i32 + k as i32;
-    
-            for i in 0..S {
-                let x = ox*S as i32 + i as i32;
-                let height = self.height(x as f32, z as f32) as i32;
-            
-                for j in 0..S {
-                    let idx = k*S + j*S*S + i;
-                    let y = oy*S as i32 + j as i32;
+        heightmap_blocks(self, ox, oy, oz)
+    }
 
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
 
-                    let block = match (y - height, y) {
-                        (dh, y) if dh > 0 && y > 0 => Block::Air,
-                        (dh, y) if dh > 0 && y <= 0 => Block::Water,
-                        (dh, y) if dh == 0 && y > 4 => Block::Grass,
-                        (dh, y) if dh == 0 && y > -4 => Block::Sand,
-                        (dh, y) if dh > -4 => Block::Dirt,
-                        _ => Block::Stone,
-                    };
-    
-                    blocks[idx] = block;
-                }
-            }
-        }
-        blocks
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::standard(self.seed)
     }
 }
 
@@ -322,7 +607,7 @@ impl LevelGenerator for GenErosion {
 
     fn height(&self, x: f32, z: f32) -> f32 {    
 
-        let h_lf = fgrad2_isotropic_exp(0.0005 * x, 0.0005 * z, self.seed + 71237127);
+        let h_lf = fgrad2_isotropic_exp(0.0005 * x, 0.0005 * z, self.seed.wrapping_add(71237127));
 
         // confederate crags, min and max of several so lots of grainy boundaries
 
@@ -332,12 +617,12 @@ impl LevelGenerator for GenErosion {
 
         // what if you just randomize a bunch of hyperparameters like lacunarity
 
-        let h1 = fgrad2_isotropic_exp(0.0025 * x, 0.0025 * z, self.seed + 123124121);
-        let h2 = fgrad2_isotropic_exp(0.0025 * x, 0.0025 * z, self.seed + 141123121);
+        let h1 = fgrad2_isotropic_exp(0.0025 * x, 0.0025 * z, self.seed.wrapping_add(123124121));
+        let h2 = fgrad2_isotropic_exp(0.0025 * x, 0.0025 * z, self.seed.wrapping_add(141123121));
 
-        let t_noise = fgrad2_isotropic(0.0025 * x, 0.0025 * z, self.seed + 1341234111);
+        let t_noise = fgrad2_isotropic(0.0025 * x, 0.0025 * z, self.seed.wrapping_add(1341234111));
 
-        let sharpness = grad2_isotropic(0.005 * x, 0.005 * z, self.seed + 2312345) * 0.015;
+        let sharpness = grad2_isotropic(0.005 * x, 0.005 * z, self.seed.wrapping_add(2312345)) * 0.015;
 
         let t = saturate(t_noise, 0.5 - sharpness, 0.50 + sharpness);
 
@@ -382,33 +667,19 @@ impl LevelGenerator for GenErosion {
     }
 
     fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
-        let mut blocks = vec![Block::Air; S*S*S];
-        for k in 0..S {
-            let z = oz*S as i32 + k as i32;
-    
-            for i in 0..S {
-                let x = ox*S as i32 + i as i32;
-                let height = self.height(x as f32, z as f32) as i32;
-            
-                for j in 0..S {
-                    let idx = k*S + j*S*S + i;
-                    let y = oy*S as i32 + j as i32;
+        heightmap_blocks(self, ox, oy, oz)
+    }
 
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
 
-                    let block = match (y - height, y) {
-                        (dh, y) if dh > 0 && y > 0 => Block::Air,
-                        (dh, y) if dh > 0 && y <= 0 => Block::Water,
-                        (dh, y) if dh == 0 && y > 4 => Block::Grass,
-                        (dh, y) if dh == 0 && y > -4 => Block::Sand,
-                        (dh, y) if dh > -4 => Block::Dirt,
-                        _ => Block::Stone,
-                    };
-    
-                    blocks[idx] = block;
-                }
-            }
-        }
-        blocks
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::standard(self.seed)
     }
 }
 
@@ -431,21 +702,21 @@ impl LevelGenerator for GenErosion2 {
 
     fn height(&self, x: f32, z: f32) -> f32 {    
 
-        let h1 = grad2_isotropic_exp(0.0005 * x, 0.0005 * z, self.seed + 71237127);
-        let h2 = 0.5 * grad2_isotropic_exp(0.001 * x, 0.001 * z, self.seed + 61712371);
-        let h3_1 = 0.25 * grad2_isotropic_exp(0.002 * x, 0.002 * z, self.seed + 51241123);
-        let h3_2 = 0.25 * grad2_isotropic_exp(0.002 * x, 0.002 * z, self.seed + 45347127);
-        let h4_1 = 0.125 * grad2_isotropic_exp(0.004 * x, 0.004 * z, self.seed + 63411233);
-        let h4_2 = 0.125 * grad2_isotropic_exp(0.004 * x, 0.004 * z, self.seed + 34717247);
-        let h5 = 0.0625 * grad2_isotropic_exp(0.008 * x, 0.008 * z, self.seed + 8573419);
+        let h1 = grad2_isotropic_exp(0.0005 * x, 0.0005 * z, self.seed.wrapping_add(71237127));
+        let h2 = 0.5 * grad2_isotropic_exp(0.001 * x, 0.001 * z, self.seed.wrapping_add(61712371));
+        let h3_1 = 0.25 * grad2_isotropic_exp(0.002 * x, 0.002 * z, self.seed.wrapping_add(51241123));
+        let h3_2 = 0.25 * grad2_isotropic_exp(0.002 * x, 0.002 * z, self.seed.wrapping_add(45347127));
+        let h4_1 = 0.125 * grad2_isotropic_exp(0.004 * x, 0.004 * z, self.seed.wrapping_add(63411233));
+        let h4_2 = 0.125 * grad2_isotropic_exp(0.004 * x, 0.004 * z, self.seed.wrapping_add(34717247));
+        let h5 = 0.0625 * grad2_isotropic_exp(0.008 * x, 0.008 * z, self.seed.wrapping_add(8573419));
 
         
-        let h1 = fgrad2_isotropic_exp(0.0025 * x, 0.0025 * z, self.seed + 123124121);
-        let h2 = fgrad2_isotropic_exp(0.0025 * x, 0.0025 * z, self.seed + 141123121);
+        let h1 = fgrad2_isotropic_exp(0.0025 * x, 0.0025 * z, self.seed.wrapping_add(123124121));
+        let h2 = fgrad2_isotropic_exp(0.0025 * x, 0.0025 * z, self.seed.wrapping_add(141123121));
         
-        let t_noise = fgrad2_isotropic(0.0025 * x, 0.0025 * z, self.seed + 1341234111);
+        let t_noise = fgrad2_isotropic(0.0025 * x, 0.0025 * z, self.seed.wrapping_add(1341234111));
         
-        let sharpness = grad2_isotropic(0.005 * x, 0.005 * z, self.seed + 2312345) * 0.015;
+        let sharpness = grad2_isotropic(0.005 * x, 0.005 * z, self.seed.wrapping_add(2312345)) * 0.015;
         
         let t = saturate(t_noise, 0.5 - sharpness, 0.50 + sharpness);
         let h3 = lerp(h3_1, h3_2, t);
@@ -456,33 +727,19 @@ impl LevelGenerator for GenErosion2 {
     }
 
     fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
-        let mut blocks = vec![Block::Air; S*S*S];
-        for k in 0..S {
-            let z = oz*S as i32 + k as i32;
-    
-            for i in 0..S {
-                let x = ox*S as i32 + i as i32;
-                let height = self.height(x as f32, z as f32) as i32;
-            
-                for j in 0..S {
-                    let idx = k*S + j*S*S + i;
-                    let y = oy*S as i32 + j as i32;
+        heightmap_blocks(self, ox, oy, oz)
+    }
 
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
 
-                    let block = match (y - height, y) {
-                        (dh, y) if dh > 0 && y > 0 => Block::Air,
-                        (dh, y) if dh > 0 && y <= 0 => Block::Water,
-                        (dh, y) if dh == 0 && y > 4 => Block::Grass,
-                        (dh, y) if dh == 0 && y > -4 => Block::Sand,
-                        (dh, y) if dh > -4 => Block::Dirt,
-                        _ => Block::Stone,
-                    };
-    
-                    blocks[idx] = block;
-                }
-            }
-        }
-        blocks
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::standard(self.seed)
     }
 }
 
@@ -507,7 +764,7 @@ impl LevelGenerator for GenCrag {
 
         let mut max: f32 = 0.0;
         for i in 0..5 {
-            let hi = fgrad2_isotropic_exp(0.0025 * x, 0.0025 * z, self.seed + i);
+            let hi = fgrad2_isotropic_exp(0.0025 * x, 0.0025 * z, self.seed.wrapping_add(i));
             max = max.max(hi);
         }
 
@@ -517,33 +774,19 @@ impl LevelGenerator for GenCrag {
     }
 
     fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
-        let mut blocks = vec![Block::Air; S*S*S];
-        for k in 0..S {
-            let z = oz*S as i32 + k as i32;
-    
-            for i in 0..S {
-                let x = ox*S as i32 + i as i32;
-                let height = self.height(x as f32, z as f32) as i32;
-            
-                for j in 0..S {
-                    let idx = k*S + j*S*S + i;
-                    let y = oy*S as i32 + j as i32;
+        heightmap_blocks(self, ox, oy, oz)
+    }
 
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
 
-                    let block = match (y - height, y) {
-                        (dh, y) if dh > 0 && y > 0 => Block::Air,
-                        (dh, y) if dh > 0 && y <= 0 => Block::Water,
-                        (dh, y) if dh == 0 && y > 4 => Block::Grass,
-                        (dh, y) if dh == 0 && y > -4 => Block::Sand,
-                        (dh, y) if dh > -4 => Block::Dirt,
-                        _ => Block::Stone,
-                    };
-    
-                    blocks[idx] = block;
-                }
-            }
-        }
-        blocks
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::standard(self.seed)
     }
 }
 
@@ -567,27 +810,31 @@ impl GenWarp {
     fn fbm2(p: Vec2, seed: u32) -> Vec2 {
         Vec2 {
             x: fgrad2_isotropic(p.x, p.y, seed),
-            y: fgrad2_isotropic(p.x, p.y, seed + 0x230895F7),
+            y: fgrad2_isotropic(p.x, p.y, seed.wrapping_add(0x230895F7)),
         }
     }
 }
 
 
 impl LevelGenerator for GenWarp {
+    // same height function as WorldGen with its default params, so take the exact gradient from there
+    fn height_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        WorldGen::new(self.seed).height_d(x, z)
+    }
 
     fn height(&self, x: f32, z: f32) -> f32 {
         let p = 0.005 * Vec2 { x, y: z};
 
         let p_lf = 0.0005 * Vec2{x, y:z};
-        let h_lf = GenWarp::fbm1(p_lf + 0.5*GenWarp::fbm2(p, self.seed + 0x12345131), self.seed + 0x42141213);
+        let h_lf = GenWarp::fbm1(p_lf + 0.5*GenWarp::fbm2(p, self.seed.wrapping_add(0x12345131)), self.seed.wrapping_add(0x42141213));
 
-        let h_mountain = GenWarp::fbm1(p + GenWarp::fbm2(p + GenWarp::fbm2(p + GenWarp::fbm2(p, self.seed + 0x31261343), self.seed + 0x91376513), self.seed + 0x23452337), self.seed);
+        let h_mountain = GenWarp::fbm1(p + GenWarp::fbm2(p + GenWarp::fbm2(p + GenWarp::fbm2(p, self.seed.wrapping_add(0x31261343)), self.seed.wrapping_add(0x91376513)), self.seed.wrapping_add(0x23452337)), self.seed);
         let h_mountain_sharp = h_mountain * h_mountain * h_mountain * h_mountain * 5.0;
 
         let h_mountain = h_mountain.max(h_mountain_sharp); // or softmax
 
         let p_rough = 0.002 * Vec2 { x, y: z};
-        let roughness = GenWarp::fbm1(p_rough, self.seed + 34192313);
+        let roughness = GenWarp::fbm1(p_rough, self.seed.wrapping_add(34192313));
         // let roughness = fgrad2_isotropic(0.001 * x, 0.001 * z, self.seed + 34192313);
         let t_mountain = saturate(roughness - 0.1, 0.35, 0.65);
 
@@ -599,7 +846,7 @@ impl LevelGenerator for GenWarp {
 
 
         let h_ocean = 0.1;
-        let t_ocean = saturate(fgrad2_isotropic(0.0005 * x, 0.0005 * z, self.seed + 34111231), 0.50, 0.7);
+        let t_ocean = saturate(fgrad2_isotropic(0.0005 * x, 0.0005 * z, self.seed.wrapping_add(34111231)), 0.50, 0.7);
 
         let h = lerp(h_land, h_ocean, t_ocean);
 
@@ -630,38 +877,62 @@ impl LevelGenerator for GenWarp {
         (h - 0.4) * 200.0
     }
 
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::gradients(self, ox, oz))
+    }
+
     fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        self.generate_blocks_with(ox, oy, oz, &ColumnSurface::gradients(self, ox, oz))
+    }
+
+    fn generate_blocks_with(&self, _ox: i32, oy: i32, _oz: i32, surface: &ColumnSurface) -> Vec<Block> {
         let mut blocks = vec![Block::Air; S*S*S];
         for k in 0..S {
-            let z = oz*S as i32 + k as i32;
     
             for i in 0..S {
-                let x = ox*S as i32 + i as i32;
-                let (height, grad) = self.height_gradient(x as f32, z as f32);
-                let height = height as i32;
+                let height = surface.height[k*S + i] as i32;
+                let grad = surface.grad[k*S + i];
             
                 for j in 0..S {
                     let idx = k*S + j*S*S + i;
                     let y = oy*S as i32 + j as i32;
-
-                    // let m = grad.x.abs().max(grad.y.abs());
-                    let m = grad.magnitude();
-                    let block = match (y - height, y, m) {
-                        (dh, y, g) if dh > 0 && y > 0 => Block::Air,
-                        (dh, y, g) if dh > 0 && y <= 0 => Block::Water,
-                        (dh, y, g) if g > 1.9 => Block::Stone,
-                        (dh, y, g) if dh == 0 && y > 4 => Block::Grass,
-                        (dh, y, g) if dh == 0 && y > -4 => Block::Sand,
-                        (dh, y, g) if dh > -4 => Block::Dirt,
-                        _ => Block::Stone,
-                    };
-    
-                    blocks[idx] = block;
+                    blocks[idx] = slope_block(y, height, grad, 1.9);
                 }
             }
         }
         blocks
     }
+
+    // the slope only matters at or under the surface, dont bother with it for chunks up in the air
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        let grad = if oy*S as i32 <= height { self.height_gradient(x as f32, z as f32).1 } else { Vec2::new(0.0, 0.0) };
+        std::array::from_fn(|j| slope_block(oy*S as i32 + j as i32, height, grad, 1.9))
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        column_block(self, x, y, z, height)
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::standard(self.seed)
+    }
+}
+
+#[test]
+fn test_warp_gradient() {
+    let gen = GenWarp::new(69);
+    let eps = 0.01;
+    for i in 0..100 {
+        let x = i as f32 * 71.3 - 2000.0;
+        let z = i as f32 * -33.9 + 900.0;
+        let (h, g) = gen.height_gradient(x, z);
+        assert!((h - gen.height(x, z)).abs() < 1e-3, "value mismatch at {} {}", x, z);
+        let fdx = (gen.height(x + eps, z) - gen.height(x - eps, z)) / (2.0 * eps);
+        let fdz = (gen.height(x, z + eps) - gen.height(x, z - eps)) / (2.0 * eps);
+        let tol = 0.05 + 0.05 * g.magnitude();
+        assert!((g.x - fdx).abs() < tol && (g.y - fdz).abs() < tol,
+            "gradient at {} {}: {:?} vs {} {}", x, z, g, fdx, fdz);
+    }
 }
 
 #[test]
@@ -689,6 +960,9 @@ fn test_gen_beach() {
 #[derive(Clone)]
 pub struct GenBeach {
     seed: u32,
+    beach_start: f32,
+    beach_peak: f32,
+    beach_end: f32,
 }
 
 pub struct Beach2d {
@@ -700,13 +974,15 @@ pub struct Beach2d {
 impl GenBeach {
     pub fn new(seed: u32) -> GenBeach {
         GenBeach {
-            seed
+            seed,
+            beach_start: 0.5,
+            beach_peak: 0.55,
+            beach_end: 0.65,
         }
     }
 
-    fn block_beach(&self, x: i32, y: i32, z: i32, beach_params: &Beach2d) -> Block {
-        let h = beach_params.height as i32;
-        let dh = y - h;
+    fn block_beach(&self, x: i32, y: i32, z: i32, height: i32, beach_params: &Beach2d) -> Block {
+        let dh = y - height;
 
         let grass_roll = khash_2float(x as u32, z as u32, self.seed);
         let surface_grass = beach_params.vegetation;
@@ -733,11 +1009,11 @@ impl GenBeach {
 
         let lf = grad2_isotropic(p.x, p.y, self.seed);//.powf();
 
-        let land_noise = fgrad2_isotropic(x * 0.005, z * 0.005, self.seed + 2345823);
+        let land_noise = fgrad2_isotropic(x * 0.005, z * 0.005, self.seed.wrapping_add(2345823));
 
-        let beach_start = 0.5;
-        let beach_peak = 0.55;
-        let beach_end = 0.65;
+        let beach_start = self.beach_start;
+        let beach_peak = self.beach_peak;
+        let beach_end = self.beach_end;
 
         let ocean_t = saturate(lf, 0.0, beach_start);
         let beach_t = saturate(lf, beach_start, beach_peak);
@@ -820,35 +1096,72 @@ impl LevelGenerator for GenBeach {
         
     }
 
+    fn params(&self) -> Vec<GenParam> {
+        vec![
+            GenParam::new("beach_start", self.beach_start, 0.0, 1.0),
+            GenParam::new("beach_peak", self.beach_peak, 0.0, 1.0),
+            GenParam::new("beach_end", self.beach_end, 0.0, 1.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "beach_start" => self.beach_start = value,
+            "beach_peak" => self.beach_peak = value,
+            "beach_end" => self.beach_end = value,
+            _ => {},
+        }
+        // saturate divides by the gaps between these, so keep them in order and apart
+        self.beach_start = self.beach_start.max(0.01);
+        self.beach_peak = self.beach_peak.max(self.beach_start + 0.01);
+        self.beach_end = self.beach_end.max(self.beach_peak + 0.01);
+    }
+
     fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
-        let mut blocks = vec![Block::Air; S*S*S];
-        for k in 0..S {
-            let z = oz*S as i32 + k as i32;
-    
-            for i in 0..S {
-                let x = ox*S as i32 + i as i32;
-                let vals2d = self.vals2d(x as f32, z as f32);
-                let height = vals2d.height as i32;
-                let b = vals2d.beachness;
-            
-                for j in 0..S {
-                    let idx = k*S + j*S*S + i;
-                    let y = oy*S as i32 + j as i32;
+        heightmap_blocks(self, ox, oy, oz)
+    }
 
-                    let block = match (y - height, y, b) {
-                        (dh, y, b) if dh > 0 && y > 0 => Block::Air,
-                        (dh, y, b) if dh > 0 && y <= 0 => Block::Water,
-                        (dh, y, b) if b < 0.99 => self.block_beach(x, y, z, &vals2d),
-                        (dh, y, b) if dh == 0 && y > 4 => Block::Grass,
-                        (dh, y, b) if dh > -4 => Block::Dirt,
-                        _ => Block::Stone,
-                    };
-    
-                    blocks[idx] = block;
-                }
-            }
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    // beaches are sand 10 deep
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        match standard_uniform_block(oy, surface) {
+            Some(Block::Stone) if oy*S as i32 + S as i32 - 1 > surface.min as i32 - 10 => None,
+            b => b,
         }
-        blocks
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        // all air and water, no need to know how beachy it is
+        if oy*S as i32 > height {
+            return std::array::from_fn(|j| standard_block(oy*S as i32 + j as i32, height));
+        }
+        let vals2d = self.vals2d(x as f32, z as f32);
+        std::array::from_fn(|j| {
+            let y = oy*S as i32 + j as i32;
+            match (y - height, y, vals2d.beachness) {
+                (dh, y, _) if dh > 0 && y > 0 => Block::Air,
+                (dh, y, _) if dh > 0 && y <= 0 => Block::Water,
+                (_, _, b) if b < 0.99 => self.block_beach(x, y, z, height, &vals2d),
+                (dh, y, _) if dh == 0 && y > 4 => Block::Grass,
+                (dh, _, _) if dh > -4 => Block::Dirt,
+                _ => Block::Stone,
+            }
+        })
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        column_block(self, x, y, z, height)
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::standard(self.seed)
     }
 }
 
@@ -873,7 +1186,7 @@ impl LevelGenerator for GenClassify {
     fn height(&self, x: f32, z: f32) -> f32 {
 
         // let d = 0.4;
-        let d = fgrad2_isotropic(x, z, self.seed + 23434713);
+        let d = fgrad2_isotropic(x, z, self.seed.wrapping_add(23434713));
         let neighs = [(-d, 0.0), (0.0, -d), (d, 0.0), (0.0, d), (0.0, 0.0)];
         let c = neighs.iter()
             // .map(|(nx, ny)| fgrad2_isotropic(x, z, self.seed))
@@ -897,33 +1210,19 @@ impl LevelGenerator for GenClassify {
     }
 
     fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
-        let mut blocks = vec![Block::Air; S*S*S];
-        for k in 0..S {
-            let z = oz*S as i32 + k as i32;
-    
-            for i in 0..S {
-                let x = ox*S as i32 + i as i32;
-                let height = self.height(x as f32, z as f32) as i32;
-            
-                for j in 0..S {
-                    let idx = k*S + j*S*S + i;
-                    let y = oy*S as i32 + j as i32;
+        heightmap_blocks(self, ox, oy, oz)
+    }
 
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
 
-                    let block = match (y - height, y) {
-                        (dh, y) if dh > 0 && y > 0 => Block::Air,
-                        (dh, y) if dh > 0 && y <= 0 => Block::Water,
-                        (dh, y) if dh == 0 && y > 4 => Block::Grass,
-                        (dh, y) if dh == 0 && y > -4 => Block::Sand,
-                        (dh, y) if dh > -4 => Block::Dirt,
-                        _ => Block::Stone,
-                    };
-    
-                    blocks[idx] = block;
-                }
-            }
-        }
-        blocks
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::standard(self.seed)
     }
 }
 #[derive(Clone)]
@@ -951,33 +1250,19 @@ impl LevelGenerator for GenTable {
     }
 
     fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
-        let mut blocks = vec![Block::Air; S*S*S];
-        for k in 0..S {
-            let z = oz*S as i32 + k as i32;
-    
-            for i in 0..S {
-                let x = ox*S as i32 + i as i32;
-                let height = self.height(x as f32, z as f32) as i32;
-            
-                for j in 0..S {
-                    let idx = k*S + j*S*S + i;
-                    let y = oy*S as i32 + j as i32;
+        heightmap_blocks(self, ox, oy, oz)
+    }
 
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
 
-                    let block = match (y - height, y) {
-                        (dh, y) if dh > 0 && y > 0 => Block::Air,
-                        (dh, y) if dh > 0 && y <= 0 => Block::Water,
-                        (dh, y) if dh == 0 && y > 4 => Block::Grass,
-                        (dh, y) if dh == 0 && y > -4 => Block::Sand,
-                        (dh, y) if dh > -4 => Block::Dirt,
-                        _ => Block::Stone,
-                    };
-    
-                    blocks[idx] = block;
-                }
-            }
-        }
-        blocks
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::standard(self.seed)
     }
 }
 
@@ -1005,48 +1290,35 @@ impl LevelGenerator for GenBlue {
     }
 
     fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
-        let mut blocks = vec![Block::Air; S*S*S];
-        for k in 0..S {
-            let z = oz*S as i32 + k as i32;
-    
-            for i in 0..S {
-                let x = ox*S as i32 + i as i32;
-                let height = self.height(x as f32, z as f32) as i32;
-            
-                for j in 0..S {
-                    let idx = k*S + j*S*S + i;
-                    let y = oy*S as i32 + j as i32;
+        heightmap_blocks(self, ox, oy, oz)
+    }
 
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
 
-                    let block = match (y - height, y) {
-                        (dh, y) if dh > 0 && y > 0 => Block::Air,
-                        (dh, y) if dh > 0 && y <= 0 => Block::Water,
-                        // (dh, y) if dh == 0 && y > 120 => Block::Snow,
-                        // (dh, y) if dh > -4 && y > 100 => Block::Stone,
-                        // (dh, y) if dh == 0 && y > 80 => Block::Dirt,
-                        (dh, y) if dh == 0 && y > 4 => Block::Grass,
-                        (dh, y) if dh == 0 && y > -4 => Block::Sand,
-                        (dh, y) if dh > -4 => Block::Dirt,
-                        _ => Block::Stone,
-                    };
-    
-                    blocks[idx] = block;
-                }
-            }
-        }
-        blocks
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::standard(self.seed)
     }
 }
 
 #[derive(Clone)]
 pub struct GenIsland {
     seed: u32,
+    radius: f32,
+    cliff_threshold: f32,
 }
 
 impl GenIsland {
     pub fn new(seed: u32) -> GenIsland {
         GenIsland {
-            seed
+            seed,
+            radius: 500.0,
+            cliff_threshold: 0.5,
         }
     }
 }
@@ -1056,8 +1328,8 @@ impl LevelGenerator for GenIsland {
     // tension between maintaining interesting coastline shapes and having tall mountains in the middle
 
     fn height(&self, x: f32, z: f32) -> f32 {   
-        let island_height_noise = fgrad2_isotropic_exp(0.005 * x, 0.005 * z, self.seed + 234235);
-        let r = 500.0;
+        let island_height_noise = fgrad2_isotropic_exp(0.005 * x, 0.005 * z, self.seed.wrapping_add(234235));
+        let r = self.radius;
         let xp = x/r;
         let zp = z/r;
         let mut island_height = 1.0 - (xp*xp + zp*zp).sqrt();
@@ -1088,11 +1360,11 @@ impl LevelGenerator for GenIsland {
             final_height
         };
 
-        let ct_noise = fgrad2_isotropic(0.001 * x, 0.001 * z, self.seed + 88901917);
-        let ct = if !on_island || ct_noise < 0.5 {
+        let ct_noise = fgrad2_isotropic(0.001 * x, 0.001 * z, self.seed.wrapping_add(88901917));
+        let ct = if !on_island || ct_noise < self.cliff_threshold {
             0.0
         } else {
-            (ct_noise - 0.5) * 2.0
+            ((ct_noise - self.cliff_threshold) / (1.0 - self.cliff_threshold)).min(1.0)
         };
 
         lerp(final_height, cliff_height, ct)
@@ -1101,37 +1373,35 @@ impl LevelGenerator for GenIsland {
         // (height_noise - 0.5) * 400.0
     }
 
+    fn params(&self) -> Vec<GenParam> {
+        vec![
+            GenParam::new("radius", self.radius, 50.0, 3000.0),
+            GenParam::new("cliff_threshold", self.cliff_threshold, 0.0, 0.99),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "radius" => self.radius = value,
+            "cliff_threshold" => self.cliff_threshold = value,
+            _ => {},
+        }
+    }
+
     fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
-        let mut blocks = vec![Block::Air; S*S*S];
-        for k in 0..S {
-            let z = oz*S as i32 + k as i32;
-    
-            for i in 0..S {
-                let x = ox*S as i32 + i as i32;
-                let height = self.height(x as f32, z as f32) as i32;
-            
-                for j in 0..S {
-                    let idx = k*S + j*S*S + i;
-                    let y = oy*S as i32 + j as i32;
+        heightmap_blocks(self, ox, oy, oz)
+    }
 
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
 
-                    let block = match (y - height, y) {
-                        (dh, y) if dh > 0 && y > 0 => Block::Air,
-                        (dh, y) if dh > 0 && y <= 0 => Block::Water,
-                        // (dh, y) if dh == 0 && y > 120 => Block::Snow,
-                        // (dh, y) if dh > -4 && y > 100 => Block::Stone,
-                        // (dh, y) if dh == 0 && y > 80 => Block::Dirt,
-                        (dh, y) if dh == 0 && y > 4 => Block::Grass,
-                        (dh, y) if dh == 0 && y > -4 => Block::Sand,
-                        (dh, y) if dh > -4 => Block::Dirt,
-                        _ => Block::Stone,
-                    };
-    
-                    blocks[idx] = block;
-                }
-            }
-        }
-        blocks
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::standard(self.seed)
     }
 }
 
@@ -1156,8 +1426,8 @@ impl GenMagicMoon {
         let height_noise = fgrad2_isotropic(0.005 * x as f32, 0.005 * z as f32, self.seed);
         
         let floor =  0.0;
-        let crater_noise = grad2_isotropic(0.005 * x as f32, 0.005 * z as f32, self.seed * 634536);
-        let lake_noise = grad2_isotropic(0.05 * x as f32, 0.05 * z as f32, self.seed * 123323);
+        let crater_noise = grad2_isotropic(0.005 * x as f32, 0.005 * z as f32, self.seed.wrapping_mul(634536));
+        let lake_noise = grad2_isotropic(0.05 * x as f32, 0.05 * z as f32, self.seed.wrapping_mul(123323));
         let crater_threshold = 0.6;
         let in_crater = crater_noise > crater_threshold;
         let cnn = if in_crater {
@@ -1173,6 +1443,32 @@ impl GenMagicMoon {
         } else {
             floor + 30.0 * height_noise + 16.0 * cnn
         };
+        // little impact craters peppered over everything, one in every other worley cell.
+        // flat bottomed, dug from the ground level at their centre, with a lip round the edge.
+        // a crater can reach over into the next cell, so the ones round about get a look too and the lowest wins
+        let seed = self.seed.wrapping_add(0x2F6B1E93);
+        let p = Vec2::new(x / 64.0, z / 64.0);
+        let (xi, zi) = (p.x.floor() as i32, p.y.floor() as i32);
+        let mut carved: Option<f32> = None;
+        for cz in zi - 1..=zi + 1 {
+            for cx in xi - 1..=xi + 1 {
+                let id = worley_id2(cx, cz, seed);
+                let point = worley_point2(cx, cz, seed, 1.0);
+                let r = 0.12 + 0.2 * (id >> 8) as f32 / (1u32 << 24) as f32;
+                let d = (p - point).magnitude();
+                if id % 2 != 0 || d >= 1.3 * r {
+                    continue;
+                }
+                let centre = point * 64.0;
+                let floor = floor + 30.0 * fgrad2_isotropic(0.005 * centre.x, 0.005 * centre.y, self.seed) - 40.0 * r;
+                let t = d / r;
+                let lip = 24.0 * r * (1.0 - (t - 1.0).abs() / 0.3).max(0.0);
+                let h = lerp(floor.min(height), height, t.min(1.0).powi(4)) + lip;
+                carved = Some(carved.map_or(h, |c: f32| c.min(h)));
+            }
+        }
+        let height = carved.unwrap_or(height);
+
         let grassy = in_crater && cnn < 0.25;
         let lake = in_crater && cnn < 0.2 && lake_noise > 0.6;
 
@@ -1194,46 +1490,272 @@ impl LevelGenerator for GenMagicMoon {
         self.props_2d(x, z).height
     }
 
+    fn sample_offset(&self) -> f32 {
+        0.5
+    }
+
     fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
-        let mut blocks = vec![Block::Air; S*S*S];
-        for k in 0..S {
-            let z = oz*S as i32 + k as i32;
-    
-            for i in 0..S {
-                let x = ox*S as i32 + i as i32;
-                let props = self.props_2d(x as f32 + 0.5, z as f32 + 0.5);
-                let height = props.height as i32;
-            
-                for j in 0..S {
-                    let idx = k*S + j*S*S + i;
-                    let y = oy*S as i32 + j as i32;
-    
-                    let block = if props.grassy {
-                        if props.lake {
-                            if y > height {
-                                Block::Air
-                            } else {
-                                Block::Water
-                            }
-                        } else {
-                            if y > height + 1 {
-                                Block::Air
-                            } else {
-                                Block::Grass
-                            }
-                        }
+        heightmap_blocks(self, ox, oy, oz)
+    }
+
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::heights(self, ox, oz))
+    }
+
+    fn generate_blocks_with(&self, ox: i32, oy: i32, oz: i32, surface: &ColumnSurface) -> Vec<Block> {
+        heightmap_blocks_with(self, ox, oy, oz, surface)
+    }
+
+    // grass sticks up one over the height, and grassy or lake columns are that all the way down
+    fn uniform_block(&self, oy: i32, surface: &ColumnSurface) -> Option<Block> {
+        if oy*S as i32 > surface.max as i32 + 1 { Some(Block::Air) } else { None }
+    }
+
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        // grass can stick up one over the height, above that theres nothing
+        if oy*S as i32 > height + 1 {
+            return [Block::Air; S];
+        }
+        let props = self.props_2d(x as f32 + 0.5, z as f32 + 0.5);
+        std::array::from_fn(|j| {
+            let y = oy*S as i32 + j as i32;
+            if props.grassy {
+                if props.lake {
+                    if y > height {
+                        Block::Air
                     } else {
-                        if y > height {
-                            Block::Air
-                        } else {
-                            Block::MoonRock
-                        }
-                    };
-    
-                    blocks[idx] = block;
+                        Block::Water
+                    }
+                } else {
+                    if y > height + 1 {
+                        Block::Air
+                    } else {
+                        Block::Grass
+                    }
+                }
+            } else {
+                if y > height {
+                    Block::Air
+                } else {
+                    Block::MoonRock
                 }
             }
+        })
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        column_block(self, x, y, z, height)
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::moon(self.seed)
+    }
+}
+// 3d density terrain, rolling base heightmap pushed around by 3d noise so you get overhangs and arches,
+// plus a band of floating islands up high
+#[derive(Clone)]
+pub struct GenOverhang {
+    seed: u32,
+    fractal: Fractal,
+    overhang: f32,
+    island_level: f32,
+}
+
+impl GenOverhang {
+    pub fn new(seed: u32) -> GenOverhang {
+        GenOverhang {
+            seed,
+            fractal: Fractal::new(3, 2.0, 0.5),
+            overhang: 40.0,
+            island_level: 140.0,
         }
-        blocks
     }
-}
\ No newline at end of file
+
+    fn base_height(&self, x: f32, z: f32) -> f32 {
+        (fgrad2_isotropic(0.002 * x, 0.002 * z, self.seed) - 0.4) * 150.0
+    }
+}
+
+impl LevelGenerator for GenOverhang {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        self.base_height(x, z)
+    }
+
+    fn density(&self, x: f32, y: f32, z: f32) -> f32 {
+        // overhangs only show up where the cliff mask is high, elsewhere its gentle hills
+        let cliffiness = saturate(fgrad2_isotropic(0.001 * x, 0.001 * z, self.seed.wrapping_add(0x7123)), 0.45, 0.6);
+        let n = self.fractal.fbm3(0.02 * x, 0.03 * y, 0.02 * z, self.seed.wrapping_add(0x3331)) - 0.5;
+        let ground = self.base_height(x, z) - y + n * self.overhang * (0.2 + cliffiness);
+
+        // islands are blobs of noise in a band, thickest in the middle of it.
+        // big soft value noise on the threshold so they come in clusters with open sky between
+        let band = 1.0 - ((y - self.island_level) / 25.0).abs();
+        let islands = if band > 0.0 {
+            let blob = self.fractal.fbm3(0.015 * x, 0.03 * y, 0.015 * z, self.seed.wrapping_add(0x9871));
+            let cluster = value3(0.004 * x, 0.01 * y, 0.004 * z, self.seed.wrapping_add(0x5A5A)) - 0.5;
+            (blob - 0.62 + 0.15 * cluster) * 60.0 * band
+        } else {
+            -1.0
+        };
+
+        ground.max(islands)
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        density_blocks(self, ox, oy, oz)
+    }
+
+    fn params(&self) -> Vec<GenParam> {
+        vec![
+            GenParam::new("overhang", self.overhang, 0.0, 150.0),
+            GenParam::new("island_level", self.island_level, 40.0, 300.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "overhang" => self.overhang = value,
+            "island_level" => self.island_level = value,
+            _ => {},
+        }
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::standard(self.seed)
+    }
+}
+
+#[test]
+fn test_gen_overhang() {
+    let gen = GenOverhang::new(69);
+    // somewhere in a decent area there should be air under solid ground, which a heightmap cant do
+    let mut overhangs = 0;
+    for i in 0..32 {
+        for k in 0..32 {
+            let (x, z) = (i as f32 * 16.0 - 256.0, k as f32 * 16.0 - 256.0);
+            let h = gen.height(x, z);
+            let mut solid_above = false;
+            for y in ((h as i32 - 20)..(h as i32 + 40)).rev() {
+                let solid = gen.density(x, y as f32, z) > 0.0;
+                if solid_above && !solid {
+                    overhangs += 1;
+                }
+                solid_above = solid;
+            }
+        }
+    }
+    assert!(overhangs > 0);
+
+    let blocks = gen.generate_blocks(0, 0, 0);
+    assert_eq!(blocks.len(), S*S*S);
+}
+
+#[test]
+fn test_gen_params() {
+    let mut gen: SharedGen = (GENERATORS[generator_index("Beach").unwrap()].1)(69);
+    gen.set_param("beach_peak", 0.6);
+    gen.set_param("not_a_param", 1.0);
+    let peak = gen.params().iter().find(|p| p.name == "beach_peak").unwrap().value;
+    assert_eq!(peak, 0.6);
+
+    // dragging the start past the peak pushes the peak along rather than dividing by zero
+    let mut beach = GenBeach::new(69);
+    beach.set_param("beach_start", 0.8);
+    assert!(beach.beach_start < beach.beach_peak && beach.beach_peak < beach.beach_end);
+    beach.set_param("beach_start", 0.0);
+    assert!((-100..100).all(|i| beach.height(i as f32 * 97.0, i as f32 * -61.0).is_finite()));
+}
+
+#[test]
+fn test_big_seeds() {
+    // seeds get salted with constants all over, none of that can overflow
+    for (name, make) in GENERATORS.iter() {
+        let gen = make(u32::MAX - 3);
+        let h = gen.height(100.0, -100.0);
+        assert!(h.is_finite(), "{}", name);
+        chunk_blocks(&gen, 6, h as i32 / S as i32, -7);
+    }
+}
+
+#[test]
+fn test_geology_seeded() {
+    // a different seed gets different ore and strata
+    for (name, make) in GENERATORS {
+        assert_ne!(make(1).geology().seed, make(2).geology().seed, "{}", name);
+    }
+}
+
+#[test]
+fn test_baseline_chunks() {
+    // NormalCliffy and Hell chunks from before they went per column, they sample the middle of each block
+    let sum = |blocks: Vec<Block>| blocks.iter().enumerate().fold(0u32, |h, (i, b)| {
+        khash(h ^ (i as u32).wrapping_mul(31) ^ format!("{:?}", b).bytes().fold(7u32, |a, c| a.wrapping_mul(131).wrapping_add(c as u32)))
+    });
+    let chunks = [
+        ("NormalCliffy", 0, 0, 0, 1902443),
+        ("Hell", 0, 2, 0, 1218948916),
+        ("NormalCliffy", 3, -1, -2, 1558282046),
+        ("Hell", 3, -6, -2, 2526950340),
+        ("NormalCliffy", -5, -1, 7, 3219483586),
+        ("Hell", -5, -7, 7, 3731860725),
+        ("NormalCliffy", 12, -3, -9, 1349565910),
+        ("Hell", 12, 1, -9, 2590075448),
+        ("NormalCliffy", -20, 4, 4, 2231984912),
+        ("Hell", -20, 2, 4, 2708023644),
+    ];
+    for (name, ox, oy, oz, expected) in chunks {
+        let gen = (GENERATORS[generator_index(name).unwrap()].1)(7);
+        assert_eq!(sum(gen.generate_blocks(ox, oy, oz)), expected, "{} chunk {} {} {}", name, ox, oy, oz);
+        let surface = gen.column_surface(ox, oz).unwrap();
+        assert_eq!(sum(gen.generate_blocks_with(ox, oy, oz, &surface)), expected, "{} chunk {} {} {}", name, ox, oy, oz);
+    }
+}
+
+#[test]
+fn test_gen_columns() {
+    // the plain generators (and biomes, whose pools reach above the ground) through the column cache come out
+    // the same as making chunks straight, and the chunks they skip really are all one thing
+    let names = ["NormalCliffy", "Hell", "Exp", "Erosion", "Erosion2", "Crag", "Beach", "Classify", "Table", "Blue", "Island", "MagicMoon", "Biomes"];
+    for name in names {
+        let gen = (GENERATORS[generator_index(name).unwrap()].1)(5);
+        let cache = ColumnCache::new();
+        let (mut made, mut skipped) = (0, 0);
+        for (ox, oz) in [(0, 0), (-9, 4), (31, -17)] {
+            assert!(cache.get(&gen, 0, ox, oz).is_some(), "{} has no column surface", name);
+            for oy in -12..12 {
+                let full = chunk_blocks(&gen, ox, oy, oz);
+                match cached_blocks(&gen, &cache, 0, ox, oy, oz) {
+                    (blocks, None) => {
+                        made += 1;
+                        assert_eq!(blocks, full, "{} chunk {} {} {}", name, ox, oy, oz);
+                    },
+                    (_, Some(Block::Air)) => {
+                        skipped += 1;
+                        assert!(full.iter().all(|b| *b == Block::Air), "{} chunk {} {} {}", name, ox, oy, oz);
+                    },
+                    (_, Some(_)) => {
+                        skipped += 1;
+                        assert!(full.iter().all(|b| b.is_opaque()), "{} chunk {} {} {}", name, ox, oy, oz);
+                    },
+                }
+            }
+        }
+        assert!(made > 0 && skipped > 0, "{} {} {}", name, made, skipped);
+    }
+}
+
+#[test]
+fn test_moon_craters() {
+    // craters reaching over a worley cell edge carry on, they used to drop off there in a cliff 20 high
+    let gen = GenMagicMoon::new(5);
+    for k in 0..40 {
+        let z = k as f32 * 13.7;
+        let mut prev = gen.height(0.0, z);
+        for i in 1..8000 {
+            let x = i as f32 * 0.25;
+            let h = gen.height(x, z);
+            assert!((h - prev).abs() < 8.0, "step at {} {}: {} to {}", x, z, prev, h);
+            prev = h;
+        }
+    }
+}
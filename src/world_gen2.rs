@@ -2,15 +2,17 @@ use crate::kmath::*;
 use crate::krand::*;
 use crate::chunk::*;
 use crate::settings::*;
-
-pub struct WorldProps2D {
-    height: f32,
-    grad: Vec2,
-}
+use crate::world_gen::*;
+use crate::column_cache::*;
+use crate::strata::*;
 
 #[derive(Clone, Copy)]
 pub struct WorldGen {
     seed: u32,
+    rough_lo: f32,
+    rough_hi: f32,
+    ocean_lo: f32,
+    ocean_hi: f32,
 }
 
 fn fbm1(p: Vec2, seed: u32) -> f32 {
@@ -20,86 +22,182 @@ fn fbm1(p: Vec2, seed: u32) -> f32 {
 fn fbm2(p: Vec2, seed: u32) -> Vec2 {
     Vec2 {
         x: fgrad2_isotropic(p.x, p.y, seed),
-        y: fgrad2_isotropic(p.x, p.y, seed + 0x230895F7),
+        y: fgrad2_isotropic(p.x, p.y, seed.wrapping_add(0x230895F7)),
     }
 }
 
+fn fbm1_d(p: Vec2, seed: u32) -> (f32, Vec2) {
+    fgrad2_isotropic_d(p.x, p.y, seed)
+}
+
+// value and jacobian, row i is the gradient of component i
+fn fbm2_d(p: Vec2, seed: u32) -> (Vec2, Mat2) {
+    let (x, gx) = fgrad2_isotropic_d(p.x, p.y, seed);
+    let (y, gy) = fgrad2_isotropic_d(p.x, p.y, seed.wrapping_add(0x230895F7));
+    (Vec2::new(x, y), Mat2::new(gx, gy))
+}
+
 impl WorldGen {
     pub fn new(seed: u32) -> WorldGen {
-        WorldGen { seed }
+        WorldGen {
+            seed,
+            rough_lo: 0.35,
+            rough_hi: 0.65,
+            ocean_lo: 0.5,
+            ocean_hi: 0.7,
+        }
     }
 
-    pub fn height(&self, x: f32, z: f32) -> f32 {
+    // height with its exact gradient, same steps as height() with the chain rule carried along.
+    // jacobians are wrt world x z
+    pub fn height_d(&self, x: f32, z: f32) -> (f32, Vec2) {
         let p = 0.005 * Vec2 { x, y: z};
+        let jp = Mat2::identity().scale(0.005);
 
         let p_lf = 0.0005 * Vec2{x, y:z};
-        let h_lf = fbm1(p_lf + 0.5*fbm2(p, self.seed + 0x12345131), self.seed + 0x42141213);
+        let (w, jw) = fbm2_d(p, self.seed.wrapping_add(0x12345131));
+        let j_lf = Mat2::identity().scale(0.0005).add(jw.mul(jp).scale(0.5));
+        let (h_lf, g) = fbm1_d(p_lf + 0.5*w, self.seed.wrapping_add(0x42141213));
+        let g_lf = j_lf.transpose_mul_vec(g);
+
+        let (w1, jw1) = fbm2_d(p, self.seed.wrapping_add(0x31261343));
+        let (q1, j1) = (p + w1, jp.add(jw1.mul(jp)));
+        let (w2, jw2) = fbm2_d(q1, self.seed.wrapping_add(0x91376513));
+        let (q2, j2) = (p + w2, jp.add(jw2.mul(j1)));
+        let (w3, jw3) = fbm2_d(q2, self.seed.wrapping_add(0x23452337));
+        let (q3, j3) = (p + w3, jp.add(jw3.mul(j2)));
+        let (h_mountain, g) = fbm1_d(q3, self.seed);
+        let g_mountain = j3.transpose_mul_vec(g);
 
-        let h_mountain = fbm1(p + fbm2(p + fbm2(p + fbm2(p, self.seed + 0x31261343), self.seed + 0x91376513), self.seed + 0x23452337), self.seed);
+        let h_mountain_sharp = h_mountain * h_mountain * h_mountain * h_mountain * 5.0;
+        let (h_mountain, g_mountain) = if h_mountain_sharp > h_mountain {
+            (h_mountain_sharp, g_mountain * (20.0 * h_mountain * h_mountain * h_mountain))
+        } else {
+            (h_mountain, g_mountain)
+        };
+
+        let p_rough = 0.002 * Vec2 { x, y: z};
+        let (roughness, g_rough) = fbm1_d(p_rough, self.seed.wrapping_add(34192313));
+        let (t_mountain, dt) = saturate_d(roughness - 0.1, self.rough_lo, self.rough_hi);
+        let g_t_mountain = g_rough * (0.002 * dt);
+
+        let h_land = h_lf + t_mountain * t_mountain * h_mountain;
+        let g_land = g_lf + g_t_mountain * (2.0 * t_mountain * h_mountain) + g_mountain * (t_mountain * t_mountain);
+
+        let h_ocean = 0.1;
+        let (n_ocean, g_ocean) = fgrad2_isotropic_d(0.0005 * x, 0.0005 * z, self.seed.wrapping_add(34111231));
+        let (t_ocean, dt) = saturate_d(n_ocean, self.ocean_lo, self.ocean_hi);
+        let g_t_ocean = g_ocean * (0.0005 * dt);
+
+        let h = lerp(h_land, h_ocean, t_ocean);
+        let g = g_land * (1.0 - t_ocean) + g_t_ocean * (h_ocean - h_land);
+        ((h - 0.4) * 200.0, g * 200.0)
+    }
+}
+
+impl LevelGenerator for WorldGen {
+    fn height(&self, x: f32, z: f32) -> f32 {
+        let p = 0.005 * Vec2 { x, y: z};
+
+        let p_lf = 0.0005 * Vec2{x, y:z};
+        let h_lf = fbm1(p_lf + 0.5*fbm2(p, self.seed.wrapping_add(0x12345131)), self.seed.wrapping_add(0x42141213));
+
+        let h_mountain = fbm1(p + fbm2(p + fbm2(p + fbm2(p, self.seed.wrapping_add(0x31261343)), self.seed.wrapping_add(0x91376513)), self.seed.wrapping_add(0x23452337)), self.seed);
         let h_mountain_sharp = h_mountain * h_mountain * h_mountain * h_mountain * 5.0;
 
         let h_mountain = h_mountain.max(h_mountain_sharp); // or softmax
 
         let p_rough = 0.002 * Vec2 { x, y: z};
-        let roughness = fbm1(p_rough, self.seed + 34192313);
-        let t_mountain = saturate(roughness - 0.1, 0.35, 0.65);
+        let roughness = fbm1(p_rough, self.seed.wrapping_add(34192313));
+        let t_mountain = saturate(roughness - 0.1, self.rough_lo, self.rough_hi);
 
         let h_land = h_lf + t_mountain * t_mountain * h_mountain;
         
 
         let h_ocean = 0.1;
-        let t_ocean = saturate(fgrad2_isotropic(0.0005 * x, 0.0005 * z, self.seed + 34111231), 0.50, 0.7);
+        let t_ocean = saturate(fgrad2_isotropic(0.0005 * x, 0.0005 * z, self.seed.wrapping_add(34111231)), self.ocean_lo, self.ocean_hi);
 
         let h = lerp(h_land, h_ocean, t_ocean);
         (h - 0.4) * 200.0
     }
 
-    pub fn props_2d(&self, x: f32, z: f32) -> WorldProps2D {
-        let height = self.height(x,z);
-        let hgx = self.height(x + 1.0, z + 0.0);
-        let hgz = self.height(x + 0.0, z + 1.0);
+    fn height_gradient(&self, x: f32, z: f32) -> (f32, Vec2) {
+        self.height_d(x, z)
+    }
 
-        let gradx = height - hgx;
-        let gradz = height - hgz;
-        let grad = Vec2::new(gradx, gradz);
+    fn params(&self) -> Vec<GenParam> {
+        vec![
+            GenParam::new("rough_lo", self.rough_lo, 0.0, 1.0),
+            GenParam::new("rough_hi", self.rough_hi, 0.0, 1.0),
+            GenParam::new("ocean_lo", self.ocean_lo, 0.0, 1.0),
+            GenParam::new("ocean_hi", self.ocean_hi, 0.0, 1.0),
+        ]
+    }
 
-        WorldProps2D {
-            height,
-            grad,
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "rough_lo" => self.rough_lo = value,
+            "rough_hi" => self.rough_hi = value,
+            "ocean_lo" => self.ocean_lo = value,
+            "ocean_hi" => self.ocean_hi = value,
+            _ => {},
         }
     }
 
-    pub fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+    fn column_surface(&self, ox: i32, oz: i32) -> Option<ColumnSurface> {
+        Some(ColumnSurface::gradients(self, ox, oz))
+    }
+
+    fn geology(&self) -> Geology {
+        Geology::standard(self.seed)
+    }
+
+    fn generate_blocks(&self, ox: i32, oy: i32, oz: i32) -> Vec<Block> {
+        self.generate_blocks_with(ox, oy, oz, &ColumnSurface::gradients(self, ox, oz))
+    }
+
+    fn generate_blocks_with(&self, _ox: i32, oy: i32, _oz: i32, surface: &ColumnSurface) -> Vec<Block> {
         let mut blocks = vec![Block::Air; S*S*S];
         for k in 0..S {
-            let z = oz*S as i32 + k as i32;
     
             for i in 0..S {
-                let x = ox*S as i32 + i as i32;
-                let props = self.props_2d(x as f32, z as f32);
-                let height = props.height as i32;
-                let grad = props.grad;
+                let height = surface.height[k*S + i] as i32;
+                let grad = surface.grad[k*S + i];
             
                 for j in 0..S {
                     let idx = k*S + j*S*S + i;
                     let y = oy*S as i32 + j as i32;
-
-                    // let m = grad.x.abs().max(grad.y.abs());
-                    let m = grad.magnitude();
-                    let block = match (y - height, y, m) {
-                        (dh, y, g) if dh > 0 && y > 0 => Block::Air,
-                        (dh, y, g) if dh > 0 && y <= 0 => Block::Water,
-                        (dh, y, g) if g > 1.9 => Block::Stone,
-                        (dh, y, g) if dh == 0 && y > 4 => Block::Grass,
-                        (dh, y, g) if dh == 0 && y > -4 => Block::Sand,
-                        (dh, y, g) if dh > -4 => Block::Dirt,
-                        _ => Block::Stone,
-                    };
-    
-                    blocks[idx] = block;
+                    blocks[idx] = slope_block(y, height, grad, 1.9);
                 }
             }
         }
         blocks
     }
-}
\ No newline at end of file
+
+    // the slope only matters at or under the surface, dont bother with it for chunks up in the air
+    fn column_blocks(&self, x: i32, z: i32, oy: i32, height: i32) -> [Block; S] {
+        let grad = if oy*S as i32 <= height { self.height_d(x as f32, z as f32).1 } else { Vec2::new(0.0, 0.0) };
+        std::array::from_fn(|j| slope_block(oy*S as i32 + j as i32, height, grad, 1.9))
+    }
+
+    fn surface_block(&self, x: i32, y: i32, z: i32, height: i32) -> Block {
+        column_block(self, x, y, z, height)
+    }
+}
+
+#[test]
+fn test_worldgen_gradient() {
+    let gen = WorldGen::new(69);
+    let eps = 0.01;
+    for i in 0..200 {
+        let x = i as f32 * 53.7 - 3000.0;
+        let z = i as f32 * -21.3 + 1500.0;
+        let (h, g) = gen.height_d(x, z);
+        assert!((h - gen.height(x, z)).abs() < 1e-3, "value mismatch at {} {}", x, z);
+        let fdx = (gen.height(x + eps, z) - gen.height(x - eps, z)) / (2.0 * eps);
+        let fdz = (gen.height(x, z + eps) - gen.height(x, z - eps)) / (2.0 * eps);
+        let tol = 0.05 + 0.05 * g.magnitude();
+        assert!((g.x - fdx).abs() < tol && (g.y - fdz).abs() < tol,
+            "gradient at {} {}: {:?} vs {} {}", x, z, g, fdx, fdz);
+    }
+}